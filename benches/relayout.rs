@@ -0,0 +1,63 @@
+// pilcrow/benches/relayout.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compares laying out a large document from scratch after typing a character with updating a
+//! `Layout` of it. Run with `cargo bench --bench relayout`.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{Document, Framesetter, Layout, Paragraph, ParagraphStyle};
+use std::time::{Duration, Instant};
+
+const PARAGRAPH_COUNT: usize = 5000;
+const EDIT_COUNT: usize = 20;
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog. Pack my box with five dozen \
+                    liquor jugs. How vexingly quick daft zebras jump!";
+
+fn type_character(document: &mut Document, edit_index: usize) {
+    let paragraph_index = edit_index * 97 % PARAGRAPH_COUNT;
+    let mut cursor = document.paragraphs_mut()[paragraph_index].edit_at(0);
+    cursor.push_string("x");
+    cursor.commit();
+}
+
+fn time<F>(mut f: F) -> Duration where F: FnMut(usize) {
+    let start = Instant::now();
+    for edit_index in 0..EDIT_COUNT {
+        f(edit_index)
+    }
+    start.elapsed() / EDIT_COUNT as u32
+}
+
+fn main() {
+    let rect = Rect::new(Point2D::zero(), Size2D::new(600.0, 800.0));
+    let mut document = Document::new();
+    for _ in 0..PARAGRAPH_COUNT {
+        document.append_paragraph(Paragraph::from_string(TEXT, ParagraphStyle::default()));
+    }
+
+    let full = time(|edit_index| {
+        type_character(&mut document, edit_index);
+        Framesetter::new(&document).layout_in_rect(&rect, None);
+    });
+
+    let mut layout = Layout::new(&document, &rect, None);
+    let incremental = time(|edit_index| {
+        type_character(&mut document, edit_index);
+        layout.update(&document);
+    });
+
+    println!("{} paragraphs, time per edit:", PARAGRAPH_COUNT);
+    println!("  full layout:        {:?}", full);
+    println!("  incremental layout: {:?}", incremental);
+}
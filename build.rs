@@ -14,6 +14,16 @@ use cbindgen::{Builder, Config};
 use std::env;
 
 fn main() {
+    // Core Text is only available on Apple platforms. Everywhere else, and on Apple platforms
+    // when the `portable` feature is enabled, lay out text with the pure-Rust backend.
+    println!("cargo:rustc-check-cfg=cfg(pilcrow_portable)");
+    // The `objc` message macros test for a `cargo-clippy` feature.
+    println!("cargo:rustc-check-cfg=cfg(feature, values(\"cargo-clippy\"))");
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if env::var("CARGO_FEATURE_PORTABLE").is_ok() || (target_os != "macos" && target_os != "ios") {
+        println!("cargo:rustc-cfg=pilcrow_portable");
+    }
+
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     let config = Config::from_file("cbindgen.toml").expect("Failed to read `cbindgen.toml`!");
     Builder::new().with_crate(crate_dir)
@@ -0,0 +1,199 @@
+// pilcrow/src/attributed_string.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The backend-neutral storage for paragraph text.
+//!
+//! All indices are in characters (Unicode scalar values), not bytes. Layout backends convert this
+//! into whatever representation they need (e.g. Core Text attributes) at layout time.
+
+use std::ops::Range;
+
+use format::Format;
+
+/// A string plus a run-length list of the formats that apply to each span of it.
+#[derive(Clone)]
+pub(crate) struct AttributedString {
+    string: String,
+    spans: Vec<FormatSpan>,
+    /// The formats that text inserted into this string takes while it's empty.
+    empty_formats: Vec<Format>,
+}
+
+#[derive(Clone)]
+struct FormatSpan {
+    char_len: usize,
+    formats: Vec<Format>,
+}
+
+impl AttributedString {
+    #[inline]
+    pub(crate) fn new() -> AttributedString {
+        AttributedString {
+            string: String::new(),
+            spans: vec![],
+            empty_formats: vec![],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn string(&self) -> &str {
+        &self.string
+    }
+
+    pub(crate) fn char_len(&self) -> usize {
+        self.spans.iter().map(|span| span.char_len).sum()
+    }
+
+    pub(crate) fn push_str(&mut self, string: &str, formats: Vec<Format>) {
+        let char_len = string.chars().count();
+        if char_len == 0 {
+            return
+        }
+        self.string.push_str(string);
+        self.spans.push(FormatSpan {
+            char_len,
+            formats,
+        })
+    }
+
+    /// Returns the formats of the character at `position`, or of the last character if
+    /// `position` is at the end of the string.
+    pub(crate) fn formats_at(&self, position: usize) -> &[Format] {
+        let mut span_start = 0;
+        for span in &self.spans {
+            if position < span_start + span.char_len {
+                return &span.formats
+            }
+            span_start += span.char_len;
+        }
+        match self.spans.last() {
+            None => &self.empty_formats,
+            Some(span) => &span.formats,
+        }
+    }
+
+    /// Returns the formats that text inserted at `position` takes: those of the character before
+    /// it, or of the character after it if `position` is at the start of the string.
+    #[inline]
+    pub(crate) fn insertion_formats_at(&self, position: usize) -> &[Format] {
+        self.formats_at(position.saturating_sub(1))
+    }
+
+    /// Removes the characters from `position` to the end, returning them. If that's nothing, what
+    /// is returned still carries the formats of the last character, for text inserted into it.
+    pub(crate) fn split_off(&mut self, position: usize) -> AttributedString {
+        let formats = self.formats_at(position).to_vec();
+        let char_len = self.char_len();
+        let mut tail = self.remove(position..char_len);
+        tail.empty_formats = formats;
+        tail
+    }
+
+    /// Inserts `other` so that its first character is at `position`.
+    pub(crate) fn insert(&mut self, position: usize, other: AttributedString) {
+        let byte_index = byte_index_for_char_index(&self.string, position);
+        self.string.insert_str(byte_index, &other.string);
+
+        let span_index = self.split_span_at(position);
+        for (offset, span) in other.spans.into_iter().enumerate() {
+            self.spans.insert(span_index + offset, span)
+        }
+    }
+
+    /// Appends `other` to the end of this string.
+    #[inline]
+    pub(crate) fn append(&mut self, other: AttributedString) {
+        let char_len = self.char_len();
+        self.insert(char_len, other)
+    }
+
+    /// Removes the characters in `range`, returning them with their formats.
+    pub(crate) fn remove(&mut self, range: Range<usize>) -> AttributedString {
+        let byte_start = byte_index_for_char_index(&self.string, range.start);
+        let byte_len = byte_index_for_char_index(&self.string[byte_start..],
+                                                 range.end - range.start);
+        let string = self.string.drain(byte_start..(byte_start + byte_len)).collect();
+
+        let start_span_index = self.split_span_at(range.start);
+        let end_span_index = self.split_span_at(range.end);
+        let spans = self.spans.drain(start_span_index..end_span_index).collect();
+        AttributedString {
+            string,
+            spans,
+            empty_formats: vec![],
+        }
+    }
+
+    /// Calls `change` on the formats of each span, then joins the spans whose formats it made
+    /// the same.
+    pub(crate) fn change_formats<F>(&mut self, mut change: F) where F: FnMut(&mut Vec<Format>) {
+        for span in &mut self.spans {
+            change(&mut span.formats)
+        }
+        self.coalesce_spans()
+    }
+
+    /// Joins adjacent spans that have the same formats.
+    pub(crate) fn coalesce_spans(&mut self) {
+        let mut spans: Vec<FormatSpan> = Vec::with_capacity(self.spans.len());
+        for span in self.spans.drain(..) {
+            match spans.last_mut() {
+                Some(last_span) if last_span.formats == span.formats => {
+                    last_span.char_len += span.char_len
+                }
+                _ => spans.push(span),
+            }
+        }
+        self.spans = spans
+    }
+
+    /// Returns the character range, text, and formats of each span.
+    pub(crate) fn spans(&self) -> Vec<(Range<usize>, &str, &[Format])> {
+        let mut char_start = 0;
+        let mut byte_start = 0;
+        self.spans.iter().map(|span| {
+            let char_range = char_start..(char_start + span.char_len);
+            let byte_len = byte_index_for_char_index(&self.string[byte_start..], span.char_len);
+            let string = &self.string[byte_start..(byte_start + byte_len)];
+            char_start = char_range.end;
+            byte_start += byte_len;
+            (char_range, string, &span.formats[..])
+        }).collect()
+    }
+
+    /// Ensures that a span boundary falls at `position`, and returns the index of the span that
+    /// begins there (which may be one past the last span).
+    fn split_span_at(&mut self, position: usize) -> usize {
+        let mut span_index = 0;
+        let mut span_start = 0;
+        while span_index < self.spans.len() &&
+                span_start + self.spans[span_index].char_len <= position {
+            span_start += self.spans[span_index].char_len;
+            span_index += 1;
+        }
+
+        if span_index < self.spans.len() && span_start < position {
+            let split_len = position - span_start;
+            let tail = FormatSpan {
+                char_len: self.spans[span_index].char_len - split_len,
+                formats: self.spans[span_index].formats.clone(),
+            };
+            self.spans[span_index].char_len = split_len;
+            span_index += 1;
+            self.spans.insert(span_index, tail);
+        }
+
+        span_index
+    }
+}
+
+fn byte_index_for_char_index(string: &str, char_index: usize) -> usize {
+    string.char_indices().nth(char_index).map(|(byte_index, _)| byte_index).unwrap_or(string.len())
+}
@@ -8,19 +8,28 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use core_text::font::CTFont;
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(not(pilcrow_portable))]
+use core_foundation::base::TCFType;
+#[cfg(not(pilcrow_portable))]
+use core_text::font::{CTFont, CTFontRef};
 use euclid::SideOffsets2D;
 use libc::c_uchar;
 use std::cmp;
+use std::ops::Range;
 use std::ptr;
 use std::slice;
 use std::str;
 
-use format::Font;
+use Font;
+use hyphenation::Hyphenator;
 use markdown::{BlockSelector, InlineSelector, MarkdownParser, ParseResults};
-use {Document, DocumentStyle, Paragraph, ParagraphStyle, TextLocation};
+use {Document, DocumentStyle, LineHeight, Paragraph, ParagraphStyle, TabAlignment, TabStop};
+use {BaseDirection, TextAlignment, TextLocation, TextOrientation, WritingMode};
 
-pub type NativeFont = CTFont;
+#[cfg(not(pilcrow_portable))]
+pub type NativeFont = CTFontRef;
 
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_document_new() -> *mut Document {
@@ -44,6 +53,69 @@ pub unsafe extern "C" fn pilcrow_document_append_document(this_document: *mut Do
     (*this_document).append_document(*Box::from_raw(other_document))
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_begin_undo_group(document: *mut Document) {
+    (*document).begin_undo_group()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_end_undo_group(document: *mut Document) {
+    (*document).end_undo_group()
+}
+
+/// Undoes the last group of edits and returns true, storing the smallest range that covers all
+/// the text that it changed in `start` and `end`, or returns false if there's nothing to undo.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_undo(document: *mut Document,
+                                               start: *mut TextLocation,
+                                               end: *mut TextLocation)
+                                               -> bool {
+    store_range((*document).undo(), start, end)
+}
+
+/// Redoes the last group of edits undone and returns true, storing the smallest range that
+/// covers all the text that it changed in `start` and `end`, or returns false if there's nothing
+/// to redo.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_redo(document: *mut Document,
+                                               start: *mut TextLocation,
+                                               end: *mut TextLocation)
+                                               -> bool {
+    store_range((*document).redo(), start, end)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_clear_history(document: *mut Document) {
+    (*document).clear_history()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_insert_paragraph(document: *mut Document,
+                                                           index: usize,
+                                                           paragraph: *mut Paragraph) {
+    (*document).insert_paragraph(index, *Box::from_raw(paragraph))
+}
+
+/// Removes paragraph `index` and returns it, to be freed with `pilcrow_paragraph_destroy()`.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_remove_paragraph(document: *mut Document, index: usize)
+                                                           -> *mut Paragraph {
+    Box::into_raw(Box::new((*document).remove_paragraph(index)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_split_paragraph_at(document: *mut Document,
+                                                             location: *const TextLocation) {
+    (*document).split_paragraph_at(*location)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_merge_paragraph_with_next(document: *mut Document,
+                                                                    index: usize)
+                                                                    -> TextLocation {
+    (*document).merge_paragraph_with_next(index)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_document_copy_string_in_range(document: *const Document,
                                                                start: *const TextLocation,
@@ -57,6 +129,26 @@ pub unsafe extern "C" fn pilcrow_document_copy_string(document: *const Document)
     Box::into_raw(Box::new((*document).copy_string()))
 }
 
+/// Replaces the text between `start` and `end` with `string`, and returns the end of the new
+/// text.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_replace_range(document: *mut Document,
+                                                        start: *const TextLocation,
+                                                        end: *const TextLocation,
+                                                        string: *const c_uchar,
+                                                        len: usize)
+                                                        -> TextLocation {
+    let string = str::from_utf8(slice::from_raw_parts(string, len)).unwrap();
+    (*document).replace_range((*start)..(*end), string).end
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_delete_range(document: *mut Document,
+                                                       start: *const TextLocation,
+                                                       end: *const TextLocation) {
+    (*document).delete_range((*start)..(*end))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_document_get_style(document: *mut Document)
                                                     -> *mut DocumentStyle {
@@ -73,6 +165,28 @@ pub unsafe extern "C" fn pilcrow_document_style_set_margin(style: *mut DocumentS
     (*style).margin = SideOffsets2D::new(top, right, bottom, left)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_style_add_fallback_font(style: *mut DocumentStyle,
+                                                                  font: *mut Font) {
+    (*style).fallback_fonts.push(*Box::from_raw(font))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_style_set_columns(style: *mut DocumentStyle,
+                                                            column_count: usize,
+                                                            column_gap: f32,
+                                                            column_rule: bool) {
+    (*style).column_count = column_count;
+    (*style).column_gap = column_gap;
+    (*style).column_rule = column_rule
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_document_style_set_writing_mode(style: *mut DocumentStyle,
+                                                                writing_mode: WritingMode) {
+    (*style).writing_mode = writing_mode
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_document_style_copy(dest: *mut DocumentStyle,
                                                      src: *const DocumentStyle) {
@@ -88,14 +202,132 @@ pub unsafe extern "C" fn pilcrow_paragraph_style_set_margin(style: *mut Paragrap
     (*style).margin = SideOffsets2D::new(top, right, bottom, left)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_alignment(style: *mut ParagraphStyle,
+                                                               alignment: TextAlignment,
+                                                               last_line_alignment:
+                                                               TextAlignment) {
+    (*style).alignment = alignment;
+    (*style).last_line_alignment = last_line_alignment
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_base_direction(style: *mut ParagraphStyle,
+                                                                    base_direction:
+                                                                    BaseDirection) {
+    (*style).base_direction = base_direction
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_text_orientation(style: *mut ParagraphStyle,
+                                                                     text_orientation:
+                                                                     TextOrientation) {
+    (*style).text_orientation = text_orientation
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_line_height_multiple(style:
+                                                                          *mut ParagraphStyle,
+                                                                          multiple: f32) {
+    (*style).line_height = LineHeight::Multiple(multiple)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_absolute_line_height(style:
+                                                                          *mut ParagraphStyle,
+                                                                          height: f32) {
+    (*style).line_height = LineHeight::Absolute(height)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_line_height_limits(style: *mut ParagraphStyle,
+                                                                        min: f32,
+                                                                        max: f32) {
+    (*style).min_line_height = min;
+    (*style).max_line_height = max
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_indents(style: *mut ParagraphStyle,
+                                                             first_line_indent: f32,
+                                                             hanging_indent: f32) {
+    (*style).first_line_indent = first_line_indent;
+    (*style).hanging_indent = hanging_indent
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_add_tab_stop(style: *mut ParagraphStyle,
+                                                              position: f32,
+                                                              alignment: TabAlignment) {
+    (*style).tab_stops.push(TabStop::new(position, alignment))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_clear_tab_stops(style: *mut ParagraphStyle) {
+    (*style).tab_stops.clear()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_default_tab_interval(style:
+                                                                          *mut ParagraphStyle,
+                                                                          interval: f32) {
+    (*style).default_tab_interval = interval
+}
+
+/// Sets the language tag of the paragraph, or clears it if `language` is null, and whether to
+/// hyphenate it. Only the portable backend hyphenates; the Core Text backend ignores these
+/// settings.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_paragraph_style_set_hyphenation(style: *mut ParagraphStyle,
+                                                                 language: *const c_uchar,
+                                                                 language_len: usize,
+                                                                 hyphenate: bool,
+                                                                 hyphenation_zone: f32) {
+    (*style).language = if language.is_null() {
+        None
+    } else {
+        let language = slice::from_raw_parts(language, language_len);
+        Some(str::from_utf8(language).unwrap().to_owned())
+    };
+    (*style).hyphenate = hyphenate;
+    (*style).hyphenation_zone = hyphenation_zone
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_paragraph_destroy(paragraph: *mut Paragraph) {
     drop(Box::from_raw(paragraph))
 }
 
+#[cfg(not(pilcrow_portable))]
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_font_new_from_native(native_font: NativeFont) -> *mut Font {
-    Box::into_raw(Box::new(Font::from_native_font(native_font.clone())))
+    Box::into_raw(Box::new(Font::from_native_font(CTFont::wrap_under_get_rule(native_font))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_font_new_from_bytes(bytes: *const c_uchar,
+                                                     len: usize,
+                                                     index: u32,
+                                                     size: f32)
+                                                     -> *mut Font {
+    let data = slice::from_raw_parts(bytes, len).to_vec();
+    match Font::from_bytes(data, index, size) {
+        Ok(font) => Box::into_raw(Box::new(font)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_font_new_from_path(path: *const c_uchar,
+                                                    len: usize,
+                                                    index: u32,
+                                                    size: f32)
+                                                    -> *mut Font {
+    let path = str::from_utf8(slice::from_raw_parts(path, len)).unwrap();
+    match Font::from_path(path, index, size) {
+        Ok(font) => Box::into_raw(Box::new(font)),
+        Err(_) => ptr::null_mut(),
+    }
 }
 
 #[no_mangle]
@@ -103,6 +335,20 @@ pub unsafe extern "C" fn pilcrow_font_destroy(font: *mut Font) {
     drop(Box::from_raw(font))
 }
 
+/// Registers hyphenation patterns for `language`, for the portable backend to use.
+#[no_mangle]
+pub unsafe extern "C" fn pilcrow_hyphenation_register_patterns(language: *const c_uchar,
+                                                               language_len: usize,
+                                                               patterns: *const c_uchar,
+                                                               patterns_len: usize,
+                                                               exceptions: *const c_uchar,
+                                                               exceptions_len: usize) {
+    let language = str::from_utf8(slice::from_raw_parts(language, language_len)).unwrap();
+    let patterns = str::from_utf8(slice::from_raw_parts(patterns, patterns_len)).unwrap();
+    let exceptions = str::from_utf8(slice::from_raw_parts(exceptions, exceptions_len)).unwrap();
+    Hyphenator::new(patterns, exceptions).register(language)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn pilcrow_markdown_parser_new() -> *mut MarkdownParser {
     Box::into_raw(Box::new(MarkdownParser::new()))
@@ -174,11 +420,27 @@ pub unsafe extern "C" fn pilcrow_string_destroy(string: *mut String) {
 }
 
 #[no_mangle]
+#[allow(dangerous_implicit_autorefs)]
 pub unsafe extern "C" fn pilcrow_string_get_byte_len(string: *const String) -> usize {
     (*string).len()
 }
 
 #[no_mangle]
+#[allow(dangerous_implicit_autorefs)]
 pub unsafe extern "C" fn pilcrow_string_get_chars(string: *const String) -> *const u8 {
     (*string).as_ptr()
 }
+
+unsafe fn store_range(range: Option<Range<TextLocation>>,
+                      start: *mut TextLocation,
+                      end: *mut TextLocation)
+                      -> bool {
+    match range {
+        None => false,
+        Some(range) => {
+            *start = range.start;
+            *end = range.end;
+            true
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// pilcrow/src/font.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Loading fonts from TrueType and OpenType data, and matching them by family, weight, and style.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+use ttf_parser::{self, name_id};
+
+use Font;
+
+pub const FONT_WEIGHT_NORMAL: u16 = 400;
+pub const FONT_WEIGHT_BOLD: u16 = 700;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+/// A set of font faces, searchable by family, weight, and style.
+#[derive(Clone, Default)]
+pub struct FontCollection {
+    faces: Vec<Arc<FontFace>>,
+}
+
+impl FontCollection {
+    #[inline]
+    pub fn new() -> FontCollection {
+        FontCollection {
+            faces: vec![],
+        }
+    }
+
+    pub fn from_directory<P>(path: P) -> io::Result<FontCollection> where P: AsRef<Path> {
+        let mut collection = FontCollection::new();
+        collection.add_directory(path)?;
+        Ok(collection)
+    }
+
+    /// Adds every font file in `path` and its subdirectories. Files that aren't fonts, or that
+    /// fail to parse, are skipped.
+    pub fn add_directory<P>(&mut self, path: P) -> io::Result<()> where P: AsRef<Path> {
+        let mut entries: Vec<_> = fs::read_dir(path)?.filter_map(|entry| entry.ok())
+                                                     .map(|entry| entry.path())
+                                                     .collect();
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() {
+                self.add_directory(&entry)?;
+                continue
+            }
+            let is_font = match entry.extension().and_then(|extension| extension.to_str()) {
+                Some(extension) => {
+                    let extension = extension.to_ascii_lowercase();
+                    extension == "ttf" || extension == "otf" || extension == "ttc" ||
+                        extension == "otc"
+                }
+                None => false,
+            };
+            if is_font {
+                match self.add_path(&entry) {
+                    Ok(()) => {}
+                    // Skip files that fail to load.
+                    Err(_) => continue,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds every face in the font file at `path`.
+    pub fn add_path<P>(&mut self, path: P) -> io::Result<()> where P: AsRef<Path> {
+        self.add_bytes(fs::read(path)?)
+    }
+
+    /// Adds every face in `data`, which may be a single font or a font collection. If any face
+    /// fails to parse, none are added.
+    pub fn add_bytes(&mut self, data: Vec<u8>) -> io::Result<()> {
+        let data = Arc::new(data);
+        let face_count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+        let faces = (0..face_count).map(|index| {
+            FontFace::new(data.clone(), index).map(Arc::new)
+        }).collect::<io::Result<Vec<_>>>()?;
+        self.faces.extend(faces);
+        Ok(())
+    }
+
+    /// Returns the names of all families in this collection, without duplicates.
+    pub fn families(&self) -> Vec<&str> {
+        let mut families: Vec<&str> = vec![];
+        for face in &self.faces {
+            if !families.iter().any(|family| family.eq_ignore_ascii_case(&face.family)) {
+                families.push(&face.family)
+            }
+        }
+        families
+    }
+
+    /// Returns the face of `family` that best matches `weight` and `style`, per the CSS font
+    /// matching algorithm.
+    pub fn select(&self, family: &str, weight: u16, style: FontStyle, size: f32)
+                  -> Option<Font> {
+        let family_faces = self.family_faces(family);
+        let face = match_face(&family_faces, weight, style)?;
+        Some(Font::from_face(face, family_faces, size))
+    }
+
+    pub(crate) fn family_faces(&self, family: &str) -> Vec<Arc<FontFace>> {
+        self.faces.iter().filter(|face| face.family.eq_ignore_ascii_case(family)).cloned().collect()
+    }
+}
+
+/// A single face within a font file, along with the properties needed to select and lay it out.
+/// Core Text reads the metrics itself.
+#[cfg_attr(not(pilcrow_portable), allow(dead_code))]
+pub(crate) struct FontFace {
+    /// The face parsed from `data`, which it borrows. It's declared first so that it's dropped
+    /// before the data is.
+    #[cfg(pilcrow_portable)]
+    parsed: rustybuzz::Face<'static>,
+    /// The font file, which the portable backend only reads through `parsed`.
+    #[cfg_attr(pilcrow_portable, allow(dead_code))]
+    pub(crate) data: Arc<Vec<u8>>,
+    pub(crate) family: String,
+    pub(crate) weight: u16,
+    pub(crate) style: FontStyle,
+    /// The OS/2 width class, from 1 (ultra-condensed) to 9 (ultra-expanded).
+    pub(crate) width: u16,
+    pub(crate) units_per_em: f32,
+    pub(crate) ascender: f32,
+    pub(crate) descender: f32,
+    pub(crate) line_gap: f32,
+}
+
+impl FontFace {
+    pub(crate) fn new(data: Arc<Vec<u8>>, index: u32) -> io::Result<FontFace> {
+        // The bytes stay where they are, unchanged, for as long as `data` holds them, which is
+        // as long as this face lives.
+        let bytes: &'static [u8] = unsafe { slice::from_raw_parts(data.as_ptr(), data.len()) };
+        let face = ttf_parser::Face::parse(bytes, index).map_err(|error| {
+            io::Error::new(ErrorKind::InvalidData, error.to_string())
+        })?;
+        let family = face_name(&face, name_id::TYPOGRAPHIC_FAMILY)
+            .or_else(|| face_name(&face, name_id::FAMILY))
+            .unwrap_or_default();
+        let style = match face.style() {
+            ttf_parser::Style::Normal => FontStyle::Normal,
+            ttf_parser::Style::Italic => FontStyle::Italic,
+            ttf_parser::Style::Oblique => FontStyle::Oblique,
+        };
+        Ok(FontFace {
+            family,
+            weight: face.weight().to_number(),
+            style,
+            width: face.width().to_number(),
+            units_per_em: face.units_per_em() as f32,
+            ascender: face.ascender() as f32,
+            descender: face.descender() as f32,
+            line_gap: face.line_gap() as f32,
+            #[cfg(pilcrow_portable)]
+            parsed: rustybuzz::Face::from_face(face),
+            data,
+        })
+    }
+
+    /// Returns the parsed face, for shaping and reading glyphs without parsing it again.
+    #[cfg(pilcrow_portable)]
+    #[inline]
+    pub(crate) fn parsed(&self) -> &rustybuzz::Face<'_> {
+        &self.parsed
+    }
+}
+
+impl Font {
+    /// Loads face `index` of the TrueType, OpenType, or font collection data in `data`.
+    pub fn from_bytes(data: Vec<u8>, index: u32, size: f32) -> io::Result<Font> {
+        let face = Arc::new(FontFace::new(Arc::new(data), index)?);
+        Ok(Font::from_face(face.clone(), vec![face], size))
+    }
+
+    /// Loads face `index` of the font file at `path`. Other faces in the same file are used as
+    /// the bold and italic variants of this font.
+    pub fn from_path<P>(path: P, index: u32, size: f32) -> io::Result<Font>
+                        where P: AsRef<Path> {
+        let mut collection = FontCollection::new();
+        collection.add_path(path)?;
+        let face = match collection.faces.get(index as usize) {
+            None => return Err(io::Error::new(ErrorKind::NotFound, "No face at that index")),
+            Some(face) => face.clone(),
+        };
+        let family_faces = collection.family_faces(&face.family);
+        Ok(Font::from_face(face, family_faces, size))
+    }
+}
+
+impl PartialEq for Font {
+    #[inline]
+    fn eq(&self, other: &Font) -> bool {
+        self.id() == other.id()
+    }
+}
+
+/// Picks the face among `faces` that best matches `weight` and `style`.
+pub(crate) fn match_face(faces: &[Arc<FontFace>], weight: u16, style: FontStyle)
+                         -> Option<Arc<FontFace>> {
+    faces.iter().min_by(|a, b| {
+        let width_distance = |face: &FontFace| (face.width as i32 - 5).abs();
+        width_distance(a).cmp(&width_distance(b))
+                         .then(style_rank(a.style, style).cmp(&style_rank(b.style, style)))
+                         .then(compare_weights(a.weight, b.weight, weight))
+    }).cloned()
+}
+
+fn style_rank(face_style: FontStyle, desired_style: FontStyle) -> u8 {
+    let preferences = match desired_style {
+        FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+        FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+        FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+    };
+    preferences.iter().position(|&style| style == face_style).unwrap() as u8
+}
+
+/// Orders two face weights by how well they match `desired`, following CSS Fonts Level 3
+/// § 5.2.
+fn compare_weights(a: u16, b: u16, desired: u16) -> Ordering {
+    let rank = |weight: u16| -> (u8, u16) {
+        if weight == desired {
+            (0, 0)
+        } else if (400..=500).contains(&desired) {
+            if weight > desired && weight <= 500 {
+                (1, weight - desired)
+            } else if weight < desired {
+                (2, desired - weight)
+            } else {
+                (3, weight - desired)
+            }
+        } else if desired < 400 {
+            if weight < desired {
+                (1, desired - weight)
+            } else {
+                (2, weight - desired)
+            }
+        } else if weight > desired {
+            (1, weight - desired)
+        } else {
+            (2, desired - weight)
+        }
+    };
+    rank(a).cmp(&rank(b))
+}
+
+fn face_name(face: &ttf_parser::Face, name_id: u16) -> Option<String> {
+    face.names().into_iter().filter(|name| name.name_id == name_id && name.is_unicode())
+                            .filter_map(|name| name.to_string())
+                            .next()
+}
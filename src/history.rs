@@ -0,0 +1,265 @@
+// pilcrow/src/history.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The undo and redo stacks of a document.
+//!
+//! Every recorded edit is stored as the step that reverses it. Undoing applies the steps of the
+//! last unit in reverse, and the steps that reverse those become the unit to redo.
+
+use std::ops::Range;
+
+use attributed_string::AttributedString;
+use format::Format;
+use {ParagraphStyle, TextLocation};
+
+/// A change to a document that can be applied to it, yielding the change that reverses it.
+pub(crate) enum EditStep {
+    /// Replaces a range with a fragment.
+    Splice(Range<TextLocation>, Fragment),
+    /// Inserts a paragraph with the given style and text at an index.
+    InsertParagraph(usize, ParagraphStyle, AttributedString),
+    /// Removes the paragraph at an index.
+    RemoveParagraph(usize),
+}
+
+/// Text cut from or pasted into a document. The first paragraph's text joins the paragraph that
+/// it's pasted into, and each following one starts a new paragraph with its own style.
+#[derive(Clone)]
+pub(crate) struct Fragment {
+    pub(crate) first: AttributedString,
+    pub(crate) rest: Vec<(ParagraphStyle, AttributedString)>,
+}
+
+pub(crate) struct History {
+    undo_units: Vec<UndoUnit>,
+    redo_units: Vec<UndoUnit>,
+    /// The unit that edits are recorded into while a group is open.
+    group: Option<UndoUnit>,
+    group_depth: usize,
+}
+
+/// The steps that undo one user action, in the order it made the changes that they reverse.
+struct UndoUnit {
+    steps: Vec<EditStep>,
+    /// Where the typing that this unit reverses ended, if it's typing, so that more typing there
+    /// can join it.
+    typing: Option<Typing>,
+}
+
+#[derive(Clone, Copy)]
+struct Typing {
+    end: TextLocation,
+    ends_with_whitespace: bool,
+}
+
+impl History {
+    #[inline]
+    pub(crate) fn new() -> History {
+        History {
+            undo_units: vec![],
+            redo_units: vec![],
+            group: None,
+            group_depth: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        *self = History::new()
+    }
+
+    #[inline]
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo_units.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo_units.is_empty()
+    }
+
+    pub(crate) fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group = Some(UndoUnit { steps: vec![], typing: None })
+        }
+        self.group_depth += 1
+    }
+
+    pub(crate) fn end_group(&mut self) {
+        assert!(self.group_depth > 0, "History::end_group(): No group is open!");
+        self.group_depth -= 1;
+        if self.group_depth > 0 {
+            return
+        }
+        let group = self.group.take().expect("History::end_group(): Group missing!");
+        if !group.steps.is_empty() {
+            self.undo_units.push(group)
+        }
+    }
+
+    /// Records `inverse`, the step that reverses an edit.
+    pub(crate) fn record(&mut self, inverse: EditStep) {
+        self.redo_units.clear();
+        match self.group {
+            Some(ref mut group) => group.steps.push(inverse),
+            None => self.undo_units.push(UndoUnit { steps: vec![inverse], typing: None }),
+        }
+    }
+
+    /// Records the insertion of `string` over `inserted_range`, which was empty, joining it to
+    /// the typing before it if it continues that typing and doesn't start a new word.
+    pub(crate) fn record_typing(&mut self, inserted_range: Range<TextLocation>, string: &str) {
+        let ends_with_whitespace = string.chars().last().is_some_and(char::is_whitespace);
+        let typing = Typing { end: inserted_range.end, ends_with_whitespace };
+
+        let last_unit = if self.group.is_none() { self.undo_units.last_mut() } else { None };
+        if let Some(unit) = last_unit {
+            if let (Some(last_typing), Some(&mut EditStep::Splice(ref mut range, _))) =
+                    (unit.typing, unit.steps.last_mut()) {
+                // A word starts where something other than whitespace follows whitespace.
+                let starts_word = last_typing.ends_with_whitespace &&
+                    !string.chars().next().is_some_and(char::is_whitespace);
+                if last_typing.end == inserted_range.start && !starts_word {
+                    range.end = inserted_range.end;
+                    unit.typing = Some(typing);
+                    self.redo_units.clear();
+                    return
+                }
+            }
+        }
+
+        self.record(EditStep::Splice(inserted_range, Fragment::empty()));
+        if self.group.is_none() {
+            self.undo_units.last_mut().unwrap().typing = Some(typing)
+        }
+    }
+
+    /// Records `inverse` just before the step last recorded, in the same unit, so that undoing
+    /// applies it after that step.
+    pub(crate) fn record_before_last(&mut self, inverse: EditStep) {
+        let unit = match self.group {
+            Some(ref mut group) => group,
+            None => self.undo_units.last_mut().expect("History::record_before_last(): No unit!"),
+        };
+        let index = unit.steps.len() - 1;
+        unit.steps.insert(index, inverse)
+    }
+
+    /// Takes the steps of the last unit to undo, last step first. Typing after this isn't
+    /// joined to the unit before it.
+    pub(crate) fn take_undo(&mut self) -> Option<Vec<EditStep>> {
+        let unit = self.undo_units.pop()?;
+        if let Some(last_unit) = self.undo_units.last_mut() {
+            last_unit.typing = None
+        }
+        Some(unit.steps.into_iter().rev().collect())
+    }
+
+    /// Takes the steps of the last unit to redo, last step first.
+    #[inline]
+    pub(crate) fn take_redo(&mut self) -> Option<Vec<EditStep>> {
+        self.redo_units.pop().map(|unit| unit.steps.into_iter().rev().collect())
+    }
+
+    /// Records `steps`, the reverse of an undo in the order they were made, as the next unit to
+    /// redo.
+    #[inline]
+    pub(crate) fn push_redo(&mut self, steps: Vec<EditStep>) {
+        self.redo_units.push(UndoUnit { steps, typing: None })
+    }
+
+    /// Records `steps`, the reverse of a redo in the order they were made, as the next unit to
+    /// undo, without forgetting what's left to redo.
+    #[inline]
+    pub(crate) fn push_undo(&mut self, steps: Vec<EditStep>) {
+        self.undo_units.push(UndoUnit { steps, typing: None })
+    }
+
+    #[inline]
+    pub(crate) fn is_grouping(&self) -> bool {
+        self.group_depth > 0
+    }
+}
+
+impl EditStep {
+    /// Moves `location`, in the document as it was before the edit that this step reverses, to
+    /// where it is after that edit. A location inside the text that the edit replaced moves to
+    /// the start of what replaced it, or to the end if `at_end` is true.
+    pub(crate) fn move_location(&self, location: TextLocation, at_end: bool) -> TextLocation {
+        // The edit replaced the text from `start` to `old_end` with text ending at `new_end`.
+        let (start, old_end, new_end) = match *self {
+            EditStep::Splice(ref range, ref removed) => {
+                (range.start, removed.end(range.start), range.end)
+            }
+            EditStep::InsertParagraph(index, ..) => {
+                (TextLocation::new(index, 0), TextLocation::new(index + 1, 0),
+                 TextLocation::new(index, 0))
+            }
+            EditStep::RemoveParagraph(index) => {
+                (TextLocation::new(index, 0), TextLocation::new(index, 0),
+                 TextLocation::new(index + 1, 0))
+            }
+        };
+        if location < start || (location == start && !at_end) {
+            location
+        } else if location < old_end {
+            if at_end { new_end } else { start }
+        } else if location.paragraph_index == old_end.paragraph_index {
+            TextLocation::new(new_end.paragraph_index,
+                              new_end.character_index + location.character_index -
+                              old_end.character_index)
+        } else {
+            TextLocation::new(location.paragraph_index + new_end.paragraph_index -
+                              old_end.paragraph_index,
+                              location.character_index)
+        }
+    }
+}
+
+impl Fragment {
+    #[inline]
+    pub(crate) fn empty() -> Fragment {
+        Fragment {
+            first: AttributedString::new(),
+            rest: vec![],
+        }
+    }
+
+    /// Makes a fragment of `string`, with a paragraph of `style` after each newline, and all
+    /// text in `formats`.
+    pub(crate) fn from_string(string: &str,
+                              formats: &[Format],
+                              style: &ParagraphStyle)
+                              -> Fragment {
+        let mut lines = string.split('\n').map(|line| {
+            let mut attributed_string = AttributedString::new();
+            attributed_string.push_str(line, formats.to_vec());
+            attributed_string
+        });
+        let first = lines.next().unwrap_or_else(AttributedString::new);
+        Fragment {
+            first,
+            rest: lines.map(|line| (style.clone(), line)).collect(),
+        }
+    }
+
+    /// Returns where this fragment ends when it's pasted at `start`.
+    pub(crate) fn end(&self, start: TextLocation) -> TextLocation {
+        match self.rest.last() {
+            None => {
+                TextLocation::new(start.paragraph_index,
+                                  start.character_index + self.first.char_len())
+            }
+            Some((_, last)) => {
+                TextLocation::new(start.paragraph_index + self.rest.len(), last.char_len())
+            }
+        }
+    }
+}
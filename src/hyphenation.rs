@@ -0,0 +1,152 @@
+// pilcrow/src/hyphenation.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding where words may be hyphenated, with Liang's algorithm as used by TeX.
+//!
+//! No patterns are built in. Load the patterns for each language, in the format of TeX's
+//! `\patterns` and `\hyphenation` lists, and register them under a BCP 47 language tag; paragraphs
+//! whose style names that language are then hyphenated with them. Only the portable backend
+//! hyphenates; Core Text breaks lines between words alone.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    static ref HYPHENATORS: RwLock<HashMap<String, Arc<Hyphenator>>> = {
+        RwLock::new(HashMap::new())
+    };
+}
+
+pub struct Hyphenator {
+    /// The interletter values of each pattern, keyed by its letters, with `.` marking the start
+    /// or end of a word.
+    patterns: HashMap<String, Vec<u8>>,
+    /// The number of letters in the longest pattern.
+    max_pattern_len: usize,
+    /// Words hyphenated explicitly, keyed by their lowercase letters, with the offsets of their
+    /// hyphens.
+    exceptions: HashMap<String, Vec<usize>>,
+    /// The fewest letters to leave before a hyphen.
+    pub left_min: usize,
+    /// The fewest letters to carry over after a hyphen.
+    pub right_min: usize,
+}
+
+impl Hyphenator {
+    /// Creates a hyphenator from whitespace-separated Liang patterns such as `hy3ph` and `.ach4`,
+    /// and whitespace-separated exceptions with their hyphens marked, such as `ta-ble`. The
+    /// minimum fragment lengths start at TeX's defaults for English, 2 and 3.
+    pub fn new(patterns: &str, exceptions: &str) -> Hyphenator {
+        let mut hyphenator = Hyphenator {
+            patterns: HashMap::new(),
+            max_pattern_len: 0,
+            exceptions: HashMap::new(),
+            left_min: 2,
+            right_min: 3,
+        };
+
+        for pattern in patterns.split_whitespace() {
+            let mut letters = String::new();
+            let mut values = vec![0];
+            for ch in pattern.chars() {
+                match ch.to_digit(10) {
+                    Some(value) => *values.last_mut().unwrap() = value as u8,
+                    None => {
+                        letters.extend(ch.to_lowercase());
+                        values.push(0)
+                    }
+                }
+            }
+            hyphenator.max_pattern_len = hyphenator.max_pattern_len.max(values.len() - 1);
+            hyphenator.patterns.insert(letters, values);
+        }
+
+        for exception in exceptions.split_whitespace() {
+            let mut letters = String::new();
+            let mut offsets = vec![];
+            for ch in exception.chars() {
+                if ch == '-' {
+                    offsets.push(letters.chars().count())
+                } else {
+                    letters.extend(ch.to_lowercase())
+                }
+            }
+            hyphenator.exceptions.insert(letters, offsets);
+        }
+
+        hyphenator
+    }
+
+    /// Makes this hyphenator the one for paragraphs in the language `language_tag`, replacing
+    /// any registered before. A tag like `en-US` also covers `en-US-x-newsletter`, and `en`
+    /// covers both.
+    pub fn register(self, language_tag: &str) {
+        HYPHENATORS.write().unwrap().insert(language_tag.to_lowercase(), Arc::new(self));
+    }
+
+    /// Returns the hyphenator registered for `language_tag`, or else for the longest prefix of
+    /// it that ends between subtags.
+    #[cfg(pilcrow_portable)]
+    pub(crate) fn for_language(language_tag: &str) -> Option<Arc<Hyphenator>> {
+        let hyphenators = HYPHENATORS.read().unwrap();
+        let mut language_tag = &*language_tag.to_lowercase();
+        loop {
+            if let Some(hyphenator) = hyphenators.get(language_tag) {
+                return Some(hyphenator.clone())
+            }
+            match language_tag.rfind('-') {
+                None => return None,
+                Some(index) => language_tag = &language_tag[..index],
+            }
+        }
+    }
+
+    /// Returns the offsets within `word`, in characters, where a hyphen may go, in increasing
+    /// order.
+    pub fn hyphenate(&self, word: &[char]) -> Vec<usize> {
+        let letters: String = word.iter().flat_map(|ch| ch.to_lowercase()).collect();
+        if letters.chars().count() != word.len() {
+            // Lowercasing changed the length, so the offsets wouldn't line up.
+            return vec![]
+        }
+
+        let offsets = match self.exceptions.get(&letters) {
+            Some(offsets) => offsets.clone(),
+            None => {
+                let padded: Vec<char> = Some('.').into_iter()
+                                                 .chain(letters.chars())
+                                                 .chain(Some('.'))
+                                                 .collect();
+                let mut values = vec![0; padded.len() + 1];
+                let mut substring = String::new();
+                for start in 0..padded.len() {
+                    substring.clear();
+                    let max_end = padded.len().min(start + self.max_pattern_len);
+                    for end in (start + 1)..(max_end + 1) {
+                        substring.push(padded[end - 1]);
+                        if let Some(pattern_values) = self.patterns.get(&substring) {
+                            for (offset, &value) in pattern_values.iter().enumerate() {
+                                let value_index = start + offset;
+                                values[value_index] = values[value_index].max(value)
+                            }
+                        }
+                    }
+                }
+
+                // The value before letter `offset` of the word follows the leading `.`.
+                (1..word.len()).filter(|&offset| values[offset + 1] % 2 == 1).collect()
+            }
+        };
+
+        offsets.into_iter().filter(|&offset| {
+            offset >= self.left_min.max(1) && offset + self.right_min.max(1) <= word.len()
+        }).collect()
+    }
+}
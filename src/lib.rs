@@ -8,62 +8,93 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[cfg(not(pilcrow_portable))]
 extern crate cocoa;
+#[cfg(not(pilcrow_portable))]
+#[macro_use]
 extern crate core_foundation;
+#[cfg(not(pilcrow_portable))]
 extern crate core_graphics;
+#[cfg(not(pilcrow_portable))]
 extern crate core_text;
 extern crate euclid;
+#[cfg(not(pilcrow_portable))]
+extern crate foreign_types;
 extern crate indexmap;
 extern crate libc;
 extern crate pulldown_cmark;
 extern crate rayon;
+#[cfg(pilcrow_portable)]
+extern crate rustybuzz;
+extern crate tiny_skia;
+extern crate ttf_parser;
+extern crate unicode_bidi;
+extern crate unicode_linebreak;
+extern crate unicode_properties;
+extern crate unicode_segmentation;
+extern crate unicode_vo;
 
 #[macro_use]
 extern crate lazy_static;
+#[cfg(not(pilcrow_portable))]
 #[macro_use]
 extern crate objc;
 
-pub use format::{Color, Font, FontFaceId, FontId, Format, Image};
-
-use core_foundation::attributedstring::{CFAttributedString, CFMutableAttributedString};
-use core_foundation::base::{CFIndex, CFRange, CFType, CFTypeRef, TCFType, kCFNotFound};
-use core_foundation::dictionary::{CFDictionary, CFMutableDictionary};
-use core_foundation::string::{CFString, CFStringRef};
-use core_foundation::stringtokenizer::{CFStringTokenizer, kCFStringTokenizerUnitWord};
-use core_graphics::base::CGFloat;
-use core_graphics::font::CGGlyph;
-use core_graphics::geometry::{CGPoint, CGRect, CGSize, CG_ZERO_POINT};
-use core_graphics::path::CGPath;
-use core_text::frame::CTFrame;
-use core_text::framesetter::CTFramesetter;
-use core_text::line::CTLine;
-use core_text::run::CTRun;
+pub use font::{FONT_WEIGHT_BOLD, FONT_WEIGHT_NORMAL, FontCollection, FontStyle};
+pub use format::{Color, Format, FormatKind, Image};
+pub use pdf::PdfWriter;
+
+#[cfg(not(pilcrow_portable))]
+pub use native::{Font, FontFaceId, FontId, Frame, Framesetter, Glyph, Line, Run};
+#[cfg(pilcrow_portable)]
+pub use portable::{Font, FontFaceId, FontId, Frame, Framesetter, Glyph, Line, Run};
+
+use attributed_string::AttributedString;
 use euclid::{Point2D, Rect, SideOffsets2D, Size2D, Vector2D};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use history::{EditStep, Fragment, History};
 use std::cmp::{self, Ordering};
+use std::f32;
+use std::mem;
 use std::ops::Range;
-use std::sync::{Mutex, MutexGuard, RwLock};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use unicode_vo::Orientation;
 
 pub mod ffi;
+pub mod hyphenation;
 pub mod markdown;
+pub mod segmentation;
 
+mod attributed_string;
+mod font;
 mod format;
-
-pub type Glyph = CGGlyph;
+mod history;
+#[cfg(not(pilcrow_portable))]
+mod native;
+#[cfg(not(pilcrow_portable))]
+mod native_ext;
+mod pdf;
+#[cfg(pilcrow_portable)]
+mod portable;
+mod render;
+mod svg;
+
+static NEXT_PARAGRAPH_REVISION: AtomicUsize = AtomicUsize::new(0);
 
 pub trait LayoutCallbacks: Send + Sync {
     fn get_image_size(&self, image_id: u32) -> Option<Size2D<u32>>;
-}
 
-lazy_static! {
-    static ref LAYOUT_CALLBACKS: RwLock<Option<Box<LayoutCallbacks>>> = {
-        RwLock::new(None)
-    };
+    /// Returns the pixels of an image as unpremultiplied RGBA, row by row from the top, for
+    /// `Section::render_to_rgba()`. Layout doesn't need them.
+    fn get_image_rgba(&self, _image_id: u32) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 pub struct Document {
     paragraphs: Vec<Paragraph>,
     style: DocumentStyle,
+    history: History,
 }
 
 impl Document {
@@ -72,12 +103,14 @@ impl Document {
         Document {
             paragraphs: vec![],
             style: DocumentStyle::default(),
+            history: History::new(),
         }
     }
 
     #[inline]
     pub fn clear(&mut self) {
-        self.paragraphs.clear()
+        self.paragraphs.clear();
+        self.history.clear()
     }
 
     #[inline]
@@ -87,7 +120,48 @@ impl Document {
 
     #[inline]
     pub fn append_document(&mut self, other_document: Document) {
-        self.paragraphs.extend(other_document.paragraphs.into_iter())
+        self.paragraphs.extend(other_document.paragraphs)
+    }
+
+    /// Inserts `paragraph` so that it's at `index`, moving the paragraphs from there on down.
+    pub fn insert_paragraph(&mut self, index: usize, paragraph: Paragraph) {
+        self.paragraphs.insert(index, paragraph);
+        self.history.record(EditStep::RemoveParagraph(index))
+    }
+
+    pub fn remove_paragraph(&mut self, index: usize) -> Paragraph {
+        let paragraph = self.paragraphs.remove(index);
+        self.history.record(EditStep::InsertParagraph(index,
+                                                      paragraph.style.clone(),
+                                                      paragraph.attributed_string.clone()));
+        paragraph
+    }
+
+    /// Splits the paragraph at `location` in two, with the text from `location` on in a new
+    /// paragraph after it. The new paragraph has the same style, and the formats that typing at
+    /// `location` would have had, even if it's empty.
+    pub fn split_paragraph_at(&mut self, location: TextLocation) {
+        assert!(self.contains_location(location),
+                "Document::split_paragraph_at(): Location out of bounds!");
+        let style = self.paragraphs[location.paragraph_index].style.clone();
+        let fragment = Fragment {
+            first: AttributedString::new(),
+            rest: vec![(style, AttributedString::new())],
+        };
+        let (range, removed) = self.splice(location..location, fragment);
+        self.history.record(EditStep::Splice(range, removed))
+    }
+
+    /// Appends the text of the paragraph after `index` to paragraph `index`, which keeps its
+    /// style, and returns the location where the appended text starts.
+    pub fn merge_paragraph_with_next(&mut self, index: usize) -> TextLocation {
+        assert!(index + 1 < self.paragraphs.len(),
+                "Document::merge_paragraph_with_next(): No paragraph after this one!");
+        let location = TextLocation::new(index, self.paragraphs[index].char_len());
+        let (range, removed) = self.splice(location..TextLocation::new(index + 1, 0),
+                                           Fragment::empty());
+        self.history.record(EditStep::Splice(range, removed));
+        location
     }
 
     #[inline]
@@ -146,45 +220,303 @@ impl Document {
     pub fn copy_string(&self) -> String {
         self.copy_string_in_range(self.entire_range())
     }
+
+    /// Replaces the text in `range` with `string`, returning the range that `string` now
+    /// occupies. Paragraphs that the range spans are merged into the first, taking its style,
+    /// and each newline in `string` starts a new paragraph with that style. The new text takes
+    /// the formats of the first character replaced or, if the range is empty, those of the
+    /// character before it, or after it at the start of a paragraph.
+    pub fn replace_range(&mut self, range: Range<TextLocation>, string: &str)
+                         -> Range<TextLocation> {
+        // An empty document is edited as one empty paragraph, which is kept, and undone with the
+        // edit, only if the edit changes something.
+        let adds_paragraph = self.paragraphs.is_empty();
+        if adds_paragraph {
+            self.paragraphs.push(Paragraph::new(ParagraphStyle::default()))
+        }
+        assert!(range.start <= range.end && self.contains_location(range.start) &&
+                self.contains_location(range.end),
+                "Document::replace_range(): Range out of bounds!");
+        if range.start == range.end && string.is_empty() {
+            if adds_paragraph {
+                self.paragraphs.clear()
+            }
+            return range
+        }
+
+        let fragment = {
+            let paragraph = &self.paragraphs[range.start.paragraph_index];
+            let attributed_string = &paragraph.attributed_string;
+            let formats = if range.start == range.end {
+                attributed_string.insertion_formats_at(range.start.character_index)
+            } else {
+                attributed_string.formats_at(range.start.character_index)
+            };
+            Fragment::from_string(string, formats, &paragraph.style)
+        };
+        let is_typing = range.start == range.end && !string.contains('\n');
+        let (range, removed) = self.splice(range, fragment);
+        if is_typing {
+            self.history.record_typing(range.clone(), string)
+        } else {
+            self.history.record(EditStep::Splice(range.clone(), removed))
+        }
+        if adds_paragraph {
+            self.history.record_before_last(EditStep::RemoveParagraph(0))
+        }
+        range
+    }
+
+    /// Removes the text in `range`, merging the paragraphs that it spans.
+    #[inline]
+    pub fn delete_range(&mut self, range: Range<TextLocation>) {
+        self.replace_range(range, "");
+    }
+
+    /// Adds `format` to the text in `range`, in place of any format of the same kind that it has.
+    pub fn add_format(&mut self, range: Range<TextLocation>, format: Format) {
+        let kind = format.kind();
+        self.change_formats(range, |formats| {
+            formats.retain(|other_format| other_format.kind() != kind);
+            formats.push(format.clone())
+        })
+    }
+
+    /// Removes the formats of `kind` from the text in `range`.
+    pub fn remove_format(&mut self, range: Range<TextLocation>, kind: FormatKind) {
+        self.change_formats(range, |formats| formats.retain(|format| format.kind() != kind))
+    }
+
+    /// Returns the formats of the character at `location`, or of the character before it if
+    /// it's at the end of its paragraph. Text typed at `location` takes those of the character
+    /// before it instead, unless it's at the start of its paragraph.
+    pub fn formats_at(&self, location: TextLocation) -> &[Format] {
+        assert!(self.contains_location(location),
+                "Document::formats_at(): Location out of bounds!");
+        let paragraph = &self.paragraphs[location.paragraph_index];
+        paragraph.attributed_string.formats_at(location.character_index)
+    }
+
+    /// Calls `change` on the formats of each span of the text in `range`, splitting spans at the
+    /// ends of the range and joining those that end up with the same formats.
+    fn change_formats<F>(&mut self, range: Range<TextLocation>, mut change: F)
+                         where F: FnMut(&mut Vec<Format>) {
+        assert!(range.start <= range.end && self.contains_location(range.start) &&
+                self.contains_location(range.end),
+                "Document::change_formats(): Range out of bounds!");
+        if range.start == range.end {
+            return
+        }
+
+        // Cut the text out, change it, and paste it back.
+        let (start, end) = (range.start, range.end);
+        let (_, mut fragment) = self.splice(range, Fragment::empty());
+        let old_fragment = fragment.clone();
+        fragment.first.change_formats(&mut change);
+        for &mut (_, ref mut attributed_string) in &mut fragment.rest {
+            attributed_string.change_formats(&mut change)
+        }
+        let (range, _) = self.splice(start..start, fragment);
+        for paragraph in &mut self.paragraphs[start.paragraph_index..(end.paragraph_index + 1)] {
+            paragraph.attributed_string.coalesce_spans()
+        }
+        self.history.record(EditStep::Splice(range, old_fragment))
+    }
+
+    /// Starts a group of edits that are undone and redone together, ending at the matching
+    /// `end_undo_group()`. Groups may be nested; only the outermost one counts.
+    #[inline]
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group()
+    }
+
+    #[inline]
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group()
+    }
+
+    /// Reverses the last group of edits, or run of typing, made through the methods of this
+    /// document, and returns the smallest range that covers all the text that it changed, which
+    /// is empty if it only removed text. Appending paragraphs and edits made through
+    /// `paragraphs_mut()` aren't recorded, so call `clear_history()` after editing that way.
+    pub fn undo(&mut self) -> Option<Range<TextLocation>> {
+        assert!(!self.history.is_grouping(), "Document::undo(): Undo group is open!");
+        let steps = self.history.take_undo()?;
+        let (inverses, range) = self.apply_steps(steps);
+        self.history.push_redo(inverses);
+        Some(range)
+    }
+
+    /// Makes again the edits that the last `undo()` reversed, and returns the smallest range that
+    /// covers all the text that they changed.
+    pub fn redo(&mut self) -> Option<Range<TextLocation>> {
+        assert!(!self.history.is_grouping(), "Document::redo(): Undo group is open!");
+        let steps = self.history.take_redo()?;
+        let (inverses, range) = self.apply_steps(steps);
+        self.history.push_undo(inverses);
+        Some(range)
+    }
+
+    #[inline]
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    #[inline]
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    #[inline]
+    pub fn clear_history(&mut self) {
+        self.history.clear()
+    }
+
+    /// Applies `steps` in order, and returns the steps that reverse them, in the order produced,
+    /// with the smallest range that covers the ranges that they all affected, as moved by the
+    /// steps after them.
+    fn apply_steps(&mut self, steps: Vec<EditStep>) -> (Vec<EditStep>, Range<TextLocation>) {
+        let mut inverses = Vec::with_capacity(steps.len());
+        let mut affected_range: Option<Range<TextLocation>> = None;
+        for step in steps {
+            let (inverse, range) = self.apply(step);
+            affected_range = Some(match affected_range {
+                None => range,
+                Some(affected_range) => {
+                    let start = inverse.move_location(affected_range.start, false);
+                    let end = inverse.move_location(affected_range.end, true);
+                    let start = if start < range.start { start } else { range.start };
+                    let end = if end > range.end { end } else { range.end };
+                    start..end
+                }
+            });
+            inverses.push(inverse)
+        }
+        let beginning = TextLocation::beginning();
+        (inverses, affected_range.unwrap_or(beginning..beginning))
+    }
+
+    /// Applies `step` without recording it, and returns the step that reverses it, with the
+    /// range of the text that it inserted.
+    fn apply(&mut self, step: EditStep) -> (EditStep, Range<TextLocation>) {
+        match step {
+            EditStep::Splice(range, fragment) => {
+                let (range, removed) = self.splice(range, fragment);
+                (EditStep::Splice(range.clone(), removed), range)
+            }
+            EditStep::InsertParagraph(index, style, attributed_string) => {
+                let range = TextLocation::new(index, 0)..
+                    TextLocation::new(index, attributed_string.char_len());
+                self.paragraphs.insert(index, Paragraph {
+                    attributed_string,
+                    style,
+                    revision: next_paragraph_revision(),
+                });
+                (EditStep::RemoveParagraph(index), range)
+            }
+            EditStep::RemoveParagraph(index) => {
+                let paragraph = self.paragraphs.remove(index);
+                let location = TextLocation::new(index, 0);
+                (EditStep::InsertParagraph(index, paragraph.style, paragraph.attributed_string),
+                 location..location)
+            }
+        }
+    }
+
+    /// Replaces `range` with `fragment` without recording it, and returns the range that the
+    /// fragment now occupies and what it replaced.
+    fn splice(&mut self, range: Range<TextLocation>, fragment: Fragment)
+              -> (Range<TextLocation>, Fragment) {
+        // Cut the text after the range off the last paragraph, and take out the paragraphs after
+        // the first.
+        let (start, end) = (range.start, range.end);
+        let tail = self.paragraphs[end.paragraph_index].attributed_string
+                                                       .split_off(end.character_index);
+        let removed_paragraphs: Vec<Paragraph> =
+            self.paragraphs.drain((start.paragraph_index + 1)..(end.paragraph_index + 1))
+                           .collect();
+
+        let removed = {
+            let first_paragraph = &mut self.paragraphs[start.paragraph_index];
+            let removed = Fragment {
+                first: first_paragraph.attributed_string.split_off(start.character_index),
+                rest: removed_paragraphs.into_iter().map(|paragraph| {
+                    (paragraph.style, paragraph.attributed_string)
+                }).collect(),
+            };
+            first_paragraph.attributed_string.append(fragment.first);
+            first_paragraph.revision = next_paragraph_revision();
+            removed
+        };
+
+        let mut paragraph_index = start.paragraph_index;
+        for (style, attributed_string) in fragment.rest {
+            paragraph_index += 1;
+            self.paragraphs.insert(paragraph_index, Paragraph {
+                attributed_string,
+                style,
+                revision: next_paragraph_revision(),
+            })
+        }
+
+        // An empty last paragraph takes the tail whole, so that it keeps the formats for typing.
+        let last_paragraph = &mut self.paragraphs[paragraph_index];
+        let end = TextLocation::new(paragraph_index, last_paragraph.char_len());
+        if end.character_index == 0 {
+            last_paragraph.attributed_string = tail
+        } else {
+            last_paragraph.attributed_string.append(tail)
+        }
+        (start..end, removed)
+    }
+
+    fn contains_location(&self, location: TextLocation) -> bool {
+        match self.paragraphs.get(location.paragraph_index) {
+            None => false,
+            Some(paragraph) => location.character_index <= paragraph.char_len(),
+        }
+    }
+}
+
+impl Default for Document {
+    #[inline]
+    fn default() -> Document {
+        Document::new()
+    }
 }
 
 pub struct Paragraph {
-    attributed_string: Mutex<CFMutableAttributedString>,
+    attributed_string: AttributedString,
     style: ParagraphStyle,
+    /// A number that's unique to this paragraph and changes whenever it's edited, so that layouts
+    /// can tell which paragraphs they're out of date for.
+    revision: usize,
 }
 
-unsafe impl Sync for Paragraph {}
-
 impl Paragraph {
     #[inline]
     pub fn new(style: ParagraphStyle) -> Paragraph {
-        let attributed_string = CFAttributedString::new(CFString::from(""), CFDictionary::new());
-        let mutable_attributed_string =
-            CFMutableAttributedString::from_attributed_string(attributed_string);
         Paragraph {
-            attributed_string: Mutex::new(mutable_attributed_string),
+            attributed_string: AttributedString::new(),
             style,
+            revision: next_paragraph_revision(),
         }
     }
 
     pub fn from_string(string: &str, style: ParagraphStyle) -> Paragraph {
-        let attributed_string = CFAttributedString::new(CFString::from(string),
-                                                        CFDictionary::new());
-        let mutable_attributed_string =
-            CFMutableAttributedString::from_attributed_string(attributed_string);
+        let mut attributed_string = AttributedString::new();
+        attributed_string.push_str(string, vec![]);
         Paragraph {
-            attributed_string: Mutex::new(mutable_attributed_string),
+            attributed_string,
             style,
+            revision: next_paragraph_revision(),
         }
     }
 
     #[inline]
     pub fn copy_string_in_range(&self, buffer: &mut String, range: Range<usize>) {
         buffer.extend(self.attributed_string
-                          .lock()
-                          .unwrap()
                           .string()
-                          .to_string()
                           .chars()
                           .skip(range.start)
                           .take(range.end - range.start))
@@ -192,59 +524,81 @@ impl Paragraph {
 
     #[inline]
     pub fn char_len(&self) -> usize {
-        self.attributed_string.lock().unwrap().string().char_len() as usize
+        self.attributed_string.char_len()
     }
 
     #[inline]
-    pub fn edit_at(&mut self, position: usize) -> ParagraphCursor {
-        let attributes = self.attributed_string
-                             .lock()
-                             .unwrap()
-                             .attributes_at(position as CFIndex)
-                             .0;
-        let format_stack = format::attributes_to_formatting(&attributes);
+    pub fn style(&self) -> &ParagraphStyle {
+        &self.style
+    }
+
+    #[inline]
+    pub fn style_mut(&mut self) -> &mut ParagraphStyle {
+        self.revision = next_paragraph_revision();
+        &mut self.style
+    }
+
+    #[inline]
+    pub fn edit_at(&mut self, position: usize) -> ParagraphCursor<'_> {
+        self.edit_range(position..position)
+    }
+
+    /// Returns a cursor whose text replaces the characters in `range` when it's committed, so
+    /// that committing an empty cursor deletes them. Its text starts out with the formats of the
+    /// first character replaced, or, if none are, those that typing there takes, like
+    /// `Document::replace_range()`.
+    pub fn edit_range(&mut self, range: Range<usize>) -> ParagraphCursor<'_> {
+        assert!(range.start <= range.end && range.end <= self.char_len(),
+                "Paragraph::edit_range(): Range out of bounds!");
+        let format_stack = if range.start == range.end {
+            self.attributed_string.insertion_formats_at(range.start).to_vec()
+        } else {
+            self.attributed_string.formats_at(range.start).to_vec()
+        };
         ParagraphCursor {
-            attributed_string: self.attributed_string.lock().unwrap(),
-            position: position,
-            buffer: CFMutableAttributedString::new(),
-            format_stack: format_stack,
+            attributed_string: &mut self.attributed_string,
+            revision: &mut self.revision,
+            range,
+            buffer: AttributedString::new(),
+            format_stack,
         }
     }
 
     pub fn word_range_at_char_index(&self, index: usize) -> Range<usize> {
-        let attributed_string = self.attributed_string.lock().unwrap();
-        let string = attributed_string.string();
-        let range = CFRange::init(0, string.char_len());
-        let tokenizer = CFStringTokenizer::new(string, range, kCFStringTokenizerUnitWord);
-        tokenizer.go_to_token_at_index(index as CFIndex);
-        let range = tokenizer.get_current_token_range();
-        (range.location as usize)..((range.location + range.length) as usize)
+        let chars: Vec<char> = self.attributed_string.string().chars().collect();
+        if index >= chars.len() || !chars[index].is_alphanumeric() {
+            return index..index
+        }
+        let mut start = index;
+        while start > 0 && chars[start - 1].is_alphanumeric() {
+            start -= 1
+        }
+        let mut end = index;
+        while end < chars.len() && chars[end].is_alphanumeric() {
+            end += 1
+        }
+        start..end
     }
 }
 
 pub struct ParagraphCursor<'a> {
-    attributed_string: MutexGuard<'a, CFMutableAttributedString>,
-    position: usize,
-    buffer: CFMutableAttributedString,
+    attributed_string: &'a mut AttributedString,
+    revision: &'a mut usize,
+    /// The characters that the text pushed replaces.
+    range: Range<usize>,
+    buffer: AttributedString,
     format_stack: Vec<Format>,
 }
 
 impl<'a> ParagraphCursor<'a> {
     pub fn commit(self) {
-        let range = CFRange::init(self.position as CFIndex, 0);
-        let buffer = self.buffer.as_attributed_string();
-        self.attributed_string.replace_attributed_string(range, buffer)
+        self.attributed_string.remove(self.range.clone());
+        self.attributed_string.insert(self.range.start, self.buffer);
+        *self.revision = next_paragraph_revision()
     }
 
     pub fn push_string(&mut self, string: &str) {
-        let mut attributes = CFMutableDictionary::new();
-        for format in &self.format_stack {
-            format.add_to_native_attributes(&mut attributes);
-        }
-        let attributes = attributes.as_dictionary();
-        let attributed_string = CFAttributedString::new(CFString::from(string), attributes);
-        let range = CFRange::init(self.buffer.string().char_len() as CFIndex, 0);
-        self.buffer.replace_attributed_string(range, attributed_string)
+        self.buffer.push_str(string, self.format_stack.clone())
     }
 
     pub fn push_format(&mut self, format: Format) {
@@ -260,156 +614,436 @@ impl<'a> ParagraphCursor<'a> {
     }
 }
 
-pub struct Framesetter {
-    framesetters: Vec<Mutex<ParagraphFramesetter>>,
-    document_style: DocumentStyle,
+pub struct Section {
+    frames: Vec<Frame>,
+    /// The bounds of each column that has frames, and the range of `frames` in it.
+    columns: Vec<(Rect<f32>, Range<usize>)>,
+    column_rules: Vec<Rect<f32>>,
+    writing_mode: WritingMode,
+    callbacks: Option<Arc<dyn LayoutCallbacks>>,
 }
 
-impl Framesetter {
-    pub fn new(document: &Document) -> Framesetter {
-        Framesetter {
-            framesetters: document.paragraphs().par_iter().map(|paragraph| {
-                let attributed_string = paragraph.attributed_string.lock().unwrap();
-                let attributed_string = attributed_string.as_attributed_string();
-                let framesetter = CTFramesetter::from_attributed_string(attributed_string.clone());
-                Mutex::new(ParagraphFramesetter {
-                    framesetter: framesetter,
-                    attributed_string: attributed_string,
-                    style: paragraph.style.clone(),
-                })
-            }).collect(),
-            document_style: document.style.clone(),
-        }
+impl Section {
+    #[inline]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
     }
 
-    pub fn layout_in_rect(&self, rect: &Rect<f32>, callbacks: Option<Box<LayoutCallbacks>>)
-                          -> Section {
-        eprintln!("document margins: {:?}", self.document_style.margin);
-
-        *LAYOUT_CALLBACKS.write().unwrap() = callbacks;
-
-        let rect = rect.inner_rect(self.document_style.margin);
+    /// Returns the rectangles to fill between adjacent columns, if the document style asks for a
+    /// column rule.
+    #[inline]
+    pub fn column_rules(&self) -> &[Rect<f32>] {
+        &self.column_rules
+    }
 
-        let mut frames: Vec<_> = self.framesetters.par_iter().map(|paragraph_framesetter| {
-            let paragraph_framesetter = paragraph_framesetter.lock().unwrap();
-            let range = CFRange::init(0, paragraph_framesetter.attributed_string
-                                                              .string()
-                                                              .char_len());
+    #[inline]
+    pub fn writing_mode(&self) -> WritingMode {
+        self.writing_mode
+    }
 
-            let mut rect = rect;
-            rect.size.width -= paragraph_framesetter.style.margin.horizontal();
+    pub fn frame_index_at_point(&self, point: &Point2D<f32>) -> Option<usize> {
+        // Columns divide at the middle of the gap between them. In vertical writing modes,
+        // they're stacked from top to bottom.
+        let column_index = self.columns.windows(2).take_while(|columns| {
+            let (first, second) = (&columns[0].0, &columns[1].0);
+            if self.writing_mode.is_vertical() {
+                point.y >= (first.max_y() + second.origin.y) * 0.5
+            } else {
+                point.x >= (first.max_x() + second.origin.x) * 0.5
+            }
+        }).count();
+        let frame_range = self.columns.get(column_index)?.1.clone();
+        self.frames[frame_range.clone()].binary_search_by(|frame| {
+            compare_bounds_and_point_in_block_direction(&frame.bounds(), point, self.writing_mode)
+        }).ok().map(|index| frame_range.start + index)
+    }
 
-            let origin = CGPoint::new(rect.origin.x as CGFloat, rect.origin.y as CGFloat);
-            let size = CGSize::new(rect.size.width as CGFloat, rect.size.height as CGFloat);
-            let path = CGPath::from_rect(CGRect::new(&origin, &size), None);
-            Frame {
-                frame: paragraph_framesetter.framesetter.create_frame(range, path, None),
-                style: paragraph_framesetter.style.clone(),
-                virtual_size: rect.size,
-                origin: Point2D::zero(),
+    /// Returns the rect of a caret at `location`, which spans the ascent and descent of its line
+    /// and has no extent along it, or None if the location isn't laid out in this section. Where
+    /// a line wraps, `affinity` picks the line. A caret in an empty paragraph is at the start of
+    /// its frame.
+    pub fn caret_rect(&self, location: TextLocation, affinity: Affinity) -> Option<Rect<f32>> {
+        let frames = self.frames.iter().filter(|frame| {
+            frame.paragraph_index() == location.paragraph_index
+        });
+        let mut first_frame = None;
+        let mut caret_line = None;
+        for frame in frames {
+            first_frame = first_frame.or(Some(frame));
+            for line in frame.lines() {
+                let char_range = line.char_range();
+                if char_range.start > location.character_index ||
+                        char_range.end < location.character_index {
+                    continue
+                }
+                let is_upstream = caret_line.is_some() && affinity == Affinity::Upstream;
+                if !is_upstream {
+                    caret_line = Some(line)
+                }
             }
-        }).collect();
+        }
 
-        // TODO(pcwalton): Vertical writing direction.
-        let mut origin = rect.origin;
-        for mut frame in &mut frames {
-            origin.y += frame.style.margin.top;
-            frame.origin = origin + Vector2D::new(frame.style.margin.left, 0.0);
-            origin.y += frame.height();
-            origin.y += frame.style.margin.bottom;
+        match (caret_line, first_frame) {
+            (Some(line), _) => {
+                let offset = line.inline_position_for_char_index(location.character_index);
+                Some(line_span_rect(&line, offset..offset, self.writing_mode))
+            }
+            (None, Some(frame)) if frame.char_len() == 0 => Some(frame.empty_caret_rect()),
+            (None, _) => None,
         }
+    }
+
+    /// Returns the rects to highlight to show `range` as selected: one for each stretch of
+    /// adjacent selected text on each line, in the order of the lines. Text that changes
+    /// direction can take more than one rect on a line.
+    pub fn selection_rects(&self, range: Range<TextLocation>) -> Vec<Rect<f32>> {
+        let mut rects = vec![];
+        for frame in &self.frames {
+            let paragraph_index = frame.paragraph_index();
+            if paragraph_index < range.start.paragraph_index ||
+                    paragraph_index > range.end.paragraph_index {
+                continue
+            }
+            let char_start = if paragraph_index == range.start.paragraph_index {
+                range.start.character_index
+            } else {
+                0
+            };
+            let char_end = if paragraph_index == range.end.paragraph_index {
+                range.end.character_index
+            } else {
+                usize::MAX
+            };
 
-        Section {
-            frames,
+            for line in frame.lines() {
+                let line_range = line.char_range();
+                let (start, end) = (line_range.start.max(char_start), line_range.end.min(char_end));
+                if start >= end {
+                    continue
+                }
+                for extent in line.inline_extents_for_char_range(start..end) {
+                    rects.push(line_span_rect(&line, extent, self.writing_mode))
+                }
+            }
         }
+        rects
     }
 }
 
-struct ParagraphFramesetter {
-    framesetter: CTFramesetter,
-    attributed_string: CFAttributedString,
-    style: ParagraphStyle,
+/// A layout of a document in a rect, as `Framesetter::layout_in_rect()` makes, that's kept up to
+/// date as the document is edited.
+///
+/// `update()` shapes and lays out again only the paragraphs that changed since the last layout,
+/// and moves the frames after them. With more than one column, frames can move between columns,
+/// so every paragraph is placed again, though unchanged ones aren't shaped again.
+pub struct Layout {
+    framesetter: Framesetter,
+    rect: Rect<f32>,
+    callbacks: Option<Arc<dyn LayoutCallbacks>>,
+    section: Section,
+    document_style: DocumentStyle,
+    /// The revision of each paragraph as of the last layout.
+    revisions: Vec<usize>,
 }
 
-pub struct Section {
-    frames: Vec<Frame>,
-}
+impl Layout {
+    pub fn new(document: &Document, rect: &Rect<f32>, callbacks: Option<Box<dyn LayoutCallbacks>>)
+               -> Layout {
+        let framesetter = Framesetter::new(document);
+        let callbacks: Option<Arc<dyn LayoutCallbacks>> = callbacks.map(Arc::from);
+        let section = flow_section(&framesetter, document, rect, callbacks.clone());
+        Layout {
+            framesetter,
+            rect: *rect,
+            callbacks,
+            section,
+            document_style: document.style.clone(),
+            revisions: paragraph_revisions(document),
+        }
+    }
 
-impl Section {
     #[inline]
-    pub fn frames(&self) -> &[Frame] {
-        &self.frames
+    pub fn section(&self) -> &Section {
+        &self.section
     }
 
-    pub fn frame_index_at_point(&self, point: &Point2D<f32>) -> Option<usize> {
-        self.frames.binary_search_by(|frame| {
-            compare_bounds_and_point_vertically(&frame.bounds(), &point)
-        }).ok()
+    /// Brings this layout up to date with `document`, which must be the document it was made
+    /// from or an edited version of it. Returns the range of paragraphs that were laid out again.
+    pub fn update(&mut self, document: &Document) -> Range<usize> {
+        let revisions = paragraph_revisions(document);
+        let paragraph_count = revisions.len();
+        self.framesetter.update(document);
+
+        if document.style != self.document_style || document.style.column_count > 1 {
+            self.section = flow_section(&self.framesetter, document, &self.rect,
+                                        self.callbacks.clone());
+            self.document_style = document.style.clone();
+            self.revisions = revisions;
+            return 0..paragraph_count
+        }
+
+        // Find the paragraphs that changed, between the unchanged ones at each end.
+        let unchanged_start = self.revisions.iter().zip(revisions.iter()).take_while(|pair| {
+            pair.0 == pair.1
+        }).count();
+        let old_rest = self.revisions[unchanged_start..].iter().rev();
+        let new_rest = revisions[unchanged_start..].iter().rev();
+        let unchanged_end = old_rest.zip(new_rest).take_while(|pair| pair.0 == pair.1).count();
+        let changed_range = unchanged_start..(paragraph_count - unchanged_end);
+        let old_changed_count = self.revisions.len() - unchanged_start - unchanged_end;
+
+        // The only column has no bottom, so each paragraph has exactly one frame. Keep the frames
+        // of unchanged paragraphs, moving those after the changed ones.
+        let rect = self.rect.inner_rect(document.style.margin);
+        let mut flow = ColumnFlow::new(&rect, &document.style, false, self.callbacks.clone());
+        let mut old_frames = mem::take(&mut self.section.frames).into_iter();
+        let paragraphs = &document.paragraphs;
+        for (paragraph, frame) in paragraphs[..unchanged_start].iter().zip(old_frames.by_ref()) {
+            flow.place_frame(frame, &paragraph.style)
+        }
+        self.framesetter.flow_paragraphs(changed_range.clone(), &mut flow);
+        let old_frames = old_frames.skip(old_changed_count);
+        for (paragraph, frame) in paragraphs[changed_range.end..].iter().zip(old_frames) {
+            flow.place_frame(frame, &paragraph.style)
+        }
+
+        self.section = flow.finish_section();
+        self.revisions = revisions;
+        changed_range
     }
 }
 
-pub struct Frame {
-    frame: CTFrame,
-    style: ParagraphStyle,
-    virtual_size: Size2D<f32>,
-    origin: Point2D<f32>,
+/// A layout of a document in one column of a rect that lays out only the paragraphs in view, for
+/// documents too large to lay out all at once.
+///
+/// The sizes of paragraphs that haven't been laid out yet are estimated from those that have.
+/// As more paragraphs are measured, the estimates of the rest are refined, which moves the
+/// paragraphs after them; `layout_viewport()` corrects the viewport so that what's in view stays
+/// put.
+pub struct LazyLayout {
+    framesetter: Framesetter,
+    /// The rect inset by the document margin, in page coordinates.
+    rect: Rect<f32>,
+    document_style: DocumentStyle,
+    callbacks: Option<Arc<dyn LayoutCallbacks>>,
+    section: Section,
+    extents: Vec<ParagraphExtent>,
+    measured_line_count: usize,
+    measured_char_count: usize,
+    /// The total size of the lines measured so far, in the direction that lines stack in.
+    measured_line_size: f32,
 }
 
-impl Frame {
-    pub fn char_len(&self) -> usize {
-        self.frame.get_string_range().length as usize
-    }
-
-    pub fn lines(&self) -> Vec<Line> {
-        let lines = self.frame.lines();
-        let mut line_origins = vec![CG_ZERO_POINT; lines.len() as usize];
-        self.frame.get_line_origins(0, &mut line_origins);
-        let virtual_height = self.virtual_size.height;
-        let frame_origin = self.origin;
-        lines.into_iter().zip(line_origins.into_iter()).map(|(line, line_origin)| {
-            Line {
-                line: (*line).clone(),
-                origin: Point2D::new(frame_origin.x + line_origin.x as f32,
-                                     frame_origin.y + virtual_height - line_origin.y as f32),
-            }
-        }).collect()
+/// What a lazy layout knows about the size of a paragraph.
+struct ParagraphExtent {
+    char_len: usize,
+    /// The space before and after the paragraph.
+    margin: f32,
+    /// The size of the paragraph's lines in the direction that they stack in, once it's been
+    /// laid out.
+    measured_size: Option<f32>,
+}
+
+/// The size of lines in the direction that they stack in, and the advance of characters, that
+/// lazy layouts estimate paragraphs with before any have been measured.
+const ESTIMATED_LINE_SIZE: f32 = 20.0;
+const ESTIMATED_CHAR_ADVANCE: f32 = 8.0;
+
+impl LazyLayout {
+    /// Prepares to lay out `document` in `rect`, inset by the document margin, without laying out
+    /// anything yet. The document's column count is ignored.
+    pub fn new(document: &Document,
+               rect: &Rect<f32>,
+               callbacks: Option<Box<dyn LayoutCallbacks>>)
+               -> LazyLayout {
+        let document_style = DocumentStyle { column_count: 1, ..document.style.clone() };
+        let callbacks: Option<Arc<dyn LayoutCallbacks>> = callbacks.map(Arc::from);
+        let rect = rect.inner_rect(document.style.margin);
+        let section = ColumnFlow::new(&rect, &document_style, false, callbacks.clone())
+            .finish_section();
+        LazyLayout {
+            framesetter: Framesetter::new(document),
+            rect,
+            document_style,
+            callbacks,
+            section,
+            extents: document.paragraphs.iter().map(|paragraph| {
+                ParagraphExtent {
+                    char_len: paragraph.char_len(),
+                    margin: paragraph.style.margin.vertical(),
+                    measured_size: None,
+                }
+            }).collect(),
+            measured_line_count: 0,
+            measured_char_count: 0,
+            measured_line_size: 0.0,
+        }
     }
 
+    /// Returns the frames of the paragraphs laid out by the last call to `layout_viewport()`.
     #[inline]
-    pub fn bounds(&self) -> Rect<f32> {
-        Rect::new(self.origin, Size2D::new(self.virtual_size.width, self.height()))
+    pub fn section(&self) -> &Section {
+        &self.section
     }
 
-    pub fn height(&self) -> f32 {
-        let lines = self.frame.lines();
-        let line_count = lines.len();
-        if line_count == 0 {
-            return 0.0
+    /// Returns the estimated size of the whole document in the direction that lines stack in: its
+    /// height, or its width in vertical writing modes.
+    pub fn estimated_block_size(&self) -> f32 {
+        self.extents.iter().map(|extent| self.estimated_size(extent)).sum()
+    }
+
+    /// Lays out the paragraphs that `viewport` overlaps, in place of those laid out before.
+    ///
+    /// Measuring them refines the estimated sizes of the paragraphs not yet laid out, including
+    /// any before the viewport. Returns `viewport` moved by as much as that moved the paragraph at
+    /// its start, so that scrolling to the returned viewport keeps the same text in view.
+    pub fn layout_viewport(&mut self, viewport: &Rect<f32>) -> Rect<f32> {
+        let writing_mode = self.document_style.writing_mode;
+        let block_range = writing_mode.block_range(&self.rect, viewport);
+        let flow_start = writing_mode.flow_rect(&self.rect).origin.y;
+        let mut flow = ColumnFlow::new(&self.rect,
+                                       &self.document_style,
+                                       false,
+                                       self.callbacks.clone());
+        if self.extents.is_empty() {
+            self.section = flow.finish_section();
+            return *viewport
         }
 
-        let last_line = lines.get(line_count - 1).unwrap();
-        let mut line_origins = [CG_ZERO_POINT];
-        self.frame.get_line_origins(line_count - 1, &mut line_origins);
-        last_line.typographic_bounds().descent as f32 - line_origins[0].y as f32 +
-            self.virtual_size.height
+        // Anchor the viewport to the paragraph at its start.
+        let anchor_index = self.paragraph_index_at(block_range.start - flow_start);
+        let old_anchor_offset = self.paragraph_offset(anchor_index);
+        let needed_size = block_range.end - flow_start - old_anchor_offset;
+
+        // Lay out paragraphs from the anchor until the viewport is full, measuring them.
+        let mut paragraph_end = anchor_index;
+        while paragraph_end < self.extents.len() &&
+                (paragraph_end == anchor_index || flow.y - flow_start < needed_size) {
+            let (y, frame_start) = (flow.y, flow.frames.len());
+            self.framesetter.flow_paragraphs(paragraph_end..(paragraph_end + 1), &mut flow);
+            let line_count = flow.frames[frame_start..].iter().map(|frame| {
+                frame.lines().len()
+            }).sum();
+            self.measure(paragraph_end, flow.y - y, line_count);
+            paragraph_end += 1
+        }
+        let frames = flow.finish_section().frames;
+
+        // Move the frames to where the refined estimates put the anchor.
+        let anchor_offset = self.paragraph_offset(anchor_index);
+        let mut flow = ColumnFlow::new(&self.rect,
+                                       &self.document_style,
+                                       false,
+                                       self.callbacks.clone());
+        flow.y = flow_start + anchor_offset;
+        for frame in frames {
+            let style = frame.style().clone();
+            flow.place_frame(frame, &style)
+        }
+        self.section = flow.finish_section();
+
+        viewport.translate(&writing_mode.block_vector(anchor_offset - old_anchor_offset))
     }
 
-    pub fn line_index_at_point(&self, point: &Point2D<f32>) -> Option<usize> {
-        self.lines().binary_search_by(|line| {
-            compare_bounds_and_point_vertically(&line.typographic_bounding_rect(), &point)
-        }).ok()
+    /// Returns the index of the paragraph at `offset` from the start of the flow, in the direction
+    /// that lines stack in, or the last paragraph if it's past the end.
+    fn paragraph_index_at(&self, offset: f32) -> usize {
+        let mut start = 0.0;
+        for (index, extent) in self.extents.iter().enumerate() {
+            start += self.estimated_size(extent);
+            if offset < start {
+                return index
+            }
+        }
+        self.extents.len() - 1
     }
 
-    #[inline]
-    pub fn style(&self) -> &ParagraphStyle {
-        &self.style
+    /// Returns the distance from the start of the flow to the start of paragraph
+    /// `paragraph_index`.
+    fn paragraph_offset(&self, paragraph_index: usize) -> f32 {
+        self.extents[..paragraph_index].iter().map(|extent| self.estimated_size(extent)).sum()
+    }
+
+    /// Returns the size of the paragraph with `extent`, including its margins, in the direction
+    /// that lines stack in: exactly if it's been measured, and estimated if not.
+    fn estimated_size(&self, extent: &ParagraphExtent) -> f32 {
+        if let Some(measured_size) = extent.measured_size {
+            return extent.margin + measured_size
+        }
+        let (line_size, chars_per_line) = if self.measured_line_count == 0 {
+            let column_width = self.document_style.writing_mode.flow_rect(&self.rect).size.width;
+            (ESTIMATED_LINE_SIZE, column_width / ESTIMATED_CHAR_ADVANCE)
+        } else {
+            let line_count = self.measured_line_count as f32;
+            (self.measured_line_size / line_count, self.measured_char_count as f32 / line_count)
+        };
+        let line_count = (extent.char_len as f32 / chars_per_line.max(1.0)).ceil().max(1.0);
+        extent.margin + line_count * line_size
+    }
+
+    /// Records that paragraph `paragraph_index` took up `size`, including its margins, in
+    /// `line_count` lines.
+    fn measure(&mut self, paragraph_index: usize, size: f32, line_count: usize) {
+        let extent = &mut self.extents[paragraph_index];
+        let line_size = size - extent.margin;
+        if extent.measured_size.is_none() && line_count > 0 {
+            self.measured_line_count += line_count;
+            self.measured_char_count += extent.char_len;
+            self.measured_line_size += line_size;
+        }
+        extent.measured_size = Some(line_size)
     }
 }
 
 #[derive(Clone, PartialEq)]
 pub struct ParagraphStyle {
     pub content: ParagraphContent,
+    /// The space around the paragraph, relative to the flow of the text: in vertical writing
+    /// modes, the top margin is on the side that lines stack from, and the left one is at the top.
     pub margin: SideOffsets2D<f32>,
+    /// How the glyphs of vertical lines are turned. Horizontal lines are always upright.
+    pub text_orientation: TextOrientation,
+    /// The direction that lines start in, and that runs of text are ordered by.
+    pub base_direction: BaseDirection,
+    pub alignment: TextAlignment,
+    /// The alignment of the last line of a justified paragraph, and of lines that end in a hard
+    /// line break. `TextAlignment::Justify` here justifies those lines too.
+    pub last_line_alignment: TextAlignment,
+    pub line_height: LineHeight,
+    /// The smallest line height, whatever `line_height` and the fonts ask for.
+    pub min_line_height: f32,
+    /// The largest line height, whatever `line_height` and the fonts ask for.
+    pub max_line_height: f32,
+    /// The indent of the first line, from the margin that lines start at: the right one in
+    /// right-to-left paragraphs.
+    pub first_line_indent: f32,
+    /// The indent of every line but the first, from the margin that lines start at. Set this
+    /// greater than `first_line_indent` for a hanging indent.
+    pub hanging_indent: f32,
+    /// Where tab characters advance to, measured from the left margin. They needn't be sorted.
+    pub tab_stops: Vec<TabStop>,
+    /// The spacing of the left-aligned tab stops that follow the last of `tab_stops`.
+    pub default_tab_interval: f32,
+    /// The BCP 47 tag of the language of this paragraph's text, e.g. `en-US`, which picks the
+    /// hyphenation patterns.
+    pub language: Option<String>,
+    /// Whether to hyphenate words at the ends of lines, with the patterns registered for
+    /// `language`. Only the portable backend hyphenates; the Core Text backend ignores this,
+    /// `language` and `hyphenation_zone`.
+    pub hyphenate: bool,
+    /// How much space a line may leave unused at its end before the next word is hyphenated to
+    /// fill it. Only the portable backend honors this.
+    pub hyphenation_zone: f32,
+    /// Keeps the end of this paragraph in the same page or column as the start of the next.
+    pub keep_with_next: bool,
+    /// Keeps all the lines of this paragraph in one page or column.
+    pub keep_together: bool,
+    /// The fewest lines of this paragraph to leave at the bottom of a page or column when it's
+    /// split.
+    pub min_orphan_lines: usize,
+    /// The fewest lines of this paragraph to carry over to the top of the next page or column
+    /// when it's split.
+    pub min_widow_lines: usize,
 }
 
 impl ParagraphStyle {
@@ -417,7 +1051,45 @@ impl ParagraphStyle {
     pub fn new(content: ParagraphContent) -> ParagraphStyle {
         ParagraphStyle {
             content,
-            margin: SideOffsets2D::zero(),
+            ..ParagraphStyle::default()
+        }
+    }
+
+    /// Returns the advance of a tab at `x` from the left margin, given the width of the text
+    /// that follows it up to the next tab or line break, and the width of that text up to its
+    /// first decimal point.
+    #[cfg(pilcrow_portable)]
+    pub(crate) fn tab_advance(&self, x: f32, text_width: f32, decimal_width: f32) -> f32 {
+        let tab_stop = self.tab_stops.iter().filter(|tab_stop| {
+            tab_stop.position > x
+        }).min_by(|a, b| a.position.partial_cmp(&b.position).unwrap_or(Ordering::Equal));
+        let stop_x = match tab_stop {
+            Some(tab_stop) => {
+                match tab_stop.alignment {
+                    TabAlignment::Left => tab_stop.position,
+                    TabAlignment::Right => tab_stop.position - text_width,
+                    TabAlignment::Center => tab_stop.position - text_width * 0.5,
+                    TabAlignment::Decimal => tab_stop.position - decimal_width,
+                }
+            }
+            None if self.default_tab_interval > 0.0 => {
+                let interval = self.default_tab_interval;
+                ((x / interval).floor() + 1.0) * interval
+            }
+            None => x,
+        };
+        (stop_x - x).max(0.0)
+    }
+
+    /// Returns where the first line of an empty paragraph of this style starts, from the left of
+    /// a frame `width` wide, given the direction that the paragraph runs in.
+    pub(crate) fn empty_line_offset(&self, direction: TextDirection, width: f32) -> f32 {
+        let indent = self.first_line_indent;
+        let alignment = TextAlignment::for_line(self, true);
+        let offset = (width - indent).max(0.0) * alignment.flush_factor(direction);
+        match direction {
+            TextDirection::LeftToRight => offset + indent,
+            TextDirection::RightToLeft => offset,
         }
     }
 }
@@ -428,6 +1100,24 @@ impl Default for ParagraphStyle {
         ParagraphStyle {
             content: ParagraphContent::Text,
             margin: SideOffsets2D::zero(),
+            text_orientation: TextOrientation::Mixed,
+            base_direction: BaseDirection::Auto,
+            alignment: TextAlignment::Start,
+            last_line_alignment: TextAlignment::Start,
+            line_height: LineHeight::Multiple(1.0),
+            min_line_height: 0.0,
+            max_line_height: f32::INFINITY,
+            first_line_indent: 0.0,
+            hanging_indent: 0.0,
+            tab_stops: vec![],
+            default_tab_interval: 36.0,
+            language: None,
+            hyphenate: false,
+            hyphenation_zone: 0.0,
+            keep_with_next: false,
+            keep_together: false,
+            min_orphan_lines: 1,
+            min_widow_lines: 1,
         }
     }
 }
@@ -438,113 +1128,274 @@ pub enum ParagraphContent {
     Rule,
 }
 
-#[derive(Clone, PartialEq)]
-pub struct DocumentStyle {
-    pub margin: SideOffsets2D<f32>,
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TabStop {
+    pub position: f32,
+    pub alignment: TabAlignment,
 }
 
-impl Default for DocumentStyle {
+impl TabStop {
     #[inline]
-    fn default() -> DocumentStyle {
-        DocumentStyle {
-            margin: SideOffsets2D::zero(),
+    pub fn new(position: f32, alignment: TabAlignment) -> TabStop {
+        TabStop {
+            position,
+            alignment,
         }
     }
 }
 
-pub struct Line {
-    line: CTLine,
-    pub origin: Point2D<f32>,
+/// Which part of the text after a tab lines up with its tab stop.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum TabAlignment {
+    Left = 0,
+    Right,
+    Center,
+    /// Lines up the first `.`, or the right edge if there isn't one.
+    Decimal,
 }
 
-impl Line {
-    pub fn runs(&self) -> Vec<Run> {
-        self.line.glyph_runs().into_iter().map(|run| {
-            Run {
-                run: (*run).clone(),
-            }
-        }).collect()
-    }
+/// The distance between the tops of consecutive lines of a paragraph. Extra space is split
+/// evenly above and below the text of each line.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineHeight {
+    /// A multiple of the height that the fonts of each line ask for, including their leading.
+    Multiple(f32),
+    /// A fixed height, in points.
+    Absolute(f32),
+}
 
-    #[inline]
-    pub fn char_range(&self) -> Range<usize> {
-        let range = self.line.string_range();
-        (range.location as usize)..((range.location + range.length) as usize)
+impl LineHeight {
+    /// Returns the height of a line whose fonts ask for `natural_height`, within the limits of
+    /// `style`.
+    #[cfg(pilcrow_portable)]
+    pub(crate) fn resolve(style: &ParagraphStyle, natural_height: f32) -> f32 {
+        let height = match style.line_height {
+            LineHeight::Multiple(multiple) => natural_height * multiple,
+            LineHeight::Absolute(height) => height,
+        };
+        height.min(style.max_line_height).max(style.min_line_height)
     }
+}
 
-    pub fn typographic_bounding_rect(&self) -> Rect<f32> {
-        let typographic_bounds = self.typographic_bounds();
-        Rect::new(Point2D::new(self.origin.x, self.origin.y - typographic_bounds.ascent),
-                  Size2D::new(typographic_bounds.width,
-                              typographic_bounds.ascent + typographic_bounds.descent))
-    }
+/// How lines are placed between the margins of a paragraph.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum TextAlignment {
+    Start = 0,
+    End,
+    Center,
+    /// Stretches the spaces in each line so that it fills the width of the paragraph.
+    Justify,
+}
 
+impl TextAlignment {
+    /// Returns the alignment of a line, given whether it's the last line of the paragraph or
+    /// ends in a hard line break.
     #[inline]
-    pub fn typographic_bounds(&self) -> TypographicBounds {
-        let typographic_bounds = self.line.typographic_bounds();
-        TypographicBounds {
-            width: typographic_bounds.width as f32,
-            ascent: typographic_bounds.ascent as f32,
-            descent: typographic_bounds.descent as f32,
-            leading: typographic_bounds.leading as f32,
+    pub(crate) fn for_line(style: &ParagraphStyle, is_last_line: bool) -> TextAlignment {
+        if style.alignment == TextAlignment::Justify && is_last_line {
+            style.last_line_alignment
+        } else {
+            style.alignment
         }
     }
 
+    /// Returns the fraction of the leftover space in a line to put to its left, in a paragraph
+    /// whose lines start on the side that `direction` starts from.
     #[inline]
-    pub fn char_index_for_position(&self, position: &Point2D<f32>) -> Option<usize> {
-        let position = CGPoint::new(position.x as CGFloat, position.y as CGFloat);
-        match self.line.get_string_index_for_position(position) {
-            kCFNotFound => None,
-            index => Some(index as usize),
+    pub(crate) fn flush_factor(self, direction: TextDirection) -> f32 {
+        let start_factor = match self {
+            TextAlignment::Start | TextAlignment::Justify => 0.0,
+            TextAlignment::End => 1.0,
+            TextAlignment::Center => 0.5,
+        };
+        match direction {
+            TextDirection::LeftToRight => start_factor,
+            TextDirection::RightToLeft => 1.0 - start_factor,
         }
     }
+}
 
-    #[inline]
-    pub fn inline_position_for_char_index(&self, index: usize) -> f32 {
-        self.line.get_offset_for_string_index(index as CFIndex).0 as f32
-    }
+/// The direction of a paragraph, which decides which side its lines start on and, by the Unicode
+/// bidirectional algorithm, the order of runs of text in the other direction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum BaseDirection {
+    /// Takes the direction of the first strong directional character of the paragraph, or
+    /// left-to-right if there is none.
+    Auto = 0,
+    LeftToRight,
+    RightToLeft,
 }
 
-pub struct Run {
-    run: CTRun,
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum TextDirection {
+    LeftToRight = 0,
+    RightToLeft,
 }
 
-impl Run {
+/// The direction that lines run in, and the direction that lines, paragraphs and columns stack
+/// in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum WritingMode {
+    /// Horizontal lines stacked from top to bottom, in columns from left to right.
+    HorizontalTb = 0,
+    /// Vertical lines stacked from right to left, in columns from top to bottom, as in Japanese.
+    VerticalRl,
+    /// Vertical lines stacked from left to right, in columns from top to bottom, as in Mongolian.
+    VerticalLr,
+}
+
+impl WritingMode {
     #[inline]
-    pub fn glyph_count(&self) -> usize {
-        self.run.glyph_count() as usize
+    pub fn is_vertical(self) -> bool {
+        self != WritingMode::HorizontalTb
     }
 
-    pub fn glyphs(&self) -> Vec<Glyph> {
-        let mut glyphs = vec![0; self.glyph_count()];
-        self.run.get_glyphs(0, &mut glyphs);
-        glyphs
+    /// Returns the area of `rect` in flow coordinates, in which lines run along X and stack down
+    /// Y. The flow coordinates of horizontal text are page coordinates.
+    pub(crate) fn flow_rect(self, rect: &Rect<f32>) -> Rect<f32> {
+        match self {
+            WritingMode::HorizontalTb => *rect,
+            WritingMode::VerticalRl | WritingMode::VerticalLr => {
+                Rect::new(Point2D::zero(), Size2D::new(rect.size.height, rect.size.width))
+            }
+        }
     }
 
-    pub fn positions(&self) -> Vec<Point2D<f32>> {
-        let mut positions = vec![CG_ZERO_POINT; self.glyph_count()];
-        self.run.get_positions(0, &mut positions);
-        positions.into_iter().map(|p| Point2D::new(p.x as f32, p.y as f32)).collect()
+    /// Converts `point` from the flow coordinates of `rect` to page coordinates.
+    pub(crate) fn page_point(self, rect: &Rect<f32>, point: &Point2D<f32>) -> Point2D<f32> {
+        match self {
+            WritingMode::HorizontalTb => *point,
+            WritingMode::VerticalRl => {
+                Point2D::new(rect.max_x() - point.y, rect.origin.y + point.x)
+            }
+            WritingMode::VerticalLr => {
+                Point2D::new(rect.origin.x + point.y, rect.origin.y + point.x)
+            }
+        }
     }
 
-    #[inline]
-    pub fn char_range(&self) -> Range<usize> {
-        let range = self.run.get_string_range();
-        (range.start as usize)..(range.end as usize)
+    /// Converts `flow_rect` from the flow coordinates of `rect` to page coordinates.
+    pub(crate) fn page_rect(self, rect: &Rect<f32>, flow_rect: &Rect<f32>) -> Rect<f32> {
+        let (a, b) = (self.page_point(rect, &flow_rect.origin),
+                      self.page_point(rect, &flow_rect.bottom_right()));
+        Rect::new(Point2D::new(a.x.min(b.x), a.y.min(b.y)),
+                  Size2D::new((a.x - b.x).abs(), (a.y - b.y).abs()))
+    }
+
+    /// Returns the extent of `page_rect` in the direction that lines stack in, in the flow
+    /// coordinates of `rect`.
+    pub(crate) fn block_range(self, rect: &Rect<f32>, page_rect: &Rect<f32>) -> Range<f32> {
+        match self {
+            WritingMode::HorizontalTb => page_rect.origin.y..page_rect.max_y(),
+            WritingMode::VerticalRl => {
+                (rect.max_x() - page_rect.max_x())..(rect.max_x() - page_rect.origin.x)
+            }
+            WritingMode::VerticalLr => {
+                (page_rect.origin.x - rect.origin.x)..(page_rect.max_x() - rect.origin.x)
+            }
+        }
+    }
+
+    /// Returns the total of `margin` on the sides that lines start and end at.
+    pub(crate) fn inline_margin(self, margin: &SideOffsets2D<f32>) -> f32 {
+        if self.is_vertical() {
+            margin.vertical()
+        } else {
+            margin.horizontal()
+        }
     }
 
-    pub fn formatting(&self) -> Vec<Format> {
-        format::attributes_to_formatting(&self.run.attributes())
+    /// Returns the total of `margin` on the sides that lines stack from and towards.
+    pub(crate) fn block_margin(self, margin: &SideOffsets2D<f32>) -> f32 {
+        if self.is_vertical() {
+            margin.horizontal()
+        } else {
+            margin.vertical()
+        }
+    }
+
+    /// Returns the vector in page coordinates that moves `distance` in the direction that lines
+    /// stack in.
+    pub(crate) fn block_vector(self, distance: f32) -> Vector2D<f32> {
+        match self {
+            WritingMode::HorizontalTb => Vector2D::new(0.0, distance),
+            WritingMode::VerticalRl => Vector2D::new(-distance, 0.0),
+            WritingMode::VerticalLr => Vector2D::new(distance, 0.0),
+        }
     }
+}
 
+/// How the glyphs of vertical lines are turned.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum TextOrientation {
+    /// Upright or rotated by each character's Vertical_Orientation property (UAX #50), so that
+    /// CJK text is upright and Latin text lies on its side.
+    Mixed = 0,
+    Upright,
+    /// Rotated 90° clockwise, as if horizontal lines were turned.
+    Sideways,
+}
+
+impl TextOrientation {
+    /// Returns how to turn `ch` in a vertical line.
+    pub(crate) fn glyph_orientation(self, ch: char) -> GlyphOrientation {
+        match self {
+            TextOrientation::Upright => GlyphOrientation::Upright,
+            TextOrientation::Sideways => GlyphOrientation::Rotated,
+            TextOrientation::Mixed => match unicode_vo::char_orientation(ch) {
+                // Characters that have vertical alternates are turned where the font has none.
+                Orientation::Upright | Orientation::TransformedOrUpright => {
+                    GlyphOrientation::Upright
+                }
+                Orientation::Rotated | Orientation::TransformedOrRotated => {
+                    GlyphOrientation::Rotated
+                }
+            },
+        }
+    }
+}
+
+/// How the glyphs of a run are turned on the page.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum GlyphOrientation {
+    Upright = 0,
+    /// Rotated 90° clockwise, so that the glyphs' Y axis points right.
+    Rotated,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct DocumentStyle {
+    pub margin: SideOffsets2D<f32>,
+    /// Fonts to try, in order, for characters that the font from the format stack lacks glyphs
+    /// for.
+    pub fallback_fonts: Vec<Font>,
+    /// The number of equal-width columns that paragraphs flow through, from left to right, or
+    /// from top to bottom in vertical writing modes.
+    pub column_count: usize,
+    /// The space between adjacent columns.
+    pub column_gap: f32,
+    /// Whether to draw a rule down the middle of the gap between adjacent columns.
+    pub column_rule: bool,
+    pub writing_mode: WritingMode,
+}
+
+impl Default for DocumentStyle {
     #[inline]
-    pub fn typographic_bounds(&self) -> TypographicBounds {
-        let typographic_bounds = self.run.typographic_bounds(0..(self.glyph_count() as CFIndex));
-        TypographicBounds {
-            width: typographic_bounds.width as f32,
-            ascent: typographic_bounds.ascent as f32,
-            descent: typographic_bounds.descent as f32,
-            leading: typographic_bounds.leading as f32,
+    fn default() -> DocumentStyle {
+        DocumentStyle {
+            margin: SideOffsets2D::zero(),
+            fallback_fonts: vec![],
+            column_count: 1,
+            column_gap: 0.0,
+            column_rule: false,
+            writing_mode: WritingMode::HorizontalTb,
         }
     }
 }
@@ -571,6 +1422,59 @@ impl TextLocation {
     }
 }
 
+/// Which line a location where a line wraps is on: the end of the line before the wrap, or the
+/// start of the line after it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub enum Affinity {
+    /// With the character before: at the end of the earlier line.
+    Upstream = 0,
+    /// With the character after: at the start of the later line.
+    Downstream,
+}
+
+/// A selection in a document, from where it was started to where it was extended to. When the
+/// two are the same, it's a caret.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub struct Selection {
+    pub anchor: TextLocation,
+    pub focus: TextLocation,
+    /// The line that the focus is on, if it's where a line wraps.
+    pub affinity: Affinity,
+}
+
+impl Selection {
+    #[inline]
+    pub fn new(anchor: TextLocation, focus: TextLocation, affinity: Affinity) -> Selection {
+        Selection {
+            anchor,
+            focus,
+            affinity,
+        }
+    }
+
+    #[inline]
+    pub fn caret(location: TextLocation, affinity: Affinity) -> Selection {
+        Selection::new(location, location, affinity)
+    }
+
+    #[inline]
+    pub fn is_caret(&self) -> bool {
+        self.anchor == self.focus
+    }
+
+    /// Returns the selected text, from whichever of the anchor and focus comes first.
+    #[inline]
+    pub fn range(&self) -> Range<TextLocation> {
+        if self.focus < self.anchor {
+            self.focus..self.anchor
+        } else {
+            self.anchor..self.focus
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct TypographicBounds {
     pub width: f32,
@@ -579,10 +1483,289 @@ pub struct TypographicBounds {
     pub leading: f32,
 }
 
-fn compare_bounds_and_point_vertically(bounds: &Rect<f32>, point: &Point2D<f32>) -> Ordering {
-    match (bounds.origin.y <= point.y, point.y < bounds.max_y()) {
+/// The range of widths that a document or paragraph can be laid out in, measured along lines.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IntrinsicWidths {
+    /// The narrowest width in which no line overflows, short of hyphenating words: that of the
+    /// widest piece of text between line break opportunities.
+    pub min_content: f32,
+    /// The width in which no line wraps: that of the widest line between hard line breaks.
+    pub max_content: f32,
+}
+
+impl IntrinsicWidths {
+    #[inline]
+    pub fn zero() -> IntrinsicWidths {
+        IntrinsicWidths {
+            min_content: 0.0,
+            max_content: 0.0,
+        }
+    }
+
+    /// Returns the widths that hold both `self` and `other`.
+    #[inline]
+    pub fn union(self, other: IntrinsicWidths) -> IntrinsicWidths {
+        IntrinsicWidths {
+            min_content: self.min_content.max(other.min_content),
+            max_content: self.max_content.max(other.max_content),
+        }
+    }
+
+    /// Returns these widths with `margin` added.
+    #[inline]
+    pub fn outset(self, margin: f32) -> IntrinsicWidths {
+        IntrinsicWidths {
+            min_content: self.min_content + margin,
+            max_content: self.max_content + margin,
+        }
+    }
+}
+
+/// Returns the rect that `extent`, offsets from the origin of `line` along it, covers, across the
+/// ascent and descent of the line.
+fn line_span_rect(line: &Line, extent: Range<f32>, writing_mode: WritingMode) -> Rect<f32> {
+    let line_rect = line.typographic_bounding_rect();
+    if writing_mode.is_vertical() {
+        Rect::new(Point2D::new(line_rect.origin.x, line.origin.y + extent.start),
+                  Size2D::new(line_rect.size.width, extent.end - extent.start))
+    } else {
+        Rect::new(Point2D::new(line.origin.x + extent.start, line_rect.origin.y),
+                  Size2D::new(extent.end - extent.start, line_rect.size.height))
+    }
+}
+
+/// Returns the text of the cluster of each glyph of a run, given the index of the first character
+/// of each glyph's cluster and the run's characters, which start at `first_char_index`. Only the
+/// first glyph of a cluster gets its text; the rest, and any outside the run, get none.
+pub(crate) fn cluster_strings(clusters: &[usize], chars: &[char], first_char_index: usize)
+                              -> Vec<String> {
+    let char_range = first_char_index..(first_char_index + chars.len());
+    let mut cluster_starts: Vec<usize> = clusters.iter()
+                                                 .cloned()
+                                                 .filter(|cluster| char_range.contains(cluster))
+                                                 .collect();
+    cluster_starts.sort();
+    cluster_starts.dedup();
+
+    let mut seen = vec![false; cluster_starts.len()];
+    clusters.iter().map(|cluster| {
+        let index = match cluster_starts.binary_search(cluster) {
+            Ok(index) if !seen[index] => index,
+            _ => return String::new(),
+        };
+        seen[index] = true;
+        let end = cluster_starts.get(index + 1).cloned().unwrap_or(char_range.end);
+        chars[(cluster - first_char_index)..(end - first_char_index)].iter().collect()
+    }).collect()
+}
+
+#[inline]
+fn next_paragraph_revision() -> usize {
+    NEXT_PARAGRAPH_REVISION.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+fn paragraph_revisions(document: &Document) -> Vec<usize> {
+    document.paragraphs.iter().map(|paragraph| paragraph.revision).collect()
+}
+
+/// Lays out all of `document` in `rect`, inset by the document margin, as
+/// `Framesetter::layout_in_rect()` does.
+fn flow_section(framesetter: &Framesetter,
+                document: &Document,
+                rect: &Rect<f32>,
+                callbacks: Option<Arc<dyn LayoutCallbacks>>)
+                -> Section {
+    let rect = rect.inner_rect(document.style.margin);
+    let mut flow = ColumnFlow::new(&rect, &document.style, false, callbacks);
+    framesetter.flow_paragraphs(0..document.paragraphs.len(), &mut flow);
+    flow.finish_section()
+}
+
+/// Returns how many of the `line_count` remaining lines of a paragraph to place on the current
+/// page, when `fitting_line_count` of them fit, so as to leave no fewer than the minimum numbers
+/// of orphan lines on this page and widow lines on the next.
+fn lines_to_place(style: &ParagraphStyle, line_count: usize, fitting_line_count: usize)
+                  -> usize {
+    if fitting_line_count >= line_count {
+        return line_count
+    }
+    let placed_line_count =
+        fitting_line_count.min(line_count.saturating_sub(style.min_widow_lines));
+    if placed_line_count < style.min_orphan_lines {
+        0
+    } else {
+        placed_line_count
+    }
+}
+
+/// Orders `bounds` against `point` along the direction that lines and frames stack in.
+fn compare_bounds_and_point_in_block_direction(bounds: &Rect<f32>,
+                                               point: &Point2D<f32>,
+                                               writing_mode: WritingMode)
+                                               -> Ordering {
+    let (start, end, position) = match writing_mode {
+        WritingMode::HorizontalTb => (bounds.origin.y, bounds.max_y(), point.y),
+        WritingMode::VerticalLr => (bounds.origin.x, bounds.max_x(), point.x),
+        // Blocks stack leftward, so flip the axis.
+        WritingMode::VerticalRl => (-bounds.max_x(), -bounds.origin.x, -point.x),
+    };
+    match (start <= position, position < end) {
         (true, true) => Ordering::Equal,
         (false, _) => Ordering::Greater,
         (_, false) => Ordering::Less,
     }
 }
+
+/// Places frames down the columns of a rect, moving to the next column when one fills up, and
+/// from the last column to a new page when paginating. Without pagination, the last column has
+/// no bottom.
+///
+/// Columns and frames are placed in flow coordinates (see `WritingMode::flow_rect()`), and
+/// converted to page coordinates as pages are finished.
+struct ColumnFlow {
+    /// The rect in page coordinates.
+    page_rect: Rect<f32>,
+    writing_mode: WritingMode,
+    columns: Vec<Rect<f32>>,
+    paginate: bool,
+    column_rule: bool,
+    callbacks: Option<Arc<dyn LayoutCallbacks>>,
+    pages: Vec<Section>,
+    frames: Vec<Frame>,
+    /// The ranges of `frames` in the columns of this page before the current one.
+    column_frame_ranges: Vec<Range<usize>>,
+    /// The top of the next frame.
+    y: f32,
+}
+
+impl ColumnFlow {
+    fn new(page_rect: &Rect<f32>,
+           style: &DocumentStyle,
+           paginate: bool,
+           callbacks: Option<Arc<dyn LayoutCallbacks>>)
+           -> ColumnFlow {
+        let rect = style.writing_mode.flow_rect(page_rect);
+        let column_count = style.column_count.max(1);
+        let gap_width = style.column_gap * (column_count - 1) as f32;
+        let column_width = ((rect.size.width - gap_width) / column_count as f32).max(0.0);
+        let columns = (0..column_count).map(|column_index| {
+            let x = rect.origin.x + (column_width + style.column_gap) * column_index as f32;
+            Rect::new(Point2D::new(x, rect.origin.y), Size2D::new(column_width, rect.size.height))
+        }).collect();
+        ColumnFlow {
+            page_rect: *page_rect,
+            writing_mode: style.writing_mode,
+            columns,
+            paginate,
+            column_rule: style.column_rule,
+            callbacks,
+            pages: vec![],
+            frames: vec![],
+            column_frame_ranges: vec![],
+            y: rect.origin.y,
+        }
+    }
+
+    #[inline]
+    fn column(&self) -> &Rect<f32> {
+        &self.columns[self.column_frame_ranges.len()]
+    }
+
+    #[inline]
+    fn column_width(&self) -> f32 {
+        self.columns[0].size.width
+    }
+
+    /// Returns the bottom of the current column.
+    fn max_y(&self) -> f32 {
+        if !self.paginate && self.column_frame_ranges.len() + 1 == self.columns.len() {
+            f32::INFINITY
+        } else {
+            self.column().max_y()
+        }
+    }
+
+    #[inline]
+    fn column_height(&self) -> f32 {
+        self.max_y() - self.column().origin.y
+    }
+
+    fn column_frame_start(&self) -> usize {
+        self.column_frame_ranges.last().map_or(0, |frame_range| frame_range.end)
+    }
+
+    #[inline]
+    fn is_column_empty(&self) -> bool {
+        self.frames.len() == self.column_frame_start()
+    }
+
+    fn push_frame(&mut self, frame: Frame) {
+        self.y += frame.block_size();
+        self.frames.push(frame)
+    }
+
+    /// Places a frame from an earlier layout of a paragraph with `style` at the current position,
+    /// with the paragraph's margins around it.
+    fn place_frame(&mut self, mut frame: Frame, style: &ParagraphStyle) {
+        self.y += style.margin.top;
+        frame.set_block_offset(self.y);
+        self.push_frame(frame);
+        self.y += style.margin.bottom
+    }
+
+    fn next_column(&mut self) {
+        let frame_range = self.column_frame_start()..self.frames.len();
+        self.column_frame_ranges.push(frame_range);
+        if self.column_frame_ranges.len() == self.columns.len() {
+            debug_assert!(self.paginate);
+            self.finish_page()
+        }
+        self.y = self.column().origin.y;
+    }
+
+    fn finish_page(&mut self) {
+        let columns: Vec<_> = self.columns
+                                  .iter()
+                                  .cloned()
+                                  .zip(self.column_frame_ranges.drain(..))
+                                  .filter(|(_, frame_range)| !frame_range.is_empty())
+                                  .collect();
+        let column_rules = if self.column_rule {
+            columns.windows(2).map(|columns| {
+                let (left, right) = (&columns[0].0, &columns[1].0);
+                let x = (left.max_x() + right.origin.x - render::RULE_THICKNESS) * 0.5;
+                let rule = Rect::new(Point2D::new(x, left.origin.y),
+                                     Size2D::new(render::RULE_THICKNESS, left.size.height));
+                self.writing_mode.page_rect(&self.page_rect, &rule)
+            }).collect()
+        } else {
+            vec![]
+        };
+        let columns = columns.into_iter().map(|(column, frame_range)| {
+            (self.writing_mode.page_rect(&self.page_rect, &column), frame_range)
+        }).collect();
+        self.pages.push(Section {
+            frames: mem::take(&mut self.frames),
+            columns,
+            column_rules,
+            writing_mode: self.writing_mode,
+            callbacks: self.callbacks.clone(),
+        })
+    }
+
+    /// Finishes a flow that doesn't paginate, which makes one section.
+    fn finish_section(self) -> Section {
+        debug_assert!(!self.paginate);
+        let mut sections = self.finish();
+        sections.pop().expect("ColumnFlow::finish_section(): Flow didn't produce a section!")
+    }
+
+    fn finish(mut self) -> Vec<Section> {
+        if !self.frames.is_empty() || self.pages.is_empty() {
+            let frame_range = self.column_frame_start()..self.frames.len();
+            self.column_frame_ranges.push(frame_range);
+            self.finish_page()
+        }
+        self.pages
+    }
+}
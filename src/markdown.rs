@@ -8,13 +8,12 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use core_graphics::base::CGFloat;
 use indexmap::IndexMap;
 use indexmap::map::Entry;
 use pulldown_cmark::{Event, Parser, Tag};
 
-use format::{Color, Font, Format, Image};
-use {Document, DocumentStyle, Paragraph, ParagraphContent, ParagraphStyle};
+use format::Format;
+use {Document, Font, Paragraph, ParagraphContent, ParagraphStyle};
 
 pub struct MarkdownParser {
     paragraph_styles: [ParagraphStyle; 5],
@@ -27,12 +26,17 @@ impl MarkdownParser {
         let monospace_font = Font::default_monospace();
         let heading_1_font = plain_font.to_size(48.0);
         let heading_2_font = plain_font.to_size(36.0);
+        let heading_style = ParagraphStyle {
+            keep_with_next: true,
+            keep_together: true,
+            ..ParagraphStyle::default()
+        };
         MarkdownParser {
             paragraph_styles: [
                 ParagraphStyle::default(),
                 ParagraphStyle::default(),
-                ParagraphStyle::default(),
-                ParagraphStyle::default(),
+                heading_style.clone(),
+                heading_style,
                 ParagraphStyle::new(ParagraphContent::Rule),
             ],
             fonts: [
@@ -94,7 +98,7 @@ impl MarkdownParser {
                                 }
                             }
 
-                            while let Some(event) = parser.next() {
+                            for event in &mut parser {
                                 match event {
                                     Event::End(ref end_tag) if *end_tag == tag => {
                                         paragraph_done = true;
@@ -144,7 +148,7 @@ impl MarkdownParser {
                                                        .clone();
                                         current_cursor.push_format(Format::from_font(font));
                                     }
-                                    Event::Start(Tag::Image(url, alt_text)) => {
+                                    Event::Start(Tag::Image(url, _)) => {
                                         let url = url.to_string();
                                         let image_count = image_urls.len();
                                         let image_id = match image_urls.entry(url) {
@@ -154,10 +158,6 @@ impl MarkdownParser {
                                             }
                                             Entry::Occupied(entry) => *entry.get(),
                                         };
-                                        let image = Image {
-                                            id: image_id,
-                                            alt_text: alt_text.to_string(),
-                                        };
                                         current_cursor.push_format(Format::from_image(image_id));
                                         current_cursor.push_string("\u{fffc}");
                                     }
@@ -168,12 +168,9 @@ impl MarkdownParser {
                                     Event::End(Tag::Image(_, _)) => {
                                         current_cursor.pop_format()
                                     }
-                                    Event::Text(string) => {
-                                        if !current_cursor.format_stack().iter().any(|format| {
-                                            format.image().is_some()
-                                        }) {
-                                            current_cursor.push_string(&string)
-                                        }
+                                    Event::Text(ref string)
+                                            if !is_in_image(current_cursor.format_stack()) => {
+                                        current_cursor.push_string(string)
                                     }
                                     Event::SoftBreak => current_cursor.push_string(" "),
                                     Event::HardBreak => break,
@@ -217,6 +214,13 @@ impl MarkdownParser {
     }
 }
 
+impl Default for MarkdownParser {
+    #[inline]
+    fn default() -> MarkdownParser {
+        MarkdownParser::new()
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 #[repr(C)]
 pub enum InlineSelector {
@@ -258,3 +262,7 @@ impl ParseResults {
         &self.image_urls[image_index]
     }
 }
+
+fn is_in_image(format_stack: &[Format]) -> bool {
+    format_stack.iter().any(|format| format.image().is_some())
+}
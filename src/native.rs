@@ -0,0 +1,1413 @@
+// pilcrow/src/native.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The Core Text layout backend, used on macOS and iOS.
+
+use cocoa::base::id;
+use core_foundation::array::{CFArray, CFArrayRef};
+use core_foundation::attributed_string::{CFAttributedString, CFMutableAttributedString};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::base::{CFIndex, CFRange, CFType, CFTypeRef, TCFType};
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef, CFMutableDictionary};
+use core_foundation::number::{CFNumber, CFNumberRef};
+use core_foundation::string::{CFString, CFStringRef};
+use core_graphics::base::CGFloat;
+use core_graphics::data_provider::CGDataProvider;
+use core_graphics::font::{CGFont, CGGlyph};
+use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
+use core_graphics::geometry::{CG_AFFINE_TRANSFORM_IDENTITY, CG_ZERO_POINT};
+use core_graphics::path::{CGPath, CGPathElementRef, CGPathElementType};
+use core_text::font as ct_font;
+use core_text::font::{CTFont, CTFontRef};
+use core_text::font_descriptor::{self, CTFontDescriptorRef, kCTFontBoldTrait};
+use core_text::font_descriptor::{kCTFontDefaultOrientation, kCTFontItalicTrait};
+use core_text::frame::CTFrame;
+use core_text::framesetter::CTFramesetter;
+use core_text::line::{CTLine, CTLineRef};
+use core_text::run::{CTRun, CTRunRef};
+use core_text::string_attributes::kCTVerticalFormsAttributeName;
+use euclid::{Point2D, Rect, Size2D};
+use objc::runtime::Class;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator};
+use rayon::iter::ParallelIterator;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::os::raw::c_void;
+use std::ptr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use ttf_parser::OutlineBuilder;
+use unicode_bidi::{self, Direction};
+
+#[cfg(target_pointer_width = "32")]
+use std::f32;
+#[cfg(target_pointer_width = "64")]
+use std::f64;
+
+use attributed_string::AttributedString;
+use font::FontFace;
+use format::{Color, Format};
+use native_ext::{CFMutableAttributedStringExt, CTFontExt, CTFrameExt};
+use native_ext::{CTFramesetterExt, CTLineExt, CTRunDelegate, CTRunExt, ICTRunDelegate};
+use native_ext::{K_CF_NOT_FOUND, new_attributed_string, new_url};
+use segmentation;
+use {BaseDirection, ColumnFlow, Document, DocumentStyle, GlyphOrientation, IntrinsicWidths};
+use {LayoutCallbacks, LineHeight, Paragraph, ParagraphStyle, Section, TabAlignment};
+use {TextAlignment, TextDirection, WritingMode};
+use {TypographicBounds, cluster_strings};
+
+pub type Glyph = CGGlyph;
+
+type NativeAttributeDictionary = CFMutableDictionary<CFString, CFType>;
+
+extern "C" {
+    static kCTFontCascadeListAttribute: CFStringRef;
+
+    fn CTFontCreateCopyWithAttributes(font: CTFontRef,
+                                      size: CGFloat,
+                                      matrix: *const CGAffineTransform,
+                                      attributes: CTFontDescriptorRef)
+                                      -> CTFontRef;
+    fn CTLineCreateJustifiedLine(line: CTLineRef,
+                                 justification_factor: CGFloat,
+                                 justification_width: f64)
+                                 -> CTLineRef;
+    fn CTLineGetPenOffsetForFlush(line: CTLineRef, flush_factor: CGFloat, flush_width: f64)
+                                  -> f64;
+    fn CTRunGetStatus(run: CTRunRef) -> u32;
+    fn CTParagraphStyleCreate(settings: *const CTParagraphStyleSetting, count: usize)
+                              -> CFTypeRef;
+    fn CTTextTabCreate(alignment: u8, location: f64, options: CFDictionaryRef) -> CFTypeRef;
+
+    static kCTTabColumnTerminatorsAttributeName: CFStringRef;
+    static kCTFrameProgressionAttributeName: CFStringRef;
+
+    fn CFCharacterSetCreateWithCharactersInString(allocator: *const c_void, string: CFStringRef)
+                                                  -> CFTypeRef;
+}
+
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_FIRST_LINE_HEAD_INDENT: u32 = 1;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_HEAD_INDENT: u32 = 2;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_TAB_STOPS: u32 = 4;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_DEFAULT_TAB_INTERVAL: u32 = 5;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_LINE_HEIGHT_MULTIPLE: u32 = 7;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_MAXIMUM_LINE_HEIGHT: u32 = 8;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_MINIMUM_LINE_HEIGHT: u32 = 9;
+const K_CT_PARAGRAPH_STYLE_SPECIFIER_BASE_WRITING_DIRECTION: u32 = 13;
+
+const K_CT_WRITING_DIRECTION_LEFT_TO_RIGHT: i8 = 0;
+const K_CT_WRITING_DIRECTION_RIGHT_TO_LEFT: i8 = 1;
+
+const K_CT_RUN_STATUS_RIGHT_TO_LEFT: u32 = 1;
+
+const K_CT_FRAME_PROGRESSION_RIGHT_TO_LEFT: i32 = 1;
+const K_CT_FRAME_PROGRESSION_LEFT_TO_RIGHT: i32 = 2;
+
+const K_CT_TEXT_ALIGNMENT_LEFT: u8 = 0;
+const K_CT_TEXT_ALIGNMENT_RIGHT: u8 = 1;
+const K_CT_TEXT_ALIGNMENT_CENTER: u8 = 2;
+
+#[repr(C)]
+struct CTParagraphStyleSetting {
+    spec: u32,
+    value_size: usize,
+    value: *const c_void,
+}
+
+/// The height of frames with no height limit, such as those in the last column of
+/// `Framesetter::layout_in_rect()`.
+const UNBOUNDED_FRAME_HEIGHT: f32 = 1.0e6;
+
+lazy_static! {
+    static ref LAYOUT_CALLBACKS: RwLock<Option<Arc<dyn LayoutCallbacks>>> = {
+        RwLock::new(None)
+    };
+}
+
+// Core Foundation objects in the formats of a paragraph are immutable, so sharing them between
+// the threads that build framesetters is safe.
+unsafe impl Sync for Paragraph {}
+
+pub struct Framesetter {
+    framesetters: Vec<Mutex<ParagraphFramesetter>>,
+    document_style: DocumentStyle,
+}
+
+impl Framesetter {
+    /// Makes a framesetter for `document`. Paragraphs are converted to Core Text strings and
+    /// framesetters the first time they're laid out.
+    pub fn new(document: &Document) -> Framesetter {
+        Framesetter {
+            framesetters: document.paragraphs().par_iter().map(|paragraph| {
+                Mutex::new(ParagraphFramesetter::new(paragraph))
+            }).collect(),
+            document_style: document.style.clone(),
+        }
+    }
+
+    /// Brings this framesetter up to date with `document`, keeping the Core Text framesetters of
+    /// the paragraphs that weren't edited since it was made from it. Nothing is kept if the
+    /// document style changed.
+    pub fn update(&mut self, document: &Document) {
+        if document.style != self.document_style {
+            *self = Framesetter::new(document);
+            return
+        }
+
+        let mut old_framesetters: HashMap<usize, Mutex<ParagraphFramesetter>> =
+            self.framesetters.drain(..).map(|framesetter| {
+                let revision = framesetter.lock().unwrap().revision;
+                (revision, framesetter)
+            }).collect();
+        let framesetters: Vec<_> = document.paragraphs().iter().map(|paragraph| {
+            old_framesetters.remove(&paragraph.revision)
+        }).collect();
+        self.framesetters = framesetters.into_par_iter().zip(document.paragraphs().par_iter())
+                                                        .map(|(framesetter, paragraph)| {
+            framesetter.unwrap_or_else(|| Mutex::new(ParagraphFramesetter::new(paragraph)))
+        }).collect();
+    }
+
+    /// Lays out the document in `rect`, inset by the document margin. With more than one column,
+    /// paragraphs flow down each column in turn, and the last column extends past the bottom of
+    /// `rect` as needed.
+    pub fn layout_in_rect(&self, rect: &Rect<f32>, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                          -> Section {
+        let rect = rect.inner_rect(self.document_style.margin);
+        let mut sections = self.flow(&rect, false, callbacks);
+        sections.pop().expect("Framesetter::layout_in_rect(): Flow didn't produce a section!")
+    }
+
+    /// Lays out the document across pages of `page_size`, inset by the document margin,
+    /// returning one section per page in that page's coordinates. Paragraphs that overflow a
+    /// page or column are split between lines, and a paragraph's top margin is dropped when it
+    /// starts a page or column.
+    ///
+    /// The keep-with-next, keep-together, and widow and orphan settings of each paragraph's style
+    /// are honored where possible; when they can't all be met even in an empty column, they're
+    /// ignored for that column.
+    pub fn layout_in_pages(&self,
+                           page_size: &Size2D<f32>,
+                           callbacks: Option<Box<dyn LayoutCallbacks>>)
+                           -> Vec<Section> {
+        let rect = Rect::new(Point2D::zero(), *page_size).inner_rect(self.document_style.margin);
+        self.flow(&rect, true, callbacks)
+    }
+
+    /// Returns the range of widths that the document can be laid out in, including the document
+    /// margin, as if it had one column. Widths are measured along lines, so they're heights in
+    /// vertical writing modes.
+    pub fn intrinsic_widths(&self, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                            -> IntrinsicWidths {
+        *LAYOUT_CALLBACKS.write().unwrap() = callbacks.map(Arc::from);
+        let writing_mode = self.document_style.writing_mode;
+        let widths = (0..self.framesetters.len()).into_par_iter().map(|paragraph_index| {
+            self.measure_intrinsic_widths(paragraph_index)
+        }).reduce(IntrinsicWidths::zero, IntrinsicWidths::union);
+        widths.outset(writing_mode.inline_margin(&self.document_style.margin))
+    }
+
+    /// Returns the range of widths that paragraph `paragraph_index` can be laid out in,
+    /// including its margins.
+    pub fn paragraph_intrinsic_widths(&self,
+                                      paragraph_index: usize,
+                                      callbacks: Option<Box<dyn LayoutCallbacks>>)
+                                      -> IntrinsicWidths {
+        *LAYOUT_CALLBACKS.write().unwrap() = callbacks.map(Arc::from);
+        self.measure_intrinsic_widths(paragraph_index)
+    }
+
+    /// Returns the extent, in the direction that lines stack in, of the document laid out in
+    /// `width` as if it had one column, including the document margin and paragraph margins.
+    pub fn height_for_width(&self, width: f32, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                            -> f32 {
+        *LAYOUT_CALLBACKS.write().unwrap() = callbacks.map(Arc::from);
+        let writing_mode = self.document_style.writing_mode;
+        let width = (width - writing_mode.inline_margin(&self.document_style.margin)).max(0.0);
+        let height: f32 = (0..self.framesetters.len()).into_par_iter().map(|paragraph_index| {
+            self.measure_height(paragraph_index, width)
+        }).sum();
+        height + writing_mode.block_margin(&self.document_style.margin)
+    }
+
+    /// Returns the extent, in the direction that lines stack in, of paragraph `paragraph_index`
+    /// laid out in `width`, including its margins.
+    pub fn paragraph_height_for_width(&self,
+                                      paragraph_index: usize,
+                                      width: f32,
+                                      callbacks: Option<Box<dyn LayoutCallbacks>>)
+                                      -> f32 {
+        *LAYOUT_CALLBACKS.write().unwrap() = callbacks.map(Arc::from);
+        self.measure_height(paragraph_index, width)
+    }
+
+    /// Measures paragraph `paragraph_index` by setting each piece of text between line break
+    /// opportunities, and each line between hard line breaks, as a line of its own.
+    fn measure_intrinsic_widths(&self, paragraph_index: usize) -> IntrinsicWidths {
+        let mut paragraph_framesetter = self.framesetters[paragraph_index].lock().unwrap();
+        let typesetter = paragraph_framesetter.native(&self.document_style).0.typesetter();
+        let (chars, utf16_offsets, style) = (&paragraph_framesetter.chars,
+                                             &paragraph_framesetter.utf16_offsets,
+                                             &paragraph_framesetter.style);
+
+        let measure = |char_range: Range<usize>| {
+            let indent = if char_range.start == 0 {
+                style.first_line_indent
+            } else {
+                style.hanging_indent
+            };
+            let mut end = char_range.end;
+            while end > char_range.start && chars[end - 1].is_whitespace() {
+                end -= 1
+            }
+            if end == char_range.start {
+                return indent
+            }
+            let utf16_range = utf16_offsets[char_range.start]..utf16_offsets[end];
+            let range = CFRange::init(utf16_range.start as CFIndex,
+                                      (utf16_range.end - utf16_range.start) as CFIndex);
+            indent + typesetter.create_line(range).typographic_bounds().width as f32
+        };
+
+        let opportunities = segmentation::line_break_opportunities(chars);
+        let mut widths = IntrinsicWidths::zero();
+        let mut segment_start = 0;
+        for opportunity in &opportunities {
+            let segment_width = measure(segment_start..opportunity.char_index);
+            widths.min_content = widths.min_content.max(segment_width);
+            segment_start = opportunity.char_index
+        }
+        let mut line_start = 0;
+        for opportunity in opportunities.iter().filter(|opportunity| opportunity.mandatory) {
+            let line_width = measure(line_start..opportunity.char_index);
+            widths.max_content = widths.max_content.max(line_width);
+            line_start = opportunity.char_index
+        }
+        widths.outset(style.margin.horizontal())
+    }
+
+    fn measure_height(&self, paragraph_index: usize, width: f32) -> f32 {
+        let (char_len, margin) = {
+            let paragraph_framesetter = self.framesetters[paragraph_index].lock().unwrap();
+            (paragraph_framesetter.char_len(),
+             paragraph_framesetter.style.margin)
+        };
+        let unbounded_size = Size2D::new(width, UNBOUNDED_FRAME_HEIGHT);
+        let frame = self.create_frame(paragraph_index, 0..char_len, &unbounded_size);
+        frame.block_size() + margin.vertical()
+    }
+
+    /// Flows the paragraphs down the columns of `rect`, continuing on new pages when `paginate`
+    /// is set.
+    fn flow(&self,
+            rect: &Rect<f32>,
+            paginate: bool,
+            callbacks: Option<Box<dyn LayoutCallbacks>>)
+            -> Vec<Section> {
+        let callbacks: Option<Arc<dyn LayoutCallbacks>> = callbacks.map(Arc::from);
+        let mut flow = ColumnFlow::new(rect, &self.document_style, paginate, callbacks);
+        self.flow_paragraphs(0..self.framesetters.len(), &mut flow);
+        flow.finish()
+    }
+
+    /// Places the paragraphs in `paragraph_range` at the current position of `flow`.
+    pub(crate) fn flow_paragraphs(&self, paragraph_range: Range<usize>, flow: &mut ColumnFlow) {
+        *LAYOUT_CALLBACKS.write().unwrap() = flow.callbacks.clone();
+
+        let width = flow.column_width();
+        for paragraph_index in paragraph_range {
+            let (char_len, style) = {
+                let paragraph_framesetter = self.framesetters[paragraph_index].lock().unwrap();
+                (paragraph_framesetter.char_len(),
+                 paragraph_framesetter.style.clone())
+            };
+
+            // Lay the paragraph out without a height limit, to find out how many lines it has
+            // and how many of those fit in each column.
+            let unbounded_size = Size2D::new(width, UNBOUNDED_FRAME_HEIGHT);
+            let mut unbounded_frame = self.create_frame(paragraph_index,
+                                                        0..char_len,
+                                                        &unbounded_size);
+
+            // Move to the next column if the lines that must stay together don't fit in this one
+            // but would in an empty one.
+            let moves_to_next_column = !flow.is_column_empty() && {
+                let kept_height = self.kept_height(paragraph_index, &unbounded_frame, width);
+                flow.y + style.margin.top + kept_height > flow.max_y() &&
+                    kept_height <= flow.column_height()
+            };
+            if moves_to_next_column {
+                flow.next_column()
+            } else {
+                flow.y += style.margin.top
+            }
+
+            let mut char_start = 0;
+            loop {
+                let line_bottoms = unbounded_frame.line_bottoms();
+                let height = flow.max_y() - flow.y;
+                let fitting_line_count = line_bottoms.iter().take_while(|&&bottom| {
+                    bottom <= height
+                }).count();
+                let mut line_count = ::lines_to_place(&style,
+                                                      line_bottoms.len(),
+                                                      fitting_line_count);
+                if line_count == 0 && char_start < char_len {
+                    if !flow.is_column_empty() {
+                        flow.next_column();
+                        continue
+                    }
+
+                    // Nothing satisfies the style in an empty column, so take what fits, or let
+                    // a line that's taller than the column overflow.
+                    line_count = fitting_line_count.max(1)
+                }
+
+                // Only a paragraph that breaks here needs another frame, for the lines before
+                // the break.
+                let mut frame = if line_count == line_bottoms.len() {
+                    unbounded_frame
+                } else {
+                    let lines = unbounded_frame.frame.lines();
+                    let last_line = lines.get(line_count as CFIndex - 1).unwrap();
+                    let char_end = char_range_for_utf16_range(&unbounded_frame.utf16_offsets,
+                                                              last_line.string_range()).end;
+                    self.create_frame(paragraph_index, char_start..char_end, &unbounded_size)
+                };
+
+                frame.origin = Point2D::new(flow.column().origin.x + style.margin.left, flow.y);
+                frame.page_rect = flow.page_rect;
+                char_start = frame.char_range().end;
+                flow.push_frame(frame);
+
+                if char_start >= char_len {
+                    break
+                }
+                unbounded_frame = self.create_frame(paragraph_index,
+                                                    char_start..char_len,
+                                                    &unbounded_size);
+                flow.next_column();
+            }
+
+            flow.y += style.margin.bottom;
+        }
+    }
+
+    /// Returns the height of the lines that must go in the same column as the start of paragraph
+    /// `paragraph_index`, whose lines without a height limit are in `frame`: all of it if it's
+    /// keep-together or keep-with-next, chained through following keep-with-next paragraphs, and
+    /// then the first orphan lines of the next.
+    fn kept_height(&self, paragraph_index: usize, frame: &Frame, width: f32) -> f32 {
+        let unbounded_size = Size2D::new(width, UNBOUNDED_FRAME_HEIGHT);
+        let mut height = 0.0;
+        for index in paragraph_index..self.framesetters.len() {
+            let (char_len, style) = {
+                let paragraph_framesetter = self.framesetters[index].lock().unwrap();
+                (paragraph_framesetter.char_len(),
+                 paragraph_framesetter.style.clone())
+            };
+            let line_bottoms = if index == paragraph_index {
+                frame.line_bottoms()
+            } else {
+                height += style.margin.top;
+                self.create_frame(index, 0..char_len, &unbounded_size).line_bottoms()
+            };
+
+            let is_last = !style.keep_with_next || index + 1 == self.framesetters.len();
+            let kept_line_count = if style.keep_together || !is_last {
+                line_bottoms.len()
+            } else {
+                line_bottoms.len().min(style.min_orphan_lines.max(1))
+            };
+            if kept_line_count > 0 {
+                height += line_bottoms[kept_line_count - 1]
+            }
+            if is_last {
+                break
+            }
+            height += style.margin.bottom;
+        }
+        height
+    }
+
+    /// Creates a frame holding as much of `char_range` of paragraph `paragraph_index` as fits
+    /// in `size`, less the paragraph's margins.
+    fn create_frame(&self, paragraph_index: usize, char_range: Range<usize>, size: &Size2D<f32>)
+                    -> Frame {
+        let mut paragraph_framesetter = self.framesetters[paragraph_index].lock().unwrap();
+        let utf16_offsets = &paragraph_framesetter.utf16_offsets;
+        let utf16_range = utf16_offsets[char_range.start]..utf16_offsets[char_range.end];
+        let range = CFRange::init(utf16_range.start as CFIndex,
+                                  (utf16_range.end - utf16_range.start) as CFIndex);
+
+        let mut size = *size;
+        size.width -= paragraph_framesetter.style.margin.horizontal();
+
+        // Vertical frames are turned: lines run down them, and stack from one side.
+        let writing_mode = self.document_style.writing_mode;
+        let (cg_size, progression) = match writing_mode {
+            WritingMode::HorizontalTb => {
+                (CGSize::new(size.width as CGFloat, size.height as CGFloat), None)
+            }
+            WritingMode::VerticalRl => {
+                (CGSize::new(size.height as CGFloat, size.width as CGFloat),
+                 Some(K_CT_FRAME_PROGRESSION_RIGHT_TO_LEFT))
+            }
+            WritingMode::VerticalLr => {
+                (CGSize::new(size.height as CGFloat, size.width as CGFloat),
+                 Some(K_CT_FRAME_PROGRESSION_LEFT_TO_RIGHT))
+            }
+        };
+        let path = CGPath::from_rect(CGRect::new(&CG_ZERO_POINT, &cg_size), None);
+        let attributes = progression.map(|progression| unsafe {
+            let key = CFString::wrap_under_get_rule(kCTFrameProgressionAttributeName);
+            CFDictionary::from_CFType_pairs(&[(key, CFNumber::from(progression).as_CFType())])
+        });
+        let framesetter = &paragraph_framesetter.native(&self.document_style).0;
+        let frame = framesetter.create_frame_with_attributes(range, path, attributes);
+        Frame {
+            frame,
+            style: paragraph_framesetter.style.clone(),
+            direction: paragraph_framesetter.direction,
+            virtual_size: size,
+            origin: Point2D::zero(),
+            page_rect: Rect::zero(),
+            writing_mode,
+            paragraph_index,
+            last_line_ends: paragraph_framesetter.last_line_ends.clone(),
+            utf16_offsets: paragraph_framesetter.utf16_offsets.clone(),
+            chars: paragraph_framesetter.chars.clone(),
+        }
+    }
+}
+
+struct ParagraphFramesetter {
+    attributed_string: AttributedString,
+    /// The Core Text framesetter and the string it was made from, made the first time the
+    /// paragraph is laid out.
+    native: Option<(CTFramesetter, CFAttributedString)>,
+    style: ParagraphStyle,
+    /// The side that lines start on.
+    direction: TextDirection,
+    /// The character indices at which lines that get the last-line alignment end: after each
+    /// hard line break, and at the end of the paragraph.
+    last_line_ends: Arc<Vec<usize>>,
+    /// The UTF-16 offset that Core Text indexes each character by, and that of the end.
+    utf16_offsets: Arc<Vec<usize>>,
+    chars: Arc<Vec<char>>,
+    /// The revision of the paragraph that this was made from.
+    revision: usize,
+}
+
+// Each paragraph framesetter is only used by the thread holding its lock.
+unsafe impl Send for ParagraphFramesetter {}
+
+impl ParagraphFramesetter {
+    fn new(paragraph: &Paragraph) -> ParagraphFramesetter {
+        let string = paragraph.attributed_string.string();
+        let direction = resolve_direction(paragraph.style.base_direction, string);
+        let last_line_ends = string.chars().enumerate().filter(|&(_, ch)| {
+            ch == '\n' || ch == '\u{2028}'
+        }).map(|(index, _)| index + 1).chain(Some(string.chars().count())).collect();
+        let utf16_offsets = Some(0).into_iter().chain(string.chars().scan(0, |offset, ch| {
+            *offset += ch.len_utf16();
+            Some(*offset)
+        })).collect();
+        ParagraphFramesetter {
+            attributed_string: paragraph.attributed_string.clone(),
+            native: None,
+            style: paragraph.style.clone(),
+            direction,
+            last_line_ends: Arc::new(last_line_ends),
+            utf16_offsets: Arc::new(utf16_offsets),
+            chars: Arc::new(string.chars().collect()),
+            revision: paragraph.revision,
+        }
+    }
+
+    fn native(&mut self, document_style: &DocumentStyle)
+              -> &(CTFramesetter, CFAttributedString) {
+        let (attributed_string, style) = (&self.attributed_string, &self.style);
+        let direction = self.direction;
+        self.native.get_or_insert_with(|| {
+            let native_attributed_string =
+                to_native_attributed_string(attributed_string,
+                                            style,
+                                            direction,
+                                            document_style.writing_mode,
+                                            &document_style.fallback_fonts);
+            (CTFramesetter::from_attributed_string(native_attributed_string.clone()),
+             native_attributed_string)
+        })
+    }
+
+    #[inline]
+    fn char_len(&self) -> usize {
+        self.utf16_offsets.len() - 1
+    }
+}
+
+pub struct Frame {
+    frame: CTFrame,
+    style: ParagraphStyle,
+    direction: TextDirection,
+    /// The size and origin of this frame in flow coordinates.
+    virtual_size: Size2D<f32>,
+    origin: Point2D<f32>,
+    /// The rect that this frame was flowed into, in page coordinates.
+    page_rect: Rect<f32>,
+    writing_mode: WritingMode,
+    paragraph_index: usize,
+    last_line_ends: Arc<Vec<usize>>,
+    utf16_offsets: Arc<Vec<usize>>,
+    chars: Arc<Vec<char>>,
+}
+
+impl Frame {
+    #[inline]
+    pub fn char_len(&self) -> usize {
+        self.char_range().len()
+    }
+
+    /// Returns the range of characters of the paragraph that this frame holds. This is less than
+    /// the whole paragraph if it was split across pages.
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        char_range_for_utf16_range(&self.utf16_offsets, self.frame.get_visible_string_range())
+    }
+
+    /// Returns the index of the paragraph in the document that this frame holds.
+    #[inline]
+    pub fn paragraph_index(&self) -> usize {
+        self.paragraph_index
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        let lines = self.frame.lines();
+        let virtual_width = self.virtual_size.width as f64;
+        let frame_origin = self.origin;
+        let line_origins = self.flow_line_origins();
+        lines.into_iter().zip(line_origins).map(|(line, line_origin)| {
+            // Core Text lays lines out flush left, so align them here.
+            let line_end = char_range_for_utf16_range(&self.utf16_offsets,
+                                                      line.string_range()).end;
+            let is_last_line = self.last_line_ends.binary_search(&line_end).is_ok();
+            let alignment = TextAlignment::for_line(&self.style, is_last_line);
+
+            // Align in what's left after the indent. Left-to-right line origins are already
+            // past it; right-to-left lines are placed from the left edge here.
+            let (line_start_x, available_width) = match self.direction {
+                TextDirection::LeftToRight => {
+                    (line_origin.x as f64, virtual_width - line_origin.x as f64)
+                }
+                TextDirection::RightToLeft => {
+                    let indent = if line.string_range().location == 0 {
+                        self.style.first_line_indent
+                    } else {
+                        self.style.hanging_indent
+                    };
+                    (0.0, virtual_width - indent as f64)
+                }
+            };
+            let line = if alignment == TextAlignment::Justify {
+                unsafe {
+                    let justified_line = CTLineCreateJustifiedLine(line.as_concrete_TypeRef(),
+                                                                   1.0,
+                                                                   available_width);
+                    if justified_line.is_null() {
+                        (*line).clone()
+                    } else {
+                        CTLine::wrap_under_create_rule(justified_line)
+                    }
+                }
+            } else {
+                (*line).clone()
+            };
+            let inline_offset = unsafe {
+                CTLineGetPenOffsetForFlush(line.as_concrete_TypeRef(),
+                                           alignment.flush_factor(self.direction) as CGFloat,
+                                           available_width)
+            };
+            let origin = Point2D::new(frame_origin.x + (line_start_x + inline_offset) as f32,
+                                      frame_origin.y + line_origin.y);
+            Line {
+                line,
+                origin: self.writing_mode.page_point(&self.page_rect, &origin),
+                writing_mode: self.writing_mode,
+                utf16_offsets: self.utf16_offsets.clone(),
+                chars: self.chars.clone(),
+            }
+        }).collect()
+    }
+
+    /// Returns the origin of each line relative to the top left of this frame in flow
+    /// coordinates, before alignment.
+    fn flow_line_origins(&self) -> Vec<Point2D<f32>> {
+        let mut line_origins = vec![CG_ZERO_POINT; self.frame.lines().len() as usize];
+        self.frame.get_line_origins(0, &mut line_origins);
+        let (virtual_width, virtual_height) = (self.virtual_size.width, self.virtual_size.height);
+        line_origins.into_iter().map(|line_origin| {
+            let (x, y) = (line_origin.x as f32, line_origin.y as f32);
+            match self.writing_mode {
+                WritingMode::HorizontalTb => Point2D::new(x, virtual_height - y),
+                // Core Text measures from the bottom left of the turned frame.
+                WritingMode::VerticalRl => Point2D::new(virtual_width - y, virtual_height - x),
+                WritingMode::VerticalLr => Point2D::new(virtual_width - y, x),
+            }
+        }).collect()
+    }
+
+    #[inline]
+    pub fn bounds(&self) -> Rect<f32> {
+        let flow_bounds = Rect::new(self.origin,
+                                    Size2D::new(self.virtual_size.width, self.block_size()));
+        self.writing_mode.page_rect(&self.page_rect, &flow_bounds)
+    }
+
+    #[inline]
+    pub fn height(&self) -> f32 {
+        self.bounds().size.height
+    }
+
+    /// Returns the caret of an empty paragraph: where its first line would start, on the side
+    /// that lines start on and as aligned, across the frame in the direction lines stack in.
+    pub(crate) fn empty_caret_rect(&self) -> Rect<f32> {
+        let offset = self.style.empty_line_offset(self.direction, self.virtual_size.width);
+        let flow_rect = Rect::new(Point2D::new(self.origin.x + offset, self.origin.y),
+                                  Size2D::new(0.0, self.block_size()));
+        self.writing_mode.page_rect(&self.page_rect, &flow_rect)
+    }
+
+    /// Returns the extent of this frame in the direction that lines stack in: its width in
+    /// vertical writing modes.
+    pub fn block_size(&self) -> f32 {
+        let lines = self.frame.lines();
+        let line_count = lines.len();
+        if line_count == 0 {
+            return 0.0
+        }
+
+        let last_line = lines.get(line_count - 1).unwrap();
+        let last_line_origin = self.flow_line_origins()[line_count as usize - 1];
+        last_line_origin.y + last_line.typographic_bounds().descent as f32
+    }
+
+    /// Returns how far the bottom of each line, below its descent, is from the top of this frame
+    /// in the direction that lines stack in.
+    fn line_bottoms(&self) -> Vec<f32> {
+        let lines = self.frame.lines();
+        self.flow_line_origins().into_iter().zip(lines.iter()).map(|(line_origin, line)| {
+            line_origin.y + line.typographic_bounds().descent as f32
+        }).collect()
+    }
+
+    /// Moves this frame to `offset` in the direction that lines stack in, in flow coordinates.
+    #[inline]
+    pub(crate) fn set_block_offset(&mut self, offset: f32) {
+        self.origin.y = offset
+    }
+
+    pub fn line_index_at_point(&self, point: &Point2D<f32>) -> Option<usize> {
+        self.lines().binary_search_by(|line| {
+            ::compare_bounds_and_point_in_block_direction(&line.typographic_bounding_rect(),
+                                                          point,
+                                                          self.writing_mode)
+        }).ok()
+    }
+
+    #[inline]
+    pub fn style(&self) -> &ParagraphStyle {
+        &self.style
+    }
+}
+
+pub struct Line {
+    line: CTLine,
+    pub origin: Point2D<f32>,
+    writing_mode: WritingMode,
+    utf16_offsets: Arc<Vec<usize>>,
+    chars: Arc<Vec<char>>,
+}
+
+impl Line {
+    pub fn runs(&self) -> Vec<Run> {
+        self.line.glyph_runs().into_iter().map(|run| {
+            Run {
+                run: (*run).clone(),
+                writing_mode: self.writing_mode,
+                utf16_offsets: self.utf16_offsets.clone(),
+                chars: self.chars.clone(),
+            }
+        }).collect()
+    }
+
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        char_range_for_utf16_range(&self.utf16_offsets, self.line.string_range())
+    }
+
+    pub fn typographic_bounding_rect(&self) -> Rect<f32> {
+        let bounds = self.typographic_bounds();
+        let thickness = bounds.ascent + bounds.descent;
+        match self.writing_mode {
+            WritingMode::HorizontalTb => {
+                Rect::new(Point2D::new(self.origin.x, self.origin.y - bounds.ascent),
+                          Size2D::new(bounds.width, thickness))
+            }
+            // The ascent is on the side that lines stack from.
+            WritingMode::VerticalRl => {
+                Rect::new(Point2D::new(self.origin.x - bounds.descent, self.origin.y),
+                          Size2D::new(thickness, bounds.width))
+            }
+            WritingMode::VerticalLr => {
+                Rect::new(Point2D::new(self.origin.x - bounds.ascent, self.origin.y),
+                          Size2D::new(thickness, bounds.width))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn typographic_bounds(&self) -> TypographicBounds {
+        let typographic_bounds = self.line.typographic_bounds();
+        TypographicBounds {
+            width: typographic_bounds.width as f32,
+            ascent: typographic_bounds.ascent as f32,
+            descent: typographic_bounds.descent as f32,
+            leading: typographic_bounds.leading as f32,
+        }
+    }
+
+    /// Returns the index of the character at `position`, which is relative to the line origin.
+    /// Along vertical lines, only the Y coordinate counts.
+    #[inline]
+    pub fn char_index_for_position(&self, position: &Point2D<f32>) -> Option<usize> {
+        let position = if self.writing_mode.is_vertical() {
+            CGPoint::new(position.y as CGFloat, 0.0)
+        } else {
+            CGPoint::new(position.x as CGFloat, position.y as CGFloat)
+        };
+        match self.line.get_string_index_for_position(position) {
+            K_CF_NOT_FOUND => None,
+            utf16_offset => Some(char_index_for_utf16_offset(&self.utf16_offsets,
+                                                             utf16_offset as usize)),
+        }
+    }
+
+    #[inline]
+    pub fn inline_position_for_char_index(&self, index: usize) -> f32 {
+        let utf16_offset = self.utf16_offsets[index] as CFIndex;
+        self.line.get_offset_for_string_index(utf16_offset).0 as f32
+    }
+
+    /// Returns the extents of the characters in `range` as offsets from the line origin, from
+    /// left to right, one for each run that they're in. Text that changes direction can take
+    /// more than one extent.
+    pub fn inline_extents_for_char_range(&self, range: Range<usize>) -> Vec<Range<f32>> {
+        let range = self.utf16_offsets[range.start]..self.utf16_offsets[range.end];
+        let mut extents: Vec<Range<f32>> = self.line.glyph_runs().into_iter().filter_map(|run| {
+            let run_range = run.get_string_range();
+            let run_start = run_range.location as usize;
+            let run_end = (run_range.location + run_range.length) as usize;
+            let (start, end) = (range.start.max(run_start), range.end.min(run_end));
+            if start >= end {
+                return None
+            }
+
+            // At the end of a run, the secondary offset is the edge of the run, rather than that
+            // of the run after it.
+            let start_offset = self.line.get_offset_for_string_index(start as CFIndex).0 as f32;
+            let end_offset = self.line.get_offset_for_string_index(end as CFIndex);
+            let end_offset = if end == run_end { end_offset.1 } else { end_offset.0 } as f32;
+            Some(start_offset.min(end_offset)..start_offset.max(end_offset))
+        }).collect();
+        extents.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap_or(Ordering::Equal));
+        extents
+    }
+}
+
+pub struct Run {
+    run: CTRun,
+    writing_mode: WritingMode,
+    utf16_offsets: Arc<Vec<usize>>,
+    chars: Arc<Vec<char>>,
+}
+
+impl Run {
+    #[inline]
+    pub fn glyph_count(&self) -> usize {
+        self.run.glyph_count() as usize
+    }
+
+    pub fn glyphs(&self) -> Vec<Glyph> {
+        let mut glyphs = vec![0; self.glyph_count()];
+        self.run.get_glyphs(0, &mut glyphs);
+        glyphs
+    }
+
+    /// Returns the position of each glyph relative to the line origin, with Y up. Along vertical
+    /// lines, X runs across the line and Y down it.
+    pub fn positions(&self) -> Vec<Point2D<f32>> {
+        let mut positions = vec![CG_ZERO_POINT; self.glyph_count()];
+        self.run.get_positions(0, &mut positions);
+        let vertical = self.writing_mode.is_vertical();
+        positions.into_iter().map(|p| {
+            if vertical {
+                Point2D::new(p.y as f32, -p.x as f32)
+            } else {
+                Point2D::new(p.x as f32, p.y as f32)
+            }
+        }).collect()
+    }
+
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        char_range_for_utf16_range(&self.utf16_offsets, self.run.get_string_range())
+    }
+
+    /// Returns the text of each glyph's cluster, on the first glyph of the cluster, with none on
+    /// the rest.
+    pub fn glyph_strings(&self) -> Vec<String> {
+        let mut string_indices = vec![0; self.glyph_count()];
+        self.run.get_string_indices(0, &mut string_indices);
+        let clusters: Vec<_> = string_indices.into_iter().map(|string_index| {
+            char_index_for_utf16_offset(&self.utf16_offsets, string_index as usize)
+        }).collect();
+        let char_range = self.char_range();
+        cluster_strings(&clusters, &self.chars[char_range.clone()], char_range.start)
+    }
+
+    pub fn formatting(&self) -> Vec<Format> {
+        self.run.attributes().map_or(vec![], |attributes| attributes_to_formatting(&attributes))
+    }
+
+    #[inline]
+    pub fn typographic_bounds(&self) -> TypographicBounds {
+        let typographic_bounds = self.run.typographic_bounds(0..(self.glyph_count() as CFIndex));
+        TypographicBounds {
+            width: typographic_bounds.width as f32,
+            ascent: typographic_bounds.ascent as f32,
+            descent: typographic_bounds.descent as f32,
+            leading: typographic_bounds.leading as f32,
+        }
+    }
+
+    /// Returns the direction of this run's text. Its glyphs are in visual order either way.
+    #[inline]
+    pub fn direction(&self) -> TextDirection {
+        let status = unsafe { CTRunGetStatus(self.run.as_concrete_TypeRef()) };
+        if status & K_CT_RUN_STATUS_RIGHT_TO_LEFT != 0 {
+            TextDirection::RightToLeft
+        } else {
+            TextDirection::LeftToRight
+        }
+    }
+
+    /// Returns how this run's glyphs are turned. Core Text sets glyphs upright where the vertical
+    /// forms attribute is on.
+    pub fn glyph_orientation(&self) -> GlyphOrientation {
+        if !self.writing_mode.is_vertical() {
+            return GlyphOrientation::Upright
+        }
+        let vertical_forms = unsafe {
+            CFString::wrap_under_get_rule(kCTVerticalFormsAttributeName)
+        };
+        let attributes = match self.run.attributes() {
+            None => return GlyphOrientation::Rotated,
+            Some(attributes) => attributes,
+        };
+        match attributes.find(&vertical_forms) {
+            Some(value) if value.downcast::<CFBoolean>().is_some_and(bool::from) => {
+                GlyphOrientation::Upright
+            }
+            _ => GlyphOrientation::Rotated,
+        }
+    }
+
+    /// Returns the distance along the line from its origin to the start of this run.
+    pub(crate) fn inline_offset(&self) -> f32 {
+        if self.glyph_count() == 0 {
+            return 0.0
+        }
+        let mut positions = [CG_ZERO_POINT];
+        self.run.get_positions(0, &mut positions);
+        positions[0].x as f32
+    }
+}
+
+#[derive(Clone)]
+pub struct Font {
+    native_font: CTFont,
+}
+
+impl Font {
+    #[inline]
+    pub fn from_native_font(native_font: CTFont) -> Font {
+        Font {
+            native_font
+        }
+    }
+
+    /// Creates a Core Text font from face data loaded by us.
+    ///
+    /// Core Graphics can only load the first face of a font collection, so the family faces are
+    /// unused here; Core Text finds bold and italic variants itself.
+    pub(crate) fn from_face(face: Arc<FontFace>, _: Vec<Arc<FontFace>>, size: f32) -> Font {
+        let data_provider = CGDataProvider::from_buffer(face.data.clone());
+        let cg_font = CGFont::from_data_provider(data_provider)
+            .expect("Font::from_face(): Core Graphics failed to load the font!");
+        Font::from_native_font(ct_font::new_from_CGFont(&cg_font, size as CGFloat))
+    }
+
+    pub fn default_serif() -> Font {
+        Font::from_native_font(ct_font::new_from_name("Times", 16.0).unwrap())
+    }
+
+    pub fn default_monospace() -> Font {
+        Font::from_native_font(ct_font::new_from_name("Menlo", 12.0).unwrap())
+    }
+
+    #[inline]
+    pub fn id(&self) -> FontId {
+        FontId::from_native_font(self.native_font.clone())
+    }
+
+    #[inline]
+    pub fn face_id(&self) -> FontFaceId {
+        FontFaceId::from_native_font(self.native_font.clone())
+    }
+
+    #[inline]
+    pub fn size(&self) -> f32 {
+        self.native_font.pt_size() as f32
+    }
+
+    #[inline]
+    pub fn native_font(&self) -> CTFont {
+        self.native_font.clone()
+    }
+
+    /// Emits the outline of `glyph` to `builder`, in points at this font's size with Y pointing
+    /// up.
+    pub(crate) fn outline_glyph(&self, glyph: Glyph, builder: &mut dyn OutlineBuilder) {
+        let identity = CG_AFFINE_TRANSFORM_IDENTITY;
+        let path = match self.native_font.create_path_for_glyph(glyph, &identity) {
+            Err(()) => return,
+            Ok(path) => path,
+        };
+        let apply = |element: CGPathElementRef| {
+            let points: Vec<_> = element.points().iter().map(|point| {
+                (point.x as f32, point.y as f32)
+            }).collect();
+            match element.element_type {
+                CGPathElementType::MoveToPoint => builder.move_to(points[0].0, points[0].1),
+                CGPathElementType::AddLineToPoint => builder.line_to(points[0].0, points[0].1),
+                CGPathElementType::AddQuadCurveToPoint => {
+                    builder.quad_to(points[0].0, points[0].1, points[1].0, points[1].1)
+                }
+                CGPathElementType::AddCurveToPoint => {
+                    builder.curve_to(points[0].0,
+                                     points[0].1,
+                                     points[1].0,
+                                     points[1].1,
+                                     points[2].0,
+                                     points[2].1)
+                }
+                CGPathElementType::CloseSubpath => builder.close(),
+            }
+        };
+        path.apply(&apply);
+    }
+
+    /// Returns the horizontal advance of `glyph`, in points at this font's size.
+    pub(crate) fn glyph_advance(&self, glyph: Glyph) -> f32 {
+        unsafe {
+            self.native_font.get_advances_for_glyphs(kCTFontDefaultOrientation,
+                                                     &glyph,
+                                                     ptr::null_mut(),
+                                                     1) as f32
+        }
+    }
+
+    pub fn to_size(&self, new_size: f32) -> Font {
+        Font::from_native_font(self.native_font.clone_with_font_size(new_size as f64))
+    }
+
+    pub fn to_bold(&self) -> Option<Font> {
+        self.native_font
+            .clone_with_symbolic_traits(kCTFontBoldTrait, kCTFontBoldTrait)
+            .map(Font::from_native_font)
+    }
+
+    pub fn to_italic(&self) -> Option<Font> {
+        self.native_font
+            .clone_with_symbolic_traits(kCTFontItalicTrait, kCTFontItalicTrait)
+            .map(Font::from_native_font)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub struct FontFaceId(usize);
+
+impl FontFaceId {
+    fn from_native_font(font: CTFont) -> FontFaceId {
+        unsafe {
+            FontFaceId(mem::transmute::<CGFont, usize>(font.copy_to_CGFont()))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub struct FontId(usize);
+
+impl FontId {
+    fn from_native_font(font: CTFont) -> FontId {
+        unsafe {
+            FontId(mem::transmute::<CTFont, usize>(font))
+        }
+    }
+}
+
+impl Color {
+    /// Reads the components of `color`, an `NSColor` or `UIColor`.
+    ///
+    /// # Safety
+    ///
+    /// `color` must be a valid color object in an RGB color space.
+    pub unsafe fn from_native_color(color: id) -> Color {
+        let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+        let _: () = msg_send![color, getRed:&mut r green:&mut g blue:&mut b alpha:&mut a];
+        Color {
+            r: round_CGFloat(r * 255.0) as u8,
+            g: round_CGFloat(g * 255.0) as u8,
+            b: round_CGFloat(b * 255.0) as u8,
+            a: round_CGFloat(a * 255.0) as u8,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct ImageAttachment {
+    id: u32,
+}
+
+impl ICTRunDelegate for ImageAttachment {
+    fn width(&mut self) -> CGFloat {
+        match *LAYOUT_CALLBACKS.read().unwrap() {
+            Some(ref callbacks) => {
+                callbacks.get_image_size(self.id).map(|size| size.width as CGFloat).unwrap_or(0.0)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn ascent(&mut self) -> CGFloat {
+        match *LAYOUT_CALLBACKS.read().unwrap() {
+            Some(ref callbacks) => {
+                callbacks.get_image_size(self.id).map(|size| size.height as CGFloat).unwrap_or(0.0)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn descent(&mut self) -> CGFloat {
+        0.0
+    }
+}
+
+/// Converts our attributed string into a Core Foundation one, with each format expressed as Core
+/// Text attributes.
+fn to_native_attributed_string(attributed_string: &AttributedString,
+                               style: &ParagraphStyle,
+                               direction: TextDirection,
+                               writing_mode: WritingMode,
+                               fallback_fonts: &[Font])
+                               -> CFAttributedString {
+    let mut native_attributed_string = CFMutableAttributedString::new();
+    for (_, string, formats) in attributed_string.spans() {
+        let mut attributes = CFMutableDictionary::new();
+        for format in formats {
+            add_format_to_native_attributes(format, fallback_fonts, &mut attributes);
+        }
+        let span = new_attributed_string(&CFString::from(string), &attributes.to_immutable());
+        let range = CFRange::init(native_attributed_string.string().char_len(), 0);
+        native_attributed_string.replace_attributed_string(range, span);
+    }
+
+    let range = CFRange::init(0, native_attributed_string.string().char_len());
+    let paragraph_style_key = CFString::from_static_string("NSParagraphStyle");
+    native_attributed_string.set_attribute(range,
+                                           paragraph_style_key.as_concrete_TypeRef(),
+                                           native_paragraph_style(style, direction));
+
+    // Core Text lays vertical text on its side unless it's marked to be upright.
+    if writing_mode.is_vertical() {
+        let vertical_forms = unsafe {
+            CFString::wrap_under_get_rule(kCTVerticalFormsAttributeName)
+        };
+        let mut utf16_index = 0;
+        for ch in attributed_string.string().chars() {
+            let utf16_len = ch.len_utf16() as CFIndex;
+            if style.text_orientation.glyph_orientation(ch) == GlyphOrientation::Upright {
+                native_attributed_string.set_attribute(CFRange::init(utf16_index, utf16_len),
+                                                       vertical_forms.as_concrete_TypeRef(),
+                                                       CFBoolean::true_value());
+            }
+            utf16_index += utf16_len
+        }
+    }
+    native_attributed_string.as_attributed_string()
+}
+
+/// Returns a Core Text paragraph style with the line height, indents and writing direction of
+/// `style`, given that the paragraph runs in `direction`. Alignment
+/// is applied to each line when frames are read out instead, since Core Text has no setting for
+/// the alignment of the last line of a justified paragraph.
+fn native_paragraph_style(style: &ParagraphStyle, direction: TextDirection) -> CFType {
+    let (line_height_multiple, min_line_height, max_line_height) = match style.line_height {
+        LineHeight::Multiple(multiple) => {
+            (multiple, style.min_line_height, style.max_line_height)
+        }
+        LineHeight::Absolute(height) => {
+            let height = height.min(style.max_line_height).max(style.min_line_height);
+            (0.0, height, height)
+        }
+    };
+    let values: [CGFloat; 6] = [
+        style.first_line_indent as CGFloat,
+        style.hanging_indent as CGFloat,
+        line_height_multiple as CGFloat,
+        // Zero means no maximum.
+        if max_line_height.is_finite() { max_line_height as CGFloat } else { 0.0 },
+        min_line_height as CGFloat,
+        style.default_tab_interval as CGFloat,
+    ];
+    let specifiers = [
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_FIRST_LINE_HEAD_INDENT,
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_HEAD_INDENT,
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_LINE_HEIGHT_MULTIPLE,
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_MAXIMUM_LINE_HEIGHT,
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_MINIMUM_LINE_HEIGHT,
+        K_CT_PARAGRAPH_STYLE_SPECIFIER_DEFAULT_TAB_INTERVAL,
+    ];
+    let mut settings: Vec<_> = specifiers.iter().zip(values.iter()).map(|(&spec, value)| {
+        CTParagraphStyleSetting {
+            spec,
+            value_size: mem::size_of::<CGFloat>(),
+            value: value as *const CGFloat as *const c_void,
+        }
+    }).collect();
+
+    let tab_stops = native_tab_stops(style);
+    let tab_stops_ref = tab_stops.as_concrete_TypeRef();
+    let writing_direction = match direction {
+        TextDirection::LeftToRight => K_CT_WRITING_DIRECTION_LEFT_TO_RIGHT,
+        TextDirection::RightToLeft => K_CT_WRITING_DIRECTION_RIGHT_TO_LEFT,
+    };
+    settings.push(CTParagraphStyleSetting {
+        spec: K_CT_PARAGRAPH_STYLE_SPECIFIER_BASE_WRITING_DIRECTION,
+        value_size: mem::size_of::<i8>(),
+        value: &writing_direction as *const i8 as *const c_void,
+    });
+
+    settings.push(CTParagraphStyleSetting {
+        spec: K_CT_PARAGRAPH_STYLE_SPECIFIER_TAB_STOPS,
+        value_size: mem::size_of::<CFArrayRef>(),
+        value: &tab_stops_ref as *const CFArrayRef as *const c_void,
+    });
+
+    unsafe {
+        CFType::wrap_under_create_rule(CTParagraphStyleCreate(settings.as_ptr(), settings.len()))
+    }
+}
+
+fn native_tab_stops(style: &ParagraphStyle) -> CFArray<CFType> {
+    unsafe {
+        let decimal_options = CFDictionary::from_CFType_pairs(&[
+            (CFString::wrap_under_get_rule(kCTTabColumnTerminatorsAttributeName),
+             CFType::wrap_under_create_rule(CFCharacterSetCreateWithCharactersInString(
+                ptr::null(),
+                CFString::from_static_string(".").as_concrete_TypeRef()))),
+        ]);
+        let tab_stops: Vec<_> = style.tab_stops.iter().map(|tab_stop| {
+            let (alignment, options) = match tab_stop.alignment {
+                TabAlignment::Left => (K_CT_TEXT_ALIGNMENT_LEFT, ptr::null()),
+                TabAlignment::Right => (K_CT_TEXT_ALIGNMENT_RIGHT, ptr::null()),
+                TabAlignment::Center => (K_CT_TEXT_ALIGNMENT_CENTER, ptr::null()),
+                TabAlignment::Decimal => {
+                    (K_CT_TEXT_ALIGNMENT_RIGHT, decimal_options.as_concrete_TypeRef())
+                }
+            };
+            CFType::wrap_under_create_rule(CTTextTabCreate(alignment,
+                                                           tab_stop.position as f64,
+                                                           options))
+        }).collect();
+        CFArray::from_CFTypes(&tab_stops)
+    }
+}
+
+fn add_format_to_native_attributes(format: &Format,
+                                   fallback_fonts: &[Font],
+                                   dictionary: &mut NativeAttributeDictionary) {
+    match *format {
+        Format::Font(ref font) => {
+            let native_font = native_font_with_cascade_list(font, fallback_fonts);
+            dictionary.set(CFString::from_static_string("NSFont"), native_font.as_CFType())
+        }
+        Format::Color(ref color) => add_color_to_native_attributes(dictionary, color),
+        Format::Link(link_id, ref url) => {
+            // Keep the URL as a string too, since it may not parse.
+            let url = CFString::from_str(url).unwrap();
+            let link_dictionary = CFDictionary::from_CFType_pairs(&[
+                (CFString::from_static_string("PCLinkID"),
+                 CFNumber::from(link_id as i64).as_CFType()),
+                (CFString::from_static_string("PCLinkURL"), url.as_CFType()),
+            ]);
+            if let Some(url) = new_url(&url) {
+                dictionary.set(CFString::from_static_string("NSLink"), url.as_CFType())
+            }
+            dictionary.set(CFString::from_static_string("PCLink"),
+                           link_dictionary.as_CFType());
+        }
+        Format::Image(image_id) => {
+            let run_delegate = CTRunDelegate::new(Box::new(ImageAttachment {
+                id: image_id,
+            }));
+            let id_object = CFNumber::from(image_id as i64);
+            dictionary.set(CFString::from_static_string("CTRunDelegate"),
+                           run_delegate.as_CFType());
+            dictionary.set(CFString::from_static_string("PCImage"), id_object.as_CFType());
+        }
+    }
+}
+
+/// Returns a copy of `font` that Core Text will fall back from to `fallback_fonts`, in order, for
+/// characters it lacks glyphs for. Runs that use a fallback report it in their `NSFont`
+/// attribute.
+fn native_font_with_cascade_list(font: &Font, fallback_fonts: &[Font]) -> CTFont {
+    if fallback_fonts.is_empty() {
+        return font.native_font()
+    }
+
+    unsafe {
+        let descriptors: Vec<_> = fallback_fonts.iter().map(|fallback_font| {
+            fallback_font.native_font.copy_descriptor()
+        }).collect();
+        let cascade_list = CFArray::from_CFTypes(&descriptors);
+        let attributes = CFDictionary::from_CFType_pairs(&[
+            (CFString::wrap_under_get_rule(kCTFontCascadeListAttribute),
+             cascade_list.as_CFType()),
+        ]);
+        let descriptor = font_descriptor::new_from_attributes(&attributes);
+        let native_font = CTFontCreateCopyWithAttributes(font.native_font.as_concrete_TypeRef(),
+                                                         0.0,
+                                                         ptr::null(),
+                                                         descriptor.as_concrete_TypeRef());
+        CTFont::wrap_under_create_rule(native_font)
+    }
+}
+
+fn add_color_to_native_attributes(dictionary: &mut NativeAttributeDictionary, color: &Color) {
+    unsafe {
+        let color: id = msg_send![native_color_class(),
+                                  colorWithRed:color.r_f32() as CGFloat
+                                  green:color.g_f32() as CGFloat
+                                  blue:color.b_f32() as CGFloat
+                                  alpha:color.a_f32() as CGFloat];
+        dictionary.set(CFString::from_static_string("NSForegroundColor"),
+                       CFType::wrap_under_get_rule(color as CFTypeRef))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn native_color_class() -> &'static Class {
+    class!(NSColor)
+}
+
+#[cfg(target_os = "ios")]
+fn native_color_class() -> &'static Class {
+    class!(UIColor)
+}
+
+fn attributes_to_formatting(attributes: &CFDictionary<CFString, CFType>) -> Vec<Format> {
+    let mut formatting = vec![];
+    let (attribute_keys, attribute_values) = attributes.get_keys_and_values();
+    for (key, value) in attribute_keys.into_iter().zip(attribute_values) {
+        unsafe {
+            let key = mem::transmute::<*const c_void, CFStringRef>(key);
+            let key = CFString::wrap_under_get_rule(key);
+            if key == CFString::from_static_string("NSFont") {
+                let font = mem::transmute::<*const c_void, CTFontRef>(value);
+                let font = Font::from_native_font(CTFont::wrap_under_get_rule(font));
+                formatting.push(Format::Font(font))
+            } else if key == CFString::from_static_string("NSForegroundColor") {
+                let color = mem::transmute::<*const c_void, id>(value);
+                formatting.push(Format::Color(Color::from_native_color(color)));
+            } else if key == CFString::from_static_string("PCLink") {
+                let info = mem::transmute::<*const c_void, CFDictionaryRef>(value);
+                let info: CFDictionary<CFString, CFType> = CFDictionary::wrap_under_get_rule(info);
+                let id_key = CFString::from_static_string("PCLinkID");
+                let url_key = CFString::from_static_string("PCLinkURL");
+                let id = mem::transmute::<CFType, CFNumber>((*info.get(id_key)).clone());
+                let url = mem::transmute::<CFType, CFString>((*info.get(url_key)).clone());
+                let url = url.to_string();
+                formatting.push(Format::Link(id.to_i64().unwrap() as u32, url))
+            } else if key == CFString::from_static_string("PCImage") {
+                let id = mem::transmute::<*const c_void, CFNumberRef>(value);
+                let id = CFNumber::wrap_under_get_rule(id);
+                formatting.push(Format::Image(id.to_i64().unwrap() as u32))
+            }
+        }
+    }
+    formatting
+}
+
+#[cfg(target_pointer_width = "32")]
+#[allow(non_snake_case)]
+fn round_CGFloat(n: CGFloat) -> CGFloat {
+    f32::round(n)
+}
+
+#[cfg(target_pointer_width = "64")]
+#[allow(non_snake_case)]
+fn round_CGFloat(n: CGFloat) -> CGFloat {
+    f64::round(n)
+}
+
+/// Returns the index of the character at `utf16_offset`, given the UTF-16 offset of each
+/// character and of the end of the string.
+fn char_index_for_utf16_offset(utf16_offsets: &[usize], utf16_offset: usize) -> usize {
+    match utf16_offsets.binary_search(&utf16_offset) {
+        Ok(char_index) => char_index,
+        // Inside a surrogate pair.
+        Err(char_index) => char_index - 1,
+    }
+}
+
+fn char_range_for_utf16_range(utf16_offsets: &[usize], utf16_range: CFRange) -> Range<usize> {
+    let start = utf16_range.location as usize;
+    let end = start + utf16_range.length as usize;
+    char_index_for_utf16_offset(utf16_offsets, start)..
+        char_index_for_utf16_offset(utf16_offsets, end)
+}
+
+/// Returns the direction of a paragraph of `string`, taking the first strong directional
+/// character's for `BaseDirection::Auto`.
+fn resolve_direction(base_direction: BaseDirection, string: &str) -> TextDirection {
+    match base_direction {
+        BaseDirection::LeftToRight => TextDirection::LeftToRight,
+        BaseDirection::RightToLeft => TextDirection::RightToLeft,
+        BaseDirection::Auto => match unicode_bidi::get_base_direction(string) {
+            Direction::Rtl => TextDirection::RightToLeft,
+            Direction::Ltr | Direction::Mixed => TextDirection::LeftToRight,
+        },
+    }
+}
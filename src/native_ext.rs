@@ -0,0 +1,442 @@
+// pilcrow/src/native_ext.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The Core Foundation and Core Text calls that the Core Text backend needs but the
+//! `core-foundation` and `core-text` crates don't wrap, as extension traits on their types.
+
+use core_foundation::array::{CFArray, CFArrayRef};
+use core_foundation::attributed_string::{CFAttributedString, CFAttributedStringRef};
+use core_foundation::attributed_string::{CFAttributedStringCreate, CFMutableAttributedString};
+use core_foundation::attributed_string::CFMutableAttributedStringRef;
+use core_foundation::base::{CFAllocatorRef, CFIndex, CFRange, CFType, CFTypeID, TCFType};
+use core_foundation::base::kCFAllocatorDefault;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::{CFString, CFStringRef};
+use core_foundation::url::{CFURL, CFURLRef};
+use core_graphics::base::CGFloat;
+use core_graphics::font::CGGlyph;
+use core_graphics::geometry::CGPoint;
+use core_graphics::path::CGPath;
+use core_text::font::{CTFont, CTFontRef};
+use core_text::font_descriptor::{CTFontDescriptor, CTFontDescriptorRef};
+use core_text::frame::{CTFrame, CTFrameRef};
+use core_text::framesetter::{CTFramesetter, CTFramesetterRef};
+use core_text::line::{CTLine, CTLineRef};
+use core_text::run::{CTRun, CTRunRef};
+use foreign_types::ForeignType;
+use std::ops::Range;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// The index that Core Foundation returns when there's none to return.
+pub const K_CF_NOT_FOUND: CFIndex = -1;
+
+const K_CT_RUN_DELEGATE_VERSION_1: CFIndex = 1;
+
+#[repr(C)]
+pub struct __CTRunDelegate(c_void);
+
+pub type CTRunDelegateRef = *const __CTRunDelegate;
+
+declare_TCFType! {
+    CTRunDelegate, CTRunDelegateRef
+}
+impl_TCFType!(CTRunDelegate, CTRunDelegateRef, CTRunDelegateGetTypeID);
+
+#[repr(C)]
+pub struct __CTTypesetter(c_void);
+
+pub type CTTypesetterRef = *const __CTTypesetter;
+
+declare_TCFType! {
+    CTTypesetter, CTTypesetterRef
+}
+impl_TCFType!(CTTypesetter, CTTypesetterRef, CTTypesetterGetTypeID);
+
+/// The metrics of a run that Core Text asks a run delegate for.
+pub trait ICTRunDelegate {
+    fn width(&mut self) -> CGFloat;
+    fn ascent(&mut self) -> CGFloat;
+    fn descent(&mut self) -> CGFloat;
+}
+
+#[repr(C)]
+struct CTRunDelegateCallbacks {
+    version: CFIndex,
+    dealloc: extern "C" fn(ref_con: *mut c_void),
+    get_ascent: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+    get_descent: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+    get_width: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+}
+
+static RUN_DELEGATE_CALLBACKS: CTRunDelegateCallbacks = CTRunDelegateCallbacks {
+    version: K_CT_RUN_DELEGATE_VERSION_1,
+    dealloc: dealloc_run_delegate,
+    get_ascent: get_run_delegate_ascent,
+    get_descent: get_run_delegate_descent,
+    get_width: get_run_delegate_width,
+};
+
+/// The width, ascent, descent and leading of a line or run, as Core Text measures them.
+#[derive(Clone, Copy, Debug)]
+pub struct CTTypographicBounds {
+    pub width: CGFloat,
+    pub ascent: CGFloat,
+    pub descent: CGFloat,
+    pub leading: CGFloat,
+}
+
+extern "C" {
+    fn CFAttributedStringGetString(string: CFAttributedStringRef) -> CFStringRef;
+    fn CFAttributedStringCreateCopy(allocator: CFAllocatorRef, string: CFAttributedStringRef)
+                                    -> CFAttributedStringRef;
+    fn CFAttributedStringReplaceAttributedString(string: CFMutableAttributedStringRef,
+                                                 range: CFRange,
+                                                 replacement: CFAttributedStringRef);
+
+    fn CFURLCreateWithString(allocator: CFAllocatorRef, string: CFStringRef, base_url: CFURLRef)
+                             -> CFURLRef;
+
+    fn CTFontCopyFontDescriptor(font: CTFontRef) -> CTFontDescriptorRef;
+
+    fn CTFramesetterGetTypesetter(framesetter: CTFramesetterRef) -> CTTypesetterRef;
+    fn CTFramesetterCreateFrame(framesetter: CTFramesetterRef,
+                                string_range: CFRange,
+                                path: *mut <CGPath as ForeignType>::CType,
+                                attributes: *const c_void)
+                                -> CTFrameRef;
+
+    fn CTFrameGetLines(frame: CTFrameRef) -> CFArrayRef;
+    fn CTFrameGetLineOrigins(frame: CTFrameRef, range: CFRange, origins: *mut CGPoint);
+    fn CTFrameGetVisibleStringRange(frame: CTFrameRef) -> CFRange;
+
+    fn CTLineGetStringRange(line: CTLineRef) -> CFRange;
+    fn CTLineGetTypographicBounds(line: CTLineRef,
+                                  ascent: *mut CGFloat,
+                                  descent: *mut CGFloat,
+                                  leading: *mut CGFloat)
+                                  -> f64;
+    fn CTLineGetStringIndexForPosition(line: CTLineRef, position: CGPoint) -> CFIndex;
+    fn CTLineGetOffsetForStringIndex(line: CTLineRef,
+                                     char_index: CFIndex,
+                                     secondary_offset: *mut CGFloat)
+                                     -> CGFloat;
+
+    fn CTRunGetStringRange(run: CTRunRef) -> CFRange;
+    fn CTRunGetGlyphs(run: CTRunRef, range: CFRange, buffer: *mut CGGlyph);
+    fn CTRunGetPositions(run: CTRunRef, range: CFRange, buffer: *mut CGPoint);
+    fn CTRunGetStringIndices(run: CTRunRef, range: CFRange, buffer: *mut CFIndex);
+    fn CTRunGetTypographicBounds(run: CTRunRef,
+                                 range: CFRange,
+                                 ascent: *mut CGFloat,
+                                 descent: *mut CGFloat,
+                                 leading: *mut CGFloat)
+                                 -> f64;
+
+    fn CTTypesetterGetTypeID() -> CFTypeID;
+    fn CTTypesetterCreateLine(typesetter: CTTypesetterRef, string_range: CFRange) -> CTLineRef;
+
+    fn CTRunDelegateGetTypeID() -> CFTypeID;
+    fn CTRunDelegateCreate(callbacks: *const CTRunDelegateCallbacks, ref_con: *mut c_void)
+                           -> CTRunDelegateRef;
+}
+
+/// Makes an attributed string of `string` with `attributes` throughout.
+pub fn new_attributed_string(string: &CFString, attributes: &CFDictionary<CFString, CFType>)
+                             -> CFAttributedString {
+    unsafe {
+        CFAttributedString::wrap_under_create_rule(
+            CFAttributedStringCreate(kCFAllocatorDefault,
+                                     string.as_concrete_TypeRef(),
+                                     attributes.as_concrete_TypeRef()))
+    }
+}
+
+/// Parses `string` as a URL, returning `None` if it isn't one.
+pub fn new_url(string: &CFString) -> Option<CFURL> {
+    unsafe {
+        let url = CFURLCreateWithString(kCFAllocatorDefault,
+                                        string.as_concrete_TypeRef(),
+                                        ptr::null());
+        if url.is_null() {
+            None
+        } else {
+            Some(CFURL::wrap_under_create_rule(url))
+        }
+    }
+}
+
+pub trait CFMutableAttributedStringExt {
+    fn string(&self) -> CFString;
+    fn replace_attributed_string(&mut self, range: CFRange, replacement: CFAttributedString);
+    /// Returns an immutable copy of this string.
+    fn as_attributed_string(&self) -> CFAttributedString;
+}
+
+impl CFMutableAttributedStringExt for CFMutableAttributedString {
+    #[inline]
+    fn string(&self) -> CFString {
+        unsafe {
+            CFString::wrap_under_get_rule(CFAttributedStringGetString(self.as_concrete_TypeRef()))
+        }
+    }
+
+    #[inline]
+    fn replace_attributed_string(&mut self, range: CFRange, replacement: CFAttributedString) {
+        unsafe {
+            CFAttributedStringReplaceAttributedString(self.as_concrete_TypeRef(),
+                                                      range,
+                                                      replacement.as_concrete_TypeRef())
+        }
+    }
+
+    #[inline]
+    fn as_attributed_string(&self) -> CFAttributedString {
+        unsafe {
+            CFAttributedString::wrap_under_create_rule(
+                CFAttributedStringCreateCopy(kCFAllocatorDefault, self.as_concrete_TypeRef()))
+        }
+    }
+}
+
+pub trait CTFontExt {
+    fn copy_descriptor(&self) -> CTFontDescriptor;
+}
+
+impl CTFontExt for CTFont {
+    #[inline]
+    fn copy_descriptor(&self) -> CTFontDescriptor {
+        unsafe {
+            CTFontDescriptor::wrap_under_create_rule(
+                CTFontCopyFontDescriptor(self.as_concrete_TypeRef()))
+        }
+    }
+}
+
+pub trait CTFramesetterExt {
+    fn from_attributed_string(string: CFAttributedString) -> Self;
+    /// Returns the typesetter that this framesetter breaks lines with.
+    fn typesetter(&self) -> CTTypesetter;
+    /// Makes a frame of `range` in `path`, with frame attributes such as the progression.
+    fn create_frame_with_attributes(&self,
+                                    range: CFRange,
+                                    path: CGPath,
+                                    attributes: Option<CFDictionary<CFString, CFType>>)
+                                    -> CTFrame;
+}
+
+impl CTFramesetterExt for CTFramesetter {
+    #[inline]
+    fn from_attributed_string(string: CFAttributedString) -> CTFramesetter {
+        CTFramesetter::new_with_attributed_string(string.as_concrete_TypeRef())
+    }
+
+    #[inline]
+    fn typesetter(&self) -> CTTypesetter {
+        unsafe {
+            CTTypesetter::wrap_under_get_rule(CTFramesetterGetTypesetter(
+                self.as_concrete_TypeRef()))
+        }
+    }
+
+    fn create_frame_with_attributes(&self,
+                                    range: CFRange,
+                                    path: CGPath,
+                                    attributes: Option<CFDictionary<CFString, CFType>>)
+                                    -> CTFrame {
+        let attributes = attributes.as_ref().map_or(ptr::null(), |attributes| {
+            attributes.as_concrete_TypeRef() as *const c_void
+        });
+        unsafe {
+            CTFrame::wrap_under_create_rule(CTFramesetterCreateFrame(self.as_concrete_TypeRef(),
+                                                                     range,
+                                                                     path.as_ptr(),
+                                                                     attributes))
+        }
+    }
+}
+
+pub trait CTFrameExt {
+    fn lines(&self) -> CFArray<CTLine>;
+    /// Copies the origins of the lines from `start_index` on into `origins`, as many as it holds.
+    fn get_line_origins(&self, start_index: CFIndex, origins: &mut [CGPoint]);
+    fn get_visible_string_range(&self) -> CFRange;
+}
+
+impl CTFrameExt for CTFrame {
+    #[inline]
+    fn lines(&self) -> CFArray<CTLine> {
+        unsafe { CFArray::wrap_under_get_rule(CTFrameGetLines(self.as_concrete_TypeRef())) }
+    }
+
+    #[inline]
+    fn get_line_origins(&self, start_index: CFIndex, origins: &mut [CGPoint]) {
+        if origins.is_empty() {
+            return
+        }
+        let range = CFRange::init(start_index, origins.len() as CFIndex);
+        unsafe {
+            CTFrameGetLineOrigins(self.as_concrete_TypeRef(), range, origins.as_mut_ptr())
+        }
+    }
+
+    #[inline]
+    fn get_visible_string_range(&self) -> CFRange {
+        unsafe { CTFrameGetVisibleStringRange(self.as_concrete_TypeRef()) }
+    }
+}
+
+pub trait CTLineExt {
+    fn string_range(&self) -> CFRange;
+    fn typographic_bounds(&self) -> CTTypographicBounds;
+    fn get_string_index_for_position(&self, position: CGPoint) -> CFIndex;
+    /// Returns the primary and secondary offsets of the caret at `char_index` from the line
+    /// origin.
+    fn get_offset_for_string_index(&self, char_index: CFIndex) -> (CGFloat, CGFloat);
+}
+
+impl CTLineExt for CTLine {
+    #[inline]
+    fn string_range(&self) -> CFRange {
+        unsafe { CTLineGetStringRange(self.as_concrete_TypeRef()) }
+    }
+
+    #[inline]
+    fn typographic_bounds(&self) -> CTTypographicBounds {
+        let (mut ascent, mut descent, mut leading) = (0.0, 0.0, 0.0);
+        unsafe {
+            let width = CTLineGetTypographicBounds(self.as_concrete_TypeRef(),
+                                                   &mut ascent,
+                                                   &mut descent,
+                                                   &mut leading);
+            CTTypographicBounds { width: width as CGFloat, ascent, descent, leading }
+        }
+    }
+
+    #[inline]
+    fn get_string_index_for_position(&self, position: CGPoint) -> CFIndex {
+        unsafe { CTLineGetStringIndexForPosition(self.as_concrete_TypeRef(), position) }
+    }
+
+    #[inline]
+    fn get_offset_for_string_index(&self, char_index: CFIndex) -> (CGFloat, CGFloat) {
+        let mut secondary_offset = 0.0;
+        unsafe {
+            let primary_offset = CTLineGetOffsetForStringIndex(self.as_concrete_TypeRef(),
+                                                               char_index,
+                                                               &mut secondary_offset);
+            (primary_offset, secondary_offset)
+        }
+    }
+}
+
+pub trait CTRunExt {
+    fn get_string_range(&self) -> CFRange;
+    /// Copies the glyphs from `start_index` on into `glyphs`, as many as it holds.
+    fn get_glyphs(&self, start_index: CFIndex, glyphs: &mut [CGGlyph]);
+    /// Copies the glyph positions from `start_index` on into `positions`, as many as it holds.
+    fn get_positions(&self, start_index: CFIndex, positions: &mut [CGPoint]);
+    /// Copies the string index of each glyph from `start_index` on into `indices`, as many as it
+    /// holds.
+    fn get_string_indices(&self, start_index: CFIndex, indices: &mut [CFIndex]);
+    fn typographic_bounds(&self, glyph_range: Range<CFIndex>) -> CTTypographicBounds;
+}
+
+impl CTRunExt for CTRun {
+    #[inline]
+    fn get_string_range(&self) -> CFRange {
+        unsafe { CTRunGetStringRange(self.as_concrete_TypeRef()) }
+    }
+
+    #[inline]
+    fn get_glyphs(&self, start_index: CFIndex, glyphs: &mut [CGGlyph]) {
+        if glyphs.is_empty() {
+            return
+        }
+        let range = CFRange::init(start_index, glyphs.len() as CFIndex);
+        unsafe { CTRunGetGlyphs(self.as_concrete_TypeRef(), range, glyphs.as_mut_ptr()) }
+    }
+
+    #[inline]
+    fn get_positions(&self, start_index: CFIndex, positions: &mut [CGPoint]) {
+        if positions.is_empty() {
+            return
+        }
+        let range = CFRange::init(start_index, positions.len() as CFIndex);
+        unsafe { CTRunGetPositions(self.as_concrete_TypeRef(), range, positions.as_mut_ptr()) }
+    }
+
+    #[inline]
+    fn get_string_indices(&self, start_index: CFIndex, indices: &mut [CFIndex]) {
+        if indices.is_empty() {
+            return
+        }
+        let range = CFRange::init(start_index, indices.len() as CFIndex);
+        unsafe { CTRunGetStringIndices(self.as_concrete_TypeRef(), range, indices.as_mut_ptr()) }
+    }
+
+    #[inline]
+    fn typographic_bounds(&self, glyph_range: Range<CFIndex>) -> CTTypographicBounds {
+        let range = CFRange::init(glyph_range.start, glyph_range.end - glyph_range.start);
+        let (mut ascent, mut descent, mut leading) = (0.0, 0.0, 0.0);
+        unsafe {
+            let width = CTRunGetTypographicBounds(self.as_concrete_TypeRef(),
+                                                  range,
+                                                  &mut ascent,
+                                                  &mut descent,
+                                                  &mut leading);
+            CTTypographicBounds { width: width as CGFloat, ascent, descent, leading }
+        }
+    }
+}
+
+impl CTRunDelegate {
+    /// Makes a run delegate that asks `delegate` for the metrics of the runs it's attached to.
+    pub fn new(delegate: Box<dyn ICTRunDelegate>) -> CTRunDelegate {
+        // Core Text only holds a thin pointer, so box the trait object again.
+        let ref_con = Box::into_raw(Box::new(delegate)) as *mut c_void;
+        unsafe {
+            CTRunDelegate::wrap_under_create_rule(CTRunDelegateCreate(&RUN_DELEGATE_CALLBACKS,
+                                                                      ref_con))
+        }
+    }
+}
+
+impl CTTypesetter {
+    /// Sets `range`, which must not be empty, as one line, however long it is.
+    #[inline]
+    pub fn create_line(&self, range: CFRange) -> CTLine {
+        unsafe {
+            CTLine::wrap_under_create_rule(CTTypesetterCreateLine(self.as_concrete_TypeRef(),
+                                                                  range))
+        }
+    }
+}
+
+#[inline]
+unsafe fn run_delegate<'a>(ref_con: *mut c_void) -> &'a mut Box<dyn ICTRunDelegate> {
+    &mut *(ref_con as *mut Box<dyn ICTRunDelegate>)
+}
+
+extern "C" fn dealloc_run_delegate(ref_con: *mut c_void) {
+    unsafe { drop(Box::from_raw(ref_con as *mut Box<dyn ICTRunDelegate>)) }
+}
+
+extern "C" fn get_run_delegate_ascent(ref_con: *mut c_void) -> CGFloat {
+    unsafe { run_delegate(ref_con).ascent() }
+}
+
+extern "C" fn get_run_delegate_descent(ref_con: *mut c_void) -> CGFloat {
+    unsafe { run_delegate(ref_con).descent() }
+}
+
+extern "C" fn get_run_delegate_width(ref_con: *mut c_void) -> CGFloat {
+    unsafe { run_delegate(ref_con).width() }
+}
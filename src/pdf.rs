@@ -0,0 +1,616 @@
+// pilcrow/src/pdf.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Writing laid-out sections to PDF, one section per page.
+//!
+//! Fonts are embedded as Type 3 fonts built from the outlines of only the glyphs that are used,
+//! so that embedding works the same way for every layout backend and font format. A face with
+//! more than 256 used glyphs is split across several PDF fonts.
+
+use euclid::{Point2D, Rect, Size2D};
+use std::collections::HashMap;
+use std::fmt::Write;
+use ttf_parser::OutlineBuilder;
+
+use format::{Color, Format};
+use render::{self, DEFAULT_COLOR, RULE_THICKNESS};
+use svg::number;
+use {Font, FontFaceId, Glyph, GlyphOrientation, ParagraphContent, Section};
+
+/// The size of the em square in the glyph space of the embedded fonts.
+const UNITS_PER_EM: f32 = 1000.0;
+
+/// Collects pages and writes them out as a PDF file.
+pub struct PdfWriter {
+    page_size: Size2D<f32>,
+    pages: Vec<Page>,
+    fonts: Vec<EmbeddedFont>,
+    glyph_codes: HashMap<(FontFaceId, Glyph), (usize, u8)>,
+    images: Vec<EmbeddedImage>,
+    image_indices: HashMap<u32, usize>,
+    fill_alphas: Vec<u8>,
+}
+
+struct Page {
+    content: String,
+    links: Vec<Link>,
+}
+
+struct Link {
+    /// The clickable area, in PDF coordinates (Y pointing up from the bottom of the page).
+    rect: Rect<f32>,
+    url: String,
+}
+
+struct EmbeddedFont {
+    /// Any instance of the face; outlines and advances are scaled to the em square.
+    font: Font,
+    glyphs: Vec<Glyph>,
+    /// The text that each glyph was first seen standing for, for the `ToUnicode` CMap.
+    strings: Vec<String>,
+}
+
+struct EmbeddedImage {
+    size: Size2D<u32>,
+    rgb: Vec<u8>,
+    alpha: Option<Vec<u8>>,
+}
+
+impl PdfWriter {
+    /// Creates a writer whose pages are `page_size` points in size.
+    #[inline]
+    pub fn new(page_size: Size2D<f32>) -> PdfWriter {
+        PdfWriter {
+            page_size,
+            pages: vec![],
+            fonts: vec![],
+            glyph_codes: HashMap::new(),
+            images: vec![],
+            image_indices: HashMap::new(),
+            fill_alphas: vec![],
+        }
+    }
+
+    /// Adds a page containing `section`, whose coordinates are relative to the top left of the
+    /// page.
+    pub fn add_page(&mut self, section: &Section) {
+        let mut content = String::new();
+        let mut links = vec![];
+
+        // Flip the coordinate system so that Y points down, as in layout.
+        writeln!(content, "1 0 0 -1 0 {} cm", number(self.page_size.height)).unwrap();
+
+        for rule in section.column_rules() {
+            writeln!(content,
+                     "{} rg {} {} {} {} re f",
+                     pdf_color(DEFAULT_COLOR),
+                     number(rule.origin.x),
+                     number(rule.origin.y),
+                     number(rule.size.width),
+                     number(rule.size.height)).unwrap();
+        }
+
+        for frame in section.frames() {
+            if frame.style().content == ParagraphContent::Rule {
+                let (start, end) = render::rule_line(&frame.bounds(), section.writing_mode());
+                writeln!(content,
+                         "{} RG {} w {} {} m {} {} l S",
+                         pdf_color(DEFAULT_COLOR),
+                         number(RULE_THICKNESS),
+                         number(start.x),
+                         number(start.y),
+                         number(end.x),
+                         number(end.y)).unwrap();
+                continue
+            }
+
+            for line in frame.lines() {
+                for run in line.runs() {
+                    let formats = run.formatting();
+                    let positions = run.positions();
+
+                    if let (Some((_, url)), Some(position)) =
+                            (formats.iter().filter_map(|format| format.link()).next(),
+                             positions.first()) {
+                        let bounds = run.typographic_bounds();
+                        let rect = if section.writing_mode().is_vertical() {
+                            // Span the line across, and the run along it.
+                            let line_rect = line.typographic_bounding_rect();
+                            let top = line.origin.y + run.inline_offset();
+                            Rect::new(Point2D::new(line_rect.origin.x,
+                                                   self.page_size.height - top - bounds.width),
+                                      Size2D::new(line_rect.size.width, bounds.width))
+                        } else {
+                            let bottom = line.origin.y - position.y + bounds.descent;
+                            let origin = Point2D::new(line.origin.x + position.x,
+                                                      self.page_size.height - bottom);
+                            Rect::new(origin, Size2D::new(bounds.width,
+                                                          bounds.ascent + bounds.descent))
+                        };
+                        links.push(Link {
+                            rect,
+                            url: url.to_owned(),
+                        })
+                    }
+
+                    if let Some(image_id) = formats.iter().filter_map(Format::image).next() {
+                        if let Some(position) = positions.first() {
+                            let image_index = match self.add_image(section, image_id) {
+                                None => continue,
+                                Some(image_index) => image_index,
+                            };
+                            let size = self.images[image_index].size;
+                            writeln!(content,
+                                     "q {} 0 0 {} {} {} cm /Im{} Do Q",
+                                     size.width,
+                                     -(size.height as i64),
+                                     number(line.origin.x + position.x),
+                                     number(line.origin.y - position.y),
+                                     image_index).unwrap();
+                        }
+                        continue
+                    }
+
+                    let font = formats.iter()
+                                      .filter_map(Format::font)
+                                      .next()
+                                      .unwrap_or_else(Font::default_serif);
+                    let color = formats.iter()
+                                       .filter_map(Format::color)
+                                       .next()
+                                       .unwrap_or(DEFAULT_COLOR);
+
+                    content.push_str("q ");
+                    if color.a != 255 {
+                        write!(content, "/GS{} gs ", self.fill_alpha_index(color.a)).unwrap();
+                    }
+                    writeln!(content, "{} rg BT", pdf_color(color)).unwrap();
+
+                    let mut current_font_index = None;
+                    let glyphs = run.glyphs().into_iter().zip(run.glyph_strings());
+                    for ((glyph, string), position) in glyphs.zip(positions) {
+                        let (font_index, code) = self.glyph_code(&font, glyph, string);
+                        if current_font_index != Some(font_index) {
+                            writeln!(content, "/F{} {} Tf", font_index, number(font.size()))
+                                .unwrap();
+                            current_font_index = Some(font_index)
+                        }
+                        // Text space has Y pointing up, so flip it back, or turn it to point right
+                        // for rotated glyphs.
+                        let matrix = match run.glyph_orientation() {
+                            GlyphOrientation::Upright => "1 0 0 -1",
+                            GlyphOrientation::Rotated => "0 1 1 0",
+                        };
+                        writeln!(content,
+                                 "{} {} {} Tm <{:02x}> Tj",
+                                 matrix,
+                                 number(line.origin.x + position.x),
+                                 number(line.origin.y - position.y),
+                                 code).unwrap();
+                    }
+
+                    content.push_str("ET Q\n");
+                }
+            }
+        }
+
+        self.pages.push(Page {
+            content,
+            links,
+        })
+    }
+
+    /// Writes out the PDF file containing all the pages added so far.
+    pub fn finish(self) -> Vec<u8> {
+        let mut writer = ObjectWriter::new();
+        let catalog_id = writer.allocate_id();
+        let pages_id = writer.allocate_id();
+        let resources_id = writer.allocate_id();
+
+        // Fonts.
+        let mut font_ids = vec![];
+        for embedded_font in &self.fonts {
+            font_ids.push(writer.write_font(embedded_font));
+        }
+
+        // Images, with their alpha channels as soft masks.
+        let mut image_ids = vec![];
+        for image in &self.images {
+            let smask = image.alpha.as_ref().map(|alpha| {
+                let id = writer.allocate_id();
+                writer.write_stream(id,
+                                    &format!("/Type /XObject /Subtype /Image /Width {} \
+                                              /Height {} /ColorSpace /DeviceGray \
+                                              /BitsPerComponent 8",
+                                             image.size.width,
+                                             image.size.height),
+                                    alpha);
+                id
+            });
+            let id = writer.allocate_id();
+            let mut dictionary = format!("/Type /XObject /Subtype /Image /Width {} /Height {} \
+                                          /ColorSpace /DeviceRGB /BitsPerComponent 8",
+                                         image.size.width,
+                                         image.size.height);
+            if let Some(smask) = smask {
+                write!(dictionary, " /SMask {} 0 R", smask).unwrap();
+            }
+            writer.write_stream(id, &dictionary, &image.rgb);
+            image_ids.push(id);
+        }
+
+        // Every page shares one resource dictionary.
+        let mut resources = String::from("<<\n/Font <<");
+        for (font_index, font_id) in font_ids.iter().enumerate() {
+            write!(resources, " /F{} {} 0 R", font_index, font_id).unwrap();
+        }
+        resources.push_str(" >>\n/XObject <<");
+        for (image_index, image_id) in image_ids.iter().enumerate() {
+            write!(resources, " /Im{} {} 0 R", image_index, image_id).unwrap();
+        }
+        resources.push_str(" >>\n/ExtGState <<");
+        for (alpha_index, &alpha) in self.fill_alphas.iter().enumerate() {
+            write!(resources,
+                   " /GS{} << /ca {} >>",
+                   alpha_index,
+                   number(alpha as f32 / 255.0)).unwrap();
+        }
+        resources.push_str(" >>\n>>");
+        writer.write_object(resources_id, &resources);
+
+        // Pages.
+        let mut page_ids = vec![];
+        for page in &self.pages {
+            let content_id = writer.allocate_id();
+            writer.write_stream(content_id, "", page.content.as_bytes());
+
+            let mut annotations = String::new();
+            for link in &page.links {
+                let annotation_id = writer.allocate_id();
+                writer.write_object(annotation_id,
+                                    &format!("<< /Type /Annot /Subtype /Link /Rect [{} {} {} {}] \
+                                              /Border [0 0 0] /A << /S /URI /URI ({}) >> >>",
+                                             number(link.rect.origin.x),
+                                             number(link.rect.origin.y),
+                                             number(link.rect.max_x()),
+                                             number(link.rect.max_y()),
+                                             escape_string(&link.url)));
+                write!(annotations, " {} 0 R", annotation_id).unwrap();
+            }
+
+            let page_id = writer.allocate_id();
+            writer.write_object(page_id,
+                                &format!("<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] \
+                                          /Resources {} 0 R /Contents {} 0 R /Annots [{} ] >>",
+                                         pages_id,
+                                         number(self.page_size.width),
+                                         number(self.page_size.height),
+                                         resources_id,
+                                         content_id,
+                                         annotations));
+            page_ids.push(page_id);
+        }
+
+        let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+        writer.write_object(pages_id,
+                            &format!("<< /Type /Pages /Kids [{}] /Count {} >>",
+                                     kids.join(" "),
+                                     page_ids.len()));
+        writer.write_object(catalog_id,
+                            &format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id));
+
+        writer.finish(catalog_id)
+    }
+
+    /// Returns the index of the PDF font containing `glyph` of `font`'s face, and its code in
+    /// that font, adding it if necessary. `string` is the text that the glyph stands for, if it
+    /// starts a cluster.
+    fn glyph_code(&mut self, font: &Font, glyph: Glyph, string: String) -> (usize, u8) {
+        let key = (font.face_id(), glyph);
+        if let Some(&(font_index, code)) = self.glyph_codes.get(&key) {
+            let known_string = &mut self.fonts[font_index].strings[code as usize];
+            if known_string.is_empty() {
+                *known_string = string
+            }
+            return (font_index, code)
+        }
+
+        let font_index = match self.fonts.iter().rposition(|embedded_font| {
+            embedded_font.font.face_id() == font.face_id()
+        }) {
+            Some(font_index) if self.fonts[font_index].glyphs.len() < 256 => font_index,
+            _ => {
+                self.fonts.push(EmbeddedFont {
+                    font: font.clone(),
+                    glyphs: vec![],
+                    strings: vec![],
+                });
+                self.fonts.len() - 1
+            }
+        };
+
+        let embedded_font = &mut self.fonts[font_index];
+        let code = (font_index, embedded_font.glyphs.len() as u8);
+        embedded_font.glyphs.push(glyph);
+        embedded_font.strings.push(string);
+        self.glyph_codes.insert(key, code);
+        code
+    }
+
+    /// Adds image `image_id` with the pixels from the section's layout callbacks, if it hasn't
+    /// been added already, returning its index, or `None` if the callbacks don't provide them.
+    fn add_image(&mut self, section: &Section, image_id: u32) -> Option<usize> {
+        if let Some(&image_index) = self.image_indices.get(&image_id) {
+            return Some(image_index)
+        }
+
+        let callbacks = section.callbacks.as_ref()?;
+        let size = callbacks.get_image_size(image_id)?;
+        let pixels = callbacks.get_image_rgba(image_id)?;
+        if pixels.len() != size.width as usize * size.height as usize * 4 {
+            return None
+        }
+
+        let mut rgb = Vec::with_capacity(pixels.len() / 4 * 3);
+        let mut alpha = Vec::with_capacity(pixels.len() / 4);
+        for pixel in pixels.chunks(4) {
+            rgb.extend_from_slice(&pixel[0..3]);
+            alpha.push(pixel[3]);
+        }
+        let is_opaque = alpha.iter().all(|&alpha| alpha == 255);
+
+        self.images.push(EmbeddedImage {
+            size,
+            rgb,
+            alpha: if is_opaque { None } else { Some(alpha) },
+        });
+        self.image_indices.insert(image_id, self.images.len() - 1);
+        Some(self.images.len() - 1)
+    }
+
+    fn fill_alpha_index(&mut self, alpha: u8) -> usize {
+        match self.fill_alphas.iter().position(|&fill_alpha| fill_alpha == alpha) {
+            Some(index) => index,
+            None => {
+                self.fill_alphas.push(alpha);
+                self.fill_alphas.len() - 1
+            }
+        }
+    }
+}
+
+/// Accumulates numbered objects and their offsets for the cross-reference table.
+struct ObjectWriter {
+    bytes: Vec<u8>,
+    offsets: Vec<Option<usize>>,
+}
+
+impl ObjectWriter {
+    fn new() -> ObjectWriter {
+        ObjectWriter {
+            bytes: b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec(),
+            offsets: vec![],
+        }
+    }
+
+    fn allocate_id(&mut self) -> usize {
+        self.offsets.push(None);
+        self.offsets.len()
+    }
+
+    fn write_object(&mut self, id: usize, object: &str) {
+        self.offsets[id - 1] = Some(self.bytes.len());
+        self.bytes.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", id, object).as_bytes());
+    }
+
+    fn write_stream(&mut self, id: usize, dictionary: &str, data: &[u8]) {
+        self.offsets[id - 1] = Some(self.bytes.len());
+        let mut header = format!("{} 0 obj\n<< ", id);
+        if !dictionary.is_empty() {
+            header.push_str(dictionary);
+            header.push(' ');
+        }
+        writeln!(header, "/Length {} >>\nstream", data.len()).unwrap();
+        self.bytes.extend_from_slice(header.as_bytes());
+        self.bytes.extend_from_slice(data);
+        self.bytes.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    /// Writes `embedded_font` and its glyph procedures, and returns the ID of the font.
+    fn write_font(&mut self, embedded_font: &EmbeddedFont) -> usize {
+        let font = &embedded_font.font;
+        let scale = UNITS_PER_EM / font.size();
+
+        let mut char_procs = String::new();
+        let mut differences = String::new();
+        let mut widths = String::new();
+        let mut glyph_corners = vec![];
+        for (code, &glyph) in embedded_font.glyphs.iter().enumerate() {
+            let mut builder = GlyphProcBuilder::new(scale);
+            font.outline_glyph(glyph, &mut builder);
+            let width = number(font.glyph_advance(glyph) * scale);
+
+            let mut procedure = format!("{} 0 ", width);
+            match builder.bounds() {
+                None => procedure.push_str("0 0 0 0 d1\n"),
+                Some(bounds) => {
+                    writeln!(procedure,
+                             "{} {} {} {} d1",
+                             number(bounds.origin.x),
+                             number(bounds.origin.y),
+                             number(bounds.max_x()),
+                             number(bounds.max_y())).unwrap();
+                    procedure.push_str(&builder.path);
+                    procedure.push_str("f\n");
+                    glyph_corners.push(bounds.origin);
+                    glyph_corners.push(bounds.bottom_right());
+                }
+            }
+
+            let proc_id = self.allocate_id();
+            self.write_stream(proc_id, "", procedure.as_bytes());
+            write!(char_procs, " /g{} {} 0 R", code, proc_id).unwrap();
+            write!(differences, " /g{}", code).unwrap();
+            write!(widths, " {}", width).unwrap();
+        }
+
+        let to_unicode_id = self.allocate_id();
+        self.write_stream(to_unicode_id, "", to_unicode_cmap(&embedded_font.strings).as_bytes());
+
+        let font_bounds = Rect::from_points(glyph_corners);
+        let id = self.allocate_id();
+        self.write_object(id,
+                          &format!("<< /Type /Font /Subtype /Type3 /FontBBox [{} {} {} {}] \
+                                    /FontMatrix [{} 0 0 {} 0 0] /CharProcs <<{} >> \
+                                    /Encoding << /Type /Encoding /Differences [0{}] >> \
+                                    /FirstChar 0 /LastChar {} /Widths [{}] /Resources << >> \
+                                    /ToUnicode {} 0 R >>",
+                                   number(font_bounds.origin.x),
+                                   number(font_bounds.origin.y),
+                                   number(font_bounds.max_x()),
+                                   number(font_bounds.max_y()),
+                                   1.0 / UNITS_PER_EM,
+                                   1.0 / UNITS_PER_EM,
+                                   char_procs,
+                                   differences,
+                                   embedded_font.glyphs.len() - 1,
+                                   widths,
+                                   to_unicode_id));
+        id
+    }
+
+    fn finish(mut self, catalog_id: usize) -> Vec<u8> {
+        let xref_offset = self.bytes.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            let offset = offset.expect("ObjectWriter::finish(): Object never written!");
+            writeln!(xref, "{:010} 00000 n ", offset).unwrap();
+        }
+        write!(xref,
+               "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+               self.offsets.len() + 1,
+               catalog_id,
+               xref_offset).unwrap();
+        self.bytes.extend_from_slice(xref.as_bytes());
+        self.bytes
+    }
+}
+
+/// Converts a glyph outline into the path operators of a Type 3 glyph procedure.
+struct GlyphProcBuilder {
+    path: String,
+    points: Vec<Point2D<f32>>,
+    scale: f32,
+    current_point: (f32, f32),
+}
+
+impl GlyphProcBuilder {
+    fn new(scale: f32) -> GlyphProcBuilder {
+        GlyphProcBuilder {
+            path: String::new(),
+            points: vec![],
+            scale,
+            current_point: (0.0, 0.0),
+        }
+    }
+
+    fn push_operator(&mut self, operator: &str, points: &[(f32, f32)]) {
+        for &(x, y) in points {
+            let (x, y) = (x * self.scale, y * self.scale);
+            write!(self.path, "{} {} ", number(x), number(y)).unwrap();
+            self.points.push(Point2D::new(x, y));
+        }
+        self.path.push_str(operator);
+        self.path.push('\n');
+        if let Some(&point) = points.last() {
+            self.current_point = point
+        }
+    }
+
+    /// Returns the bounding box of the outline's points, or `None` if it's empty.
+    fn bounds(&self) -> Option<Rect<f32>> {
+        if self.points.is_empty() {
+            None
+        } else {
+            Some(Rect::from_points(&self.points))
+        }
+    }
+}
+
+impl OutlineBuilder for GlyphProcBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.push_operator("m", &[(x, y)])
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.push_operator("l", &[(x, y)])
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        // PDF has no quadratic curves, so raise the degree.
+        let (x0, y0) = self.current_point;
+        let control_1 = (x0 + (x1 - x0) * 2.0 / 3.0, y0 + (y1 - y0) * 2.0 / 3.0);
+        let control_2 = (x + (x1 - x) * 2.0 / 3.0, y + (y1 - y) * 2.0 / 3.0);
+        self.push_operator("c", &[control_1, control_2, (x, y)])
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.push_operator("c", &[(x1, y1), (x2, y2), (x, y)])
+    }
+
+    fn close(&mut self) {
+        self.push_operator("h", &[])
+    }
+}
+
+/// Returns a CMap mapping each one-byte code to `strings[code]`, so that text can be extracted
+/// and searched. Codes with empty strings are left unmapped.
+fn to_unicode_cmap(strings: &[String]) -> String {
+    let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+                                 /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) \
+                                 /Supplement 0 >> def\n/CMapName /Adobe-Identity-UCS def\n\
+                                 /CMapType 2 def\n1 begincodespacerange\n<00> <ff>\n\
+                                 endcodespacerange\n");
+    let mappings: Vec<_> = strings.iter().enumerate().filter(|&(_, string)| {
+        !string.is_empty()
+    }).collect();
+    // A `bfchar` section can hold at most 100 mappings.
+    for chunk in mappings.chunks(100) {
+        writeln!(cmap, "{} beginbfchar", chunk.len()).unwrap();
+        for &(code, string) in chunk {
+            write!(cmap, "<{:02x}> <", code).unwrap();
+            for unit in string.encode_utf16() {
+                write!(cmap, "{:04x}", unit).unwrap();
+            }
+            cmap.push_str(">\n");
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend");
+    cmap
+}
+
+fn pdf_color(color: Color) -> String {
+    format!("{} {} {}", number(color.r_f32()), number(color.g_f32()), number(color.b_f32()))
+}
+
+fn escape_string(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for ch in string.chars() {
+        match ch {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(ch)
+            }
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,1768 @@
+// pilcrow/src/portable.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The pure-Rust layout backend, used wherever Core Text is unavailable.
+//!
+//! Shaping is done with `rustybuzz` and font metrics come from `ttf-parser`. The pipeline mirrors
+//! Core Text: a `Framesetter` shapes each paragraph the first time it's laid out, and
+//! `layout_in_rect()` breaks the shaped paragraphs into lines to produce frames.
+
+use euclid::{Point2D, Rect, Size2D, Vector2D};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator};
+use rayon::iter::ParallelIterator;
+use rustybuzz::{Direction, UnicodeBuffer};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use ttf_parser::{GlyphId, OutlineBuilder};
+use unicode_bidi::{self, BidiClass, BidiInfo, Level};
+
+use attributed_string::AttributedString;
+use font::{self, FONT_WEIGHT_BOLD, FONT_WEIGHT_NORMAL, FontCollection, FontFace, FontStyle};
+use hyphenation::Hyphenator;
+use format::Format;
+use segmentation;
+use {BaseDirection, ColumnFlow, Document, DocumentStyle, GlyphOrientation, IntrinsicWidths};
+use {LayoutCallbacks, LineHeight, Paragraph, ParagraphStyle, Section, TextAlignment};
+use {TextDirection, TypographicBounds, WritingMode, cluster_strings};
+
+pub type Glyph = u16;
+
+const OBJECT_REPLACEMENT_CHARACTER: char = '\u{fffc}';
+
+/// How far apart the edges of two grapheme clusters can be for them to count as next to each
+/// other, allowing for rounding in the sums of advances.
+const CLUSTER_ADJACENCY_EPSILON: f32 = 0.01;
+
+static DEFAULT_SERIF_PATHS: [&str; 7] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSerif.ttf",
+    "/usr/share/fonts/TTF/DejaVuSerif.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSerif.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSerif-Regular.ttf",
+    "/usr/share/fonts/liberation/LiberationSerif-Regular.ttf",
+    "/System/Library/Fonts/Times.ttc",
+    "C:\\Windows\\Fonts\\times.ttf",
+];
+
+static DEFAULT_MONOSPACE_PATHS: [&str; 7] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationMono-Regular.ttf",
+    "/usr/share/fonts/liberation/LiberationMono-Regular.ttf",
+    "/System/Library/Fonts/Menlo.ttc",
+    "C:\\Windows\\Fonts\\consola.ttf",
+];
+
+/// The faces to use when none of the default paths can be loaded.
+static BUNDLED_SERIF_FONT: &[u8] = include_bytes!("../fonts/DejaVuSerif.ttf");
+static BUNDLED_MONOSPACE_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+lazy_static! {
+    static ref DEFAULT_SERIF_FONT: Font =
+        default_font(&DEFAULT_SERIF_PATHS, BUNDLED_SERIF_FONT, 16.0);
+    static ref DEFAULT_MONOSPACE_FONT: Font =
+        default_font(&DEFAULT_MONOSPACE_PATHS, BUNDLED_MONOSPACE_FONT, 12.0);
+}
+
+pub struct Framesetter {
+    framesetters: Vec<ParagraphFramesetter>,
+    document_style: DocumentStyle,
+}
+
+impl Framesetter {
+    /// Makes a framesetter for `document`. Paragraphs are shaped the first time they're laid
+    /// out.
+    pub fn new(document: &Document) -> Framesetter {
+        Framesetter {
+            framesetters: document.paragraphs().par_iter().map(|paragraph| {
+                ParagraphFramesetter::new(paragraph)
+            }).collect(),
+            document_style: document.style.clone(),
+        }
+    }
+
+    /// Brings this framesetter up to date with `document`, keeping the shaping of the paragraphs
+    /// that weren't edited since it was made from it. Nothing is kept if the document style
+    /// changed.
+    pub fn update(&mut self, document: &Document) {
+        if document.style != self.document_style {
+            *self = Framesetter::new(document);
+            return
+        }
+
+        let mut old_framesetters: HashMap<usize, ParagraphFramesetter> =
+            self.framesetters.drain(..).map(|framesetter| {
+                (framesetter.revision, framesetter)
+            }).collect();
+        let framesetters: Vec<_> = document.paragraphs().iter().map(|paragraph| {
+            old_framesetters.remove(&paragraph.revision)
+        }).collect();
+        self.framesetters = framesetters.into_par_iter().zip(document.paragraphs().par_iter())
+                                                        .map(|(framesetter, paragraph)| {
+            framesetter.unwrap_or_else(|| ParagraphFramesetter::new(paragraph))
+        }).collect();
+    }
+
+    /// Lays out the document in `rect`, inset by the document margin. With more than one column,
+    /// paragraphs flow down each column in turn, and the last column extends past the bottom of
+    /// `rect` as needed.
+    pub fn layout_in_rect(&self, rect: &Rect<f32>, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                          -> Section {
+        let rect = rect.inner_rect(self.document_style.margin);
+        let mut sections = self.flow(&rect, false, callbacks);
+        sections.pop().expect("Framesetter::layout_in_rect(): Flow didn't produce a section!")
+    }
+
+    /// Lays out the document across pages of `page_size`, inset by the document margin,
+    /// returning one section per page in that page's coordinates. Paragraphs that overflow a
+    /// page or column are split between lines, and a paragraph's top margin is dropped when it
+    /// starts a page or column.
+    ///
+    /// The keep-with-next, keep-together, and widow and orphan settings of each paragraph's style
+    /// are honored where possible; when they can't all be met even in an empty column, they're
+    /// ignored for that column.
+    pub fn layout_in_pages(&self,
+                           page_size: &Size2D<f32>,
+                           callbacks: Option<Box<dyn LayoutCallbacks>>)
+                           -> Vec<Section> {
+        let rect = Rect::new(Point2D::zero(), *page_size).inner_rect(self.document_style.margin);
+        self.flow(&rect, true, callbacks)
+    }
+
+    /// Returns the range of widths that the document can be laid out in, including the document
+    /// margin, as if it had one column. Widths are measured along lines, so they're heights in
+    /// vertical writing modes.
+    pub fn intrinsic_widths(&self, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                            -> IntrinsicWidths {
+        let callbacks = callbacks.as_deref();
+        let writing_mode = self.document_style.writing_mode;
+        let widths = (0..self.framesetters.len()).into_par_iter().map(|paragraph_index| {
+            self.measure_intrinsic_widths(paragraph_index, callbacks)
+        }).reduce(IntrinsicWidths::zero, IntrinsicWidths::union);
+        widths.outset(writing_mode.inline_margin(&self.document_style.margin))
+    }
+
+    /// Returns the range of widths that paragraph `paragraph_index` can be laid out in,
+    /// including its margins.
+    pub fn paragraph_intrinsic_widths(&self,
+                                      paragraph_index: usize,
+                                      callbacks: Option<Box<dyn LayoutCallbacks>>)
+                                      -> IntrinsicWidths {
+        self.measure_intrinsic_widths(paragraph_index, callbacks.as_deref())
+    }
+
+    /// Returns the extent, in the direction that lines stack in, of the document laid out in
+    /// `width` as if it had one column, including the document margin and paragraph margins.
+    pub fn height_for_width(&self, width: f32, callbacks: Option<Box<dyn LayoutCallbacks>>)
+                            -> f32 {
+        let callbacks = callbacks.as_deref();
+        let writing_mode = self.document_style.writing_mode;
+        let width = (width - writing_mode.inline_margin(&self.document_style.margin)).max(0.0);
+        let height: f32 = (0..self.framesetters.len()).into_par_iter().map(|paragraph_index| {
+            self.measure_height(paragraph_index, width, callbacks)
+        }).sum();
+        height + writing_mode.block_margin(&self.document_style.margin)
+    }
+
+    /// Returns the extent, in the direction that lines stack in, of paragraph `paragraph_index`
+    /// laid out in `width`, including its margins.
+    pub fn paragraph_height_for_width(&self,
+                                      paragraph_index: usize,
+                                      width: f32,
+                                      callbacks: Option<Box<dyn LayoutCallbacks>>)
+                                      -> f32 {
+        self.measure_height(paragraph_index, width, callbacks.as_deref())
+    }
+
+    fn measure_intrinsic_widths(&self,
+                                paragraph_index: usize,
+                                callbacks: Option<&dyn LayoutCallbacks>)
+                                -> IntrinsicWidths {
+        let paragraph_framesetter = &self.framesetters[paragraph_index];
+        let style = &paragraph_framesetter.style;
+        let shaped_paragraph = paragraph_framesetter.shaped_paragraph(&self.document_style);
+        shaped_paragraph.intrinsic_widths(style, callbacks).outset(style.margin.horizontal())
+    }
+
+    fn measure_height(&self,
+                      paragraph_index: usize,
+                      width: f32,
+                      callbacks: Option<&dyn LayoutCallbacks>)
+                      -> f32 {
+        let paragraph_framesetter = &self.framesetters[paragraph_index];
+        let style = &paragraph_framesetter.style;
+        let shaped_paragraph = paragraph_framesetter.shaped_paragraph(&self.document_style);
+        let lines = shaped_paragraph.break_lines(width - style.margin.horizontal(),
+                                                 style,
+                                                 callbacks);
+        lines_height(&lines) + style.margin.vertical()
+    }
+
+    /// Flows the paragraphs down the columns of `rect`, continuing on new pages when `paginate`
+    /// is set.
+    fn flow(&self,
+            rect: &Rect<f32>,
+            paginate: bool,
+            callbacks: Option<Box<dyn LayoutCallbacks>>)
+            -> Vec<Section> {
+        let callbacks: Option<Arc<dyn LayoutCallbacks>> = callbacks.map(Arc::from);
+        let mut flow = ColumnFlow::new(rect, &self.document_style, paginate, callbacks);
+        self.flow_paragraphs(0..self.framesetters.len(), &mut flow);
+        flow.finish()
+    }
+
+    /// Places the paragraphs in `paragraph_range` at the current position of `flow`.
+    pub(crate) fn flow_paragraphs(&self, paragraph_range: Range<usize>, flow: &mut ColumnFlow) {
+        // Keep-with-next paragraphs need the lines of the paragraphs after them.
+        let mut lines_end = paragraph_range.end;
+        while lines_end > paragraph_range.start && lines_end < self.framesetters.len() &&
+                self.framesetters[lines_end - 1].style.keep_with_next {
+            lines_end += 1
+        }
+        let paragraph_lines = self.break_paragraphs(paragraph_range.start..lines_end,
+                                                    flow.column_width(),
+                                                    flow.callbacks.as_deref());
+
+        for paragraph_index in paragraph_range.clone() {
+            let lines = &paragraph_lines[paragraph_index - paragraph_range.start];
+            let style = &self.framesetters[paragraph_index].style;
+
+            // Move to the next column if the lines that must stay together don't fit in this one
+            // but would in an empty one.
+            let kept_height =
+                self.kept_height(&paragraph_lines[(paragraph_index - paragraph_range.start)..],
+                                 paragraph_index);
+            if !flow.is_column_empty() && flow.y + style.margin.top + kept_height > flow.max_y() &&
+                    kept_height <= flow.column_height() {
+                flow.next_column()
+            } else {
+                flow.y += style.margin.top
+            }
+
+            let mut remaining_lines = &lines[..];
+            loop {
+                let fitting_line_count = lines_fitting_in_height(remaining_lines,
+                                                                 flow.max_y() - flow.y);
+                let mut line_count = ::lines_to_place(style,
+                                                      remaining_lines.len(),
+                                                      fitting_line_count);
+                if line_count == 0 && !remaining_lines.is_empty() {
+                    if !flow.is_column_empty() {
+                        flow.next_column();
+                        continue
+                    }
+
+                    // Nothing satisfies the style in an empty column, so take what fits, or let
+                    // a line that's taller than the column overflow.
+                    line_count = fitting_line_count.max(1)
+                }
+
+                let frame = self.create_frame(paragraph_index,
+                                              &remaining_lines[..line_count],
+                                              flow);
+                flow.push_frame(frame);
+
+                remaining_lines = &remaining_lines[line_count..];
+                if remaining_lines.is_empty() {
+                    break
+                }
+                flow.next_column();
+            }
+
+            flow.y += style.margin.bottom;
+        }
+    }
+
+    /// Returns the height of the lines that must go in the same column as the start of paragraph
+    /// `paragraph_index`: all of it if it's keep-together or keep-with-next, chained through
+    /// following keep-with-next paragraphs, and then the first orphan lines of the next.
+    /// `paragraph_lines` starts with the lines of that paragraph.
+    fn kept_height(&self, paragraph_lines: &[Vec<LineLayout>], paragraph_index: usize) -> f32 {
+        let mut height = 0.0;
+        for (offset, lines) in paragraph_lines.iter().enumerate() {
+            let index = paragraph_index + offset;
+            let style = &self.framesetters[index].style;
+            if index > paragraph_index {
+                height += style.margin.top
+            }
+
+            let is_last = !style.keep_with_next || offset + 1 == paragraph_lines.len();
+            let kept_line_count = if style.keep_together || !is_last {
+                lines.len()
+            } else {
+                lines.len().min(style.min_orphan_lines.max(1))
+            };
+            height += lines_height(&lines[..kept_line_count]);
+            if is_last {
+                break
+            }
+            height += style.margin.bottom;
+        }
+        height
+    }
+
+    /// Breaks the paragraphs in `paragraph_range` into lines that fit in `width`, less each
+    /// paragraph's margins.
+    fn break_paragraphs(&self,
+                        paragraph_range: Range<usize>,
+                        width: f32,
+                        callbacks: Option<&dyn LayoutCallbacks>)
+                        -> Vec<Vec<LineLayout>> {
+        self.framesetters[paragraph_range].par_iter().map(|paragraph_framesetter| {
+            let width = width - paragraph_framesetter.style.margin.horizontal();
+            let shaped_paragraph = paragraph_framesetter.shaped_paragraph(&self.document_style);
+            shaped_paragraph.break_lines(width, &paragraph_framesetter.style, callbacks)
+        }).collect()
+    }
+
+    /// Creates a frame holding `lines` of paragraph `paragraph_index`, which may be a subset of
+    /// its lines, at the current position of `flow`.
+    fn create_frame(&self, paragraph_index: usize, lines: &[LineLayout], flow: &ColumnFlow)
+                    -> Frame {
+        let rect = flow.column();
+        let style = &self.framesetters[paragraph_index].style;
+        let char_range = match (lines.first(), lines.last()) {
+            (Some(first_line), Some(last_line)) => {
+                first_line.char_range.start..last_line.char_range.end
+            }
+            _ => 0..0,
+        };
+
+        // Move the lines up so that the first one starts at the top of the frame.
+        let top = lines.first().map_or(0.0, LineLayout::top);
+        let lines = lines.iter().map(|line| {
+            Arc::new(LineLayout {
+                baseline: line.baseline - top,
+                ..(*line).clone()
+            })
+        }).collect();
+
+        Frame {
+            lines,
+            style: style.clone(),
+            virtual_size: Size2D::new(rect.size.width - style.margin.horizontal(),
+                                      rect.size.height),
+            origin: Point2D::new(rect.origin.x + style.margin.left, flow.y),
+            page_rect: flow.page_rect,
+            writing_mode: flow.writing_mode,
+            char_range,
+            paragraph_index,
+        }
+    }
+}
+
+struct ParagraphFramesetter {
+    attributed_string: AttributedString,
+    /// The paragraph shaped, the first time it's laid out.
+    shaped_paragraph: OnceLock<ShapedParagraph>,
+    style: ParagraphStyle,
+    /// The revision of the paragraph that this was made from.
+    revision: usize,
+}
+
+impl ParagraphFramesetter {
+    fn new(paragraph: &Paragraph) -> ParagraphFramesetter {
+        ParagraphFramesetter {
+            attributed_string: paragraph.attributed_string.clone(),
+            shaped_paragraph: OnceLock::new(),
+            style: paragraph.style.clone(),
+            revision: paragraph.revision,
+        }
+    }
+
+    fn shaped_paragraph(&self, document_style: &DocumentStyle) -> &ShapedParagraph {
+        self.shaped_paragraph.get_or_init(|| {
+            ShapedParagraph::new(&self.attributed_string,
+                                 &document_style.fallback_fonts,
+                                 &self.style,
+                                 document_style.writing_mode)
+        })
+    }
+}
+
+/// A paragraph that has been shaped into glyphs, but not yet broken into lines.
+struct ShapedParagraph {
+    runs: Vec<ShapedRun>,
+    chars: Vec<char>,
+    /// Whether each character, and the end of the text, begins an extended grapheme cluster.
+    grapheme_starts: Arc<Vec<bool>>,
+    /// The bidi embedding level of each character, before lines are reordered.
+    levels: Vec<u8>,
+    /// The first character and embedding level of each bidi paragraph. Paragraph separators
+    /// start new ones.
+    paragraph_levels: Vec<(usize, u8)>,
+    char_len: usize,
+    writing_mode: WritingMode,
+}
+
+struct ShapedRun {
+    char_range: Range<usize>,
+    formats: Vec<Format>,
+    orientation: GlyphOrientation,
+    content: ShapedRunContent,
+}
+
+enum ShapedRunContent {
+    Text(Font, Vec<ShapedGlyph>),
+    Image(u32),
+}
+
+#[derive(Clone, Copy)]
+struct ShapedGlyph {
+    id: Glyph,
+    /// The paragraph-relative index of the first character in this glyph's cluster.
+    cluster: usize,
+    /// The advance along the line, which is downward for upright glyphs in vertical lines.
+    advance: f32,
+    /// The offset of the glyph origin from the pen, with Y up. For upright glyphs in vertical
+    /// lines, the pen is at the top center of the glyph.
+    offset: Vector2D<f32>,
+}
+
+impl ShapedParagraph {
+    fn new(attributed_string: &AttributedString,
+           fallback_fonts: &[Font],
+           style: &ParagraphStyle,
+           writing_mode: WritingMode)
+           -> ShapedParagraph {
+        let string = attributed_string.string();
+        let chars: Vec<char> = string.chars().collect();
+        let mut grapheme_starts = vec![false; chars.len() + 1];
+        for char_index in segmentation::grapheme_boundaries(&chars) {
+            grapheme_starts[char_index] = true
+        }
+
+        // Resolve the embedding levels, which come back per byte.
+        let base_level = match style.base_direction {
+            BaseDirection::Auto => None,
+            BaseDirection::LeftToRight => Some(Level::ltr()),
+            BaseDirection::RightToLeft => Some(Level::rtl()),
+        };
+        let bidi_info = BidiInfo::new(string, base_level);
+        let mut char_indices = vec![0; string.len() + 1];
+        for (char_index, (byte_index, _)) in string.char_indices().enumerate() {
+            char_indices[byte_index] = char_index
+        }
+        let levels: Vec<u8> = string.char_indices().map(|(byte_index, _)| {
+            bidi_info.levels[byte_index].number()
+        }).collect();
+        let paragraph_levels = bidi_info.paragraphs.iter().map(|paragraph| {
+            (char_indices[paragraph.range.start], paragraph.level.number())
+        }).collect();
+
+        // Turn each grapheme cluster of vertical lines by its first character.
+        let mut orientations = vec![GlyphOrientation::Upright; chars.len()];
+        if writing_mode.is_vertical() {
+            let mut orientation = GlyphOrientation::Upright;
+            for (char_index, &ch) in chars.iter().enumerate() {
+                if grapheme_starts[char_index] {
+                    orientation = style.text_orientation.glyph_orientation(ch)
+                }
+                orientations[char_index] = orientation
+            }
+        }
+
+        let mut runs = vec![];
+        for (char_range, _, formats) in attributed_string.spans() {
+            if let Some(image_id) = formats.iter().rev().filter_map(Format::image).next() {
+                for char_index in char_range.clone() {
+                    if chars[char_index] != OBJECT_REPLACEMENT_CHARACTER {
+                        continue
+                    }
+                    runs.push(ShapedRun {
+                        char_range: char_index..(char_index + 1),
+                        formats: formats.to_vec(),
+                        orientation: GlyphOrientation::Upright,
+                        content: ShapedRunContent::Image(image_id),
+                    })
+                }
+                continue
+            }
+
+            let font = formats.iter()
+                              .rev()
+                              .filter_map(Format::font)
+                              .next()
+                              .unwrap_or_else(Font::default_serif);
+            let pieces = select_fonts(&font, fallback_fonts, &chars, &grapheme_starts, char_range);
+            for (run_font, font_range) in pieces {
+                // Report the font that was actually used, so that clients can find the face.
+                let mut formats = formats.to_vec();
+                if run_font != font {
+                    formats.retain(|format| format.font().is_none());
+                    formats.push(Format::from_font(run_font.clone()));
+                }
+
+                // Shape each stretch of one direction and orientation separately.
+                let run_ranges = uniform_runs(font_range, |char_index| {
+                    (levels[char_index], orientations[char_index])
+                });
+                for run_range in run_ranges {
+                    let orientation = orientations[run_range.start];
+                    let direction = shaping_direction(writing_mode,
+                                                      orientation,
+                                                      levels[run_range.start] % 2 == 1);
+                    let glyphs = shape(&run_font,
+                                       &chars[run_range.clone()],
+                                       run_range.start,
+                                       direction);
+                    runs.push(ShapedRun {
+                        char_range: run_range,
+                        formats: formats.clone(),
+                        orientation,
+                        content: ShapedRunContent::Text(run_font.clone(), glyphs),
+                    })
+                }
+            }
+        }
+
+        ShapedParagraph {
+            char_len: chars.len(),
+            runs,
+            chars,
+            grapheme_starts: Arc::new(grapheme_starts),
+            levels,
+            paragraph_levels,
+            writing_mode,
+        }
+    }
+
+    /// Returns the embedding level of the bidi paragraph containing the character at
+    /// `char_index`.
+    fn paragraph_level(&self, char_index: usize) -> u8 {
+        self.paragraph_levels.iter()
+                             .take_while(|&&(start, _)| start <= char_index)
+                             .last()
+                             .map_or(0, |&(_, level)| level)
+    }
+
+    /// Returns the embedding level of each character of the line `char_range`, with whitespace
+    /// at its end and before separators reset to the paragraph level (rule L1 of UAX #9).
+    fn line_levels(&self, char_range: Range<usize>) -> Vec<u8> {
+        let paragraph_level = self.paragraph_level(char_range.start);
+        let mut levels = self.levels[char_range.clone()].to_vec();
+        let mut resetting = true;
+        for char_index in char_range.clone().rev() {
+            let level = &mut levels[char_index - char_range.start];
+            match unicode_bidi::bidi_class(self.chars[char_index]) {
+                BidiClass::B | BidiClass::S => {
+                    *level = paragraph_level;
+                    resetting = true
+                }
+                BidiClass::WS | BidiClass::FSI | BidiClass::LRI | BidiClass::RLI |
+                BidiClass::PDI | BidiClass::BN | BidiClass::LRE | BidiClass::RLE |
+                BidiClass::LRO | BidiClass::RLO | BidiClass::PDF if resetting => {
+                    *level = paragraph_level
+                }
+                _ => resetting = false,
+            }
+        }
+        levels
+    }
+
+    /// Breaks this paragraph into lines no wider than `max_width`, except where a single
+    /// unbreakable cluster is wider than that.
+    fn break_lines(&self,
+                   width: f32,
+                   style: &ParagraphStyle,
+                   callbacks: Option<&dyn LayoutCallbacks>)
+                   -> Vec<LineLayout> {
+        let mut advances = self.char_advances(callbacks);
+        let mut prefix_widths = Vec::with_capacity(advances.len() + 1);
+        prefix_widths.push(0.0);
+        for advance in &advances {
+            let last_width = *prefix_widths.last().unwrap();
+            prefix_widths.push(last_width + advance);
+        }
+        let has_tabs = self.chars.contains(&'\t');
+        let hyphenator = match style.language {
+            Some(ref language) if style.hyphenate => Hyphenator::for_language(language),
+            _ => None,
+        };
+
+        let opportunities = segmentation::line_break_opportunities(&self.chars);
+        let mut lines = vec![];
+        let mut line_start = 0;
+        let mut top = 0.0;
+        let mut opportunity_index = 0;
+        while line_start < self.char_len {
+            let indent = if line_start == 0 {
+                style.first_line_indent
+            } else {
+                style.hanging_indent
+            };
+            let max_width = (width - indent).max(0.0);
+
+            // Tabs advance to stops measured from the margin, so they depend on where the line
+            // starts.
+            if has_tabs {
+                self.set_tab_advances(&mut advances, line_start, indent, style);
+                for index in line_start..self.char_len {
+                    prefix_widths[index + 1] = prefix_widths[index] + advances[index]
+                }
+            }
+
+            while opportunities[opportunity_index].char_index <= line_start {
+                opportunity_index += 1
+            }
+
+            // Take the furthest break opportunity that fits, stopping at the first mandatory one.
+            let mut line_end = None;
+            for opportunity in &opportunities[opportunity_index..] {
+                let trimmed_end = self.trim_trailing_whitespace(line_start..opportunity.char_index);
+                let fits = prefix_widths[trimmed_end] - prefix_widths[line_start] <= max_width;
+                if fits || line_end.is_none() {
+                    line_end = Some(opportunity.char_index)
+                }
+                if !fits || opportunity.mandatory {
+                    break
+                }
+            }
+            let mut line_end = line_end.unwrap();
+            let trimmed_end = self.trim_trailing_whitespace(line_start..line_end);
+            let unbroken_width = prefix_widths[trimmed_end] - prefix_widths[line_start];
+            let overflows = unbroken_width > max_width;
+
+            // Hyphenate the word that overflows the line by itself, or the word after the line
+            // if the line leaves more than the hyphenation zone unused.
+            let mut hyphen_glyphs = None;
+            if let Some(ref hyphenator) = hyphenator {
+                let ends_paragraph_or_line = line_end == self.char_len ||
+                    is_hard_line_break(self.chars[line_end - 1]);
+                let word_start = if overflows {
+                    Some(line_start)
+                } else if !ends_paragraph_or_line &&
+                        max_width - unbroken_width > style.hyphenation_zone {
+                    Some(line_end)
+                } else {
+                    None
+                };
+                let hyphenation = word_start.and_then(|word_start| {
+                    self.hyphenate_word(hyphenator,
+                                        word_start,
+                                        line_start,
+                                        max_width,
+                                        &prefix_widths)
+                });
+                if let Some((hyphen_index, glyphs)) = hyphenation {
+                    line_end = hyphen_index;
+                    hyphen_glyphs = Some(glyphs)
+                }
+            }
+
+            // If even the first opportunity overflows, break between grapheme clusters instead.
+            if overflows && hyphen_glyphs.is_none() {
+                line_end = self.next_grapheme_start(line_start);
+                while line_end < trimmed_end {
+                    let next_end = self.next_grapheme_start(line_end);
+                    if prefix_widths[next_end] - prefix_widths[line_start] > max_width {
+                        break
+                    }
+                    line_end = next_end
+                }
+            }
+            let hyphen_glyphs = hyphen_glyphs.unwrap_or_default();
+            let hyphen_width: f32 = hyphen_glyphs.iter().map(|glyph| glyph.advance).sum();
+
+            // Justify by widening the spaces, or align by moving the line over.
+            let trimmed_end = self.trim_trailing_whitespace(line_start..line_end);
+            let line_width = prefix_widths[trimmed_end] - prefix_widths[line_start] + hyphen_width;
+            let leftover_width = (max_width - line_width).max(0.0);
+            let is_last_line = line_end == self.char_len ||
+                is_hard_line_break(self.chars[line_end - 1]);
+            let alignment = TextAlignment::for_line(style, is_last_line);
+            let space_count = self.chars[line_start..trimmed_end].iter()
+                                                                 .filter(|&&ch| is_stretchable(ch))
+                                                                 .count();
+            let space_stretch = if alignment == TextAlignment::Justify && space_count > 0 {
+                leftover_width / space_count as f32
+            } else {
+                0.0
+            };
+
+            let mut line = self.build_line(line_start..line_end,
+                                           &advances,
+                                           space_stretch,
+                                           &hyphen_glyphs,
+                                           callbacks);
+
+            // Lines start, and are indented, on the right in right-to-left paragraphs.
+            let direction = if self.paragraph_level(line_start) % 2 == 1 {
+                TextDirection::RightToLeft
+            } else {
+                TextDirection::LeftToRight
+            };
+            // Justified lines already fill the width, so there's nothing left to flush them by.
+            let flush_width = if space_stretch > 0.0 { 0.0 } else { leftover_width };
+            line.inline_offset = flush_width * alignment.flush_factor(direction);
+            match direction {
+                TextDirection::LeftToRight => line.inline_offset += indent,
+                TextDirection::RightToLeft => {
+                    // Trailing whitespace goes at the left of right-to-left lines, so hang it
+                    // past the margin there.
+                    line.inline_offset -= prefix_widths[line_end] - prefix_widths[trimmed_end]
+                }
+            }
+
+            // Split the difference between the line height and the fonts' above and below.
+            let natural_height = line.bounds.ascent + line.bounds.descent + line.bounds.leading;
+            line.half_spacing = (LineHeight::resolve(style, natural_height) - natural_height) * 0.5;
+            line.baseline = top + line.half_spacing + line.bounds.ascent;
+            top = line.bottom() + line.bounds.leading;
+            lines.push(line);
+
+            line_start = line_end;
+        }
+        lines
+    }
+
+    /// Returns the range of widths that this paragraph can be broken into lines in, including
+    /// indents.
+    fn intrinsic_widths(&self, style: &ParagraphStyle, callbacks: Option<&dyn LayoutCallbacks>)
+                        -> IntrinsicWidths {
+        let mut advances = self.char_advances(callbacks);
+        let has_tabs = self.chars.contains(&'\t');
+        let opportunities = segmentation::line_break_opportunities(&self.chars);
+
+        // Measures `char_range`, less trailing whitespace, as if it started a line.
+        let mut measure = |char_range: Range<usize>| {
+            let indent = if char_range.start == 0 {
+                style.first_line_indent
+            } else {
+                style.hanging_indent
+            };
+            if has_tabs {
+                self.set_tab_advances(&mut advances, char_range.start, indent, style)
+            }
+            let trimmed_end = self.trim_trailing_whitespace(char_range.clone());
+            indent + advances[char_range.start..trimmed_end].iter().sum::<f32>()
+        };
+
+        // Every line break opportunity is taken at the min-content width, and only the mandatory
+        // ones are at the max-content width.
+        let mut widths = IntrinsicWidths::zero();
+        let mut segment_start = 0;
+        for opportunity in &opportunities {
+            let segment_width = measure(segment_start..opportunity.char_index);
+            widths.min_content = widths.min_content.max(segment_width);
+            segment_start = opportunity.char_index
+        }
+        let mut line_start = 0;
+        for opportunity in opportunities.iter().filter(|opportunity| opportunity.mandatory) {
+            let line_width = measure(line_start..opportunity.char_index);
+            widths.max_content = widths.max_content.max(line_width);
+            line_start = opportunity.char_index
+        }
+        widths
+    }
+
+    /// Returns the advance of each character. The advance of a multi-character cluster is
+    /// attributed to its first character.
+    fn char_advances(&self, callbacks: Option<&dyn LayoutCallbacks>) -> Vec<f32> {
+        let mut advances = vec![0.0; self.char_len];
+        for run in &self.runs {
+            match run.content {
+                ShapedRunContent::Text(_, ref glyphs) => {
+                    for glyph in glyphs {
+                        advances[glyph.cluster] += glyph.advance
+                    }
+                }
+                ShapedRunContent::Image(image_id) => {
+                    // Images stand upright, so they're as long as they're tall in vertical lines.
+                    let size = image_size(image_id, callbacks);
+                    advances[run.char_range.start] = if self.writing_mode.is_vertical() {
+                        size.height
+                    } else {
+                        size.width
+                    }
+                }
+            }
+        }
+        advances
+    }
+
+    /// Finds the last place to hyphenate the word at or just after `word_start` so that the
+    /// line starting at `line_start` fits in `max_width` with the hyphen. Returns the index of the
+    /// character after the hyphen, and the glyphs of the hyphen.
+    fn hyphenate_word(&self,
+                      hyphenator: &Hyphenator,
+                      word_start: usize,
+                      line_start: usize,
+                      max_width: f32,
+                      prefix_widths: &[f32])
+                      -> Option<(usize, Vec<ShapedGlyph>)> {
+        // Skip punctuation, such as an opening quotation mark, and stop at the end of the letters.
+        let letters_start = word_start + self.chars[word_start..].iter().take_while(|ch| {
+            !ch.is_alphabetic() && !ch.is_whitespace()
+        }).count();
+        let letters_end = letters_start + self.chars[letters_start..].iter()
+                                                                     .take_while(|ch| {
+                                                                         ch.is_alphabetic()
+                                                                     })
+                                                                     .count();
+        let offsets = hyphenator.hyphenate(&self.chars[letters_start..letters_end]);
+        for offset in offsets.into_iter().rev() {
+            let hyphen_index = letters_start + offset;
+            if !self.grapheme_starts[hyphen_index] {
+                continue
+            }
+            let hyphen_glyphs = self.hyphen_glyphs(hyphen_index - 1);
+            let hyphen_width: f32 = hyphen_glyphs.iter().map(|glyph| glyph.advance).sum();
+            let width = prefix_widths[hyphen_index] - prefix_widths[line_start] + hyphen_width;
+            if width <= max_width {
+                return Some((hyphen_index, hyphen_glyphs))
+            }
+        }
+        None
+    }
+
+    /// Shapes a hyphen in the font and orientation of the character at `char_index`, to follow
+    /// it.
+    fn hyphen_glyphs(&self, char_index: usize) -> Vec<ShapedGlyph> {
+        for run in &self.runs {
+            if let ShapedRunContent::Text(ref font, _) = run.content {
+                if run.char_range.start <= char_index && char_index < run.char_range.end {
+                    let direction = shaping_direction(self.writing_mode, run.orientation, false);
+                    return shape(font, &['-'], char_index, direction)
+                }
+            }
+        }
+        vec![]
+    }
+
+    /// Sets the advance of each tab on the line starting at `line_start`, which is `indent` from
+    /// the left margin, so that it reaches the next tab stop.
+    fn set_tab_advances(&self,
+                        advances: &mut [f32],
+                        line_start: usize,
+                        indent: f32,
+                        style: &ParagraphStyle) {
+        let mut x = indent;
+        for index in line_start..self.char_len {
+            let ch = self.chars[index];
+            if is_hard_line_break(ch) {
+                break
+            }
+            if ch == '\t' {
+                // Measure the text that the tab stop lines up: up to the next tab or line break.
+                let text_start = index + 1;
+                let text_end = self.chars[text_start..].iter().position(|&ch| {
+                    ch == '\t' || is_hard_line_break(ch)
+                }).map_or(self.char_len, |offset| text_start + offset);
+                let decimal_end = self.chars[text_start..text_end].iter()
+                                                                  .position(|&ch| ch == '.')
+                                                                  .map_or(text_end, |offset| {
+                                                                      text_start + offset
+                                                                  });
+                let text_width = advances[text_start..text_end].iter().sum();
+                let decimal_width = advances[text_start..decimal_end].iter().sum();
+                advances[index] = style.tab_advance(x, text_width, decimal_width)
+            }
+            x += advances[index]
+        }
+    }
+
+    /// Returns the index of the first grapheme cluster after the one at `char_index`.
+    fn next_grapheme_start(&self, char_index: usize) -> usize {
+        let mut index = char_index + 1;
+        while !self.grapheme_starts[index] {
+            index += 1
+        }
+        index
+    }
+
+    fn trim_trailing_whitespace(&self, range: Range<usize>) -> usize {
+        let mut end = range.end;
+        while end > range.start && self.chars[end - 1].is_whitespace() {
+            end -= 1
+        }
+        end
+    }
+
+    /// Positions the glyphs of `char_range` along a line in visual order, adding `space_stretch`
+    /// to the advance of each whitespace character before any trailing whitespace, and ending the
+    /// line with `hyphen_glyphs`.
+    fn build_line(&self,
+                  char_range: Range<usize>,
+                  advances: &[f32],
+                  space_stretch: f32,
+                  hyphen_glyphs: &[ShapedGlyph],
+                  callbacks: Option<&dyn LayoutCallbacks>)
+                  -> LineLayout {
+        let trimmed_end = self.trim_trailing_whitespace(char_range.clone());
+        let is_stretched = |index: usize| index < trimmed_end && is_stretchable(self.chars[index]);
+        let mut runs = vec![];
+        let mut pen_x = 0.0;
+        let mut line_bounds = TypographicBounds {
+            width: 0.0,
+            ascent: 0.0,
+            descent: 0.0,
+            leading: 0.0,
+        };
+
+        // Split the runs where the line's levels change, then put the pieces in visual order.
+        let line_levels = self.line_levels(char_range.clone());
+        let mut pieces = vec![];
+        for shaped_run in &self.runs {
+            let run_range = intersect_ranges(&shaped_run.char_range, &char_range);
+            let line_level = |char_index: usize| line_levels[char_index - char_range.start];
+            for piece_range in uniform_runs(run_range, line_level) {
+                pieces.push((shaped_run, piece_range))
+            }
+        }
+        reorder_visually(&mut pieces, |(_, piece_range)| {
+            line_levels[piece_range.start - char_range.start]
+        });
+
+        for (shaped_run, run_range) in pieces {
+            let direction = if line_levels[run_range.start - char_range.start] % 2 == 1 {
+                TextDirection::RightToLeft
+            } else {
+                TextDirection::LeftToRight
+            };
+            let run_start_x = pen_x;
+            let (glyphs, ascent, descent, leading) = match shaped_run.content {
+                ShapedRunContent::Text(ref font, ref shaped_glyphs) => {
+                    let mut glyphs: Vec<_> = shaped_glyphs.iter().filter(|glyph| {
+                        run_range.start <= glyph.cluster && glyph.cluster < run_range.end
+                    }).collect();
+                    // The hyphen follows the last character, so it's on the left of a
+                    // right-to-left run.
+                    if run_range.end == char_range.end {
+                        match direction {
+                            TextDirection::LeftToRight => glyphs.extend(hyphen_glyphs),
+                            TextDirection::RightToLeft => {
+                                glyphs.splice(0..0, hyphen_glyphs);
+                            }
+                        }
+                    }
+                    let metrics = font.metrics();
+                    let glyphs: Vec<_> = glyphs.into_iter().map(|glyph| {
+                        let mut advance = if self.chars[glyph.cluster] == '\t' {
+                            advances[glyph.cluster]
+                        } else {
+                            glyph.advance
+                        };
+                        if is_stretched(glyph.cluster) {
+                            advance += space_stretch
+                        }
+                        let (offset, vertical) = (glyph.offset, self.writing_mode.is_vertical());
+                        let position = match (vertical, shaped_run.orientation) {
+                            (false, _) => Point2D::new(pen_x + offset.x, offset.y),
+                            (true, GlyphOrientation::Upright) => {
+                                Point2D::new(offset.x, offset.y - pen_x)
+                            }
+                            // Turn the glyph clockwise, with its em box centered on the line.
+                            (true, GlyphOrientation::Rotated) => {
+                                Point2D::new((metrics.descent - metrics.ascent) * 0.5 + offset.y,
+                                             -(pen_x + offset.x))
+                            }
+                        };
+                        let glyph = PositionedGlyph {
+                            id: glyph.id,
+                            cluster: glyph.cluster,
+                            advance,
+                            pen: pen_x,
+                            position,
+                        };
+                        pen_x += glyph.advance;
+                        glyph
+                    }).collect();
+                    if self.writing_mode.is_vertical() {
+                        // Vertical lines are centered on their baseline.
+                        let half_height = (metrics.ascent + metrics.descent) * 0.5;
+                        (glyphs, half_height, half_height, metrics.leading)
+                    } else {
+                        (glyphs, metrics.ascent, metrics.descent, metrics.leading)
+                    }
+                }
+                ShapedRunContent::Image(image_id) => {
+                    // Images sit on the baseline of horizontal lines and straddle that of
+                    // vertical ones. Their position is their bottom left corner.
+                    let size = image_size(image_id, callbacks);
+                    let advance = advances[run_range.start];
+                    let (position, ascent, descent) = if self.writing_mode.is_vertical() {
+                        let half_width = size.width * 0.5;
+                        (Point2D::new(-half_width, -(pen_x + advance)), half_width, half_width)
+                    } else {
+                        (Point2D::new(pen_x, 0.0), size.height, 0.0)
+                    };
+                    let glyph = PositionedGlyph {
+                        id: 0,
+                        cluster: run_range.start,
+                        advance,
+                        pen: pen_x,
+                        position,
+                    };
+                    pen_x += glyph.advance;
+                    (vec![glyph], ascent, descent, 0.0)
+                }
+            };
+
+            line_bounds.ascent = line_bounds.ascent.max(ascent);
+            line_bounds.descent = line_bounds.descent.max(descent);
+            line_bounds.leading = line_bounds.leading.max(leading);
+
+            runs.push(Arc::new(RunLayout {
+                chars: self.chars[run_range.clone()].to_vec(),
+                char_range: run_range,
+                glyphs,
+                formats: shaped_run.formats.clone(),
+                direction,
+                orientation: shaped_run.orientation,
+                bounds: TypographicBounds {
+                    width: pen_x - run_start_x,
+                    ascent,
+                    descent,
+                    leading,
+                },
+            }))
+        }
+
+        line_bounds.width = pen_x;
+        LineLayout {
+            char_range,
+            runs,
+            grapheme_starts: self.grapheme_starts.clone(),
+            writing_mode: self.writing_mode,
+            bounds: line_bounds,
+            inline_offset: 0.0,
+            baseline: 0.0,
+            half_spacing: 0.0,
+        }
+    }
+}
+
+/// Splits `char_range` wherever `key` of its characters, such as their embedding level, changes.
+fn uniform_runs<F, T>(char_range: Range<usize>, key: F) -> Vec<Range<usize>>
+                      where F: Fn(usize) -> T, T: PartialEq {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for char_index in char_range {
+        match ranges.last_mut() {
+            Some(range) if key(range.start) == key(char_index) => {
+                range.end = char_index + 1
+            }
+            _ => ranges.push(char_index..(char_index + 1)),
+        }
+    }
+    ranges
+}
+
+/// Puts `items`, given in logical order, in visual order by reversing each sequence of them at
+/// or above each odd level, from the highest down (rule L2 of UAX #9).
+fn reorder_visually<T, F>(items: &mut [T], level: F) where F: Fn(&T) -> u8 {
+    let max_level = match items.iter().map(&level).max() {
+        None => return,
+        Some(max_level) => max_level,
+    };
+    let lowest_odd_level = items.iter().map(&level).min().unwrap() | 1;
+    for reversed_level in (lowest_odd_level..(max_level + 1)).rev() {
+        let mut start = 0;
+        while start < items.len() {
+            let end = start + items[start..].iter()
+                                            .take_while(|item| level(item) >= reversed_level)
+                                            .count();
+            items[start..end].reverse();
+            start = end + 1
+        }
+    }
+}
+
+/// Returns the direction to shape text with `orientation` in lines of `writing_mode`.
+fn shaping_direction(writing_mode: WritingMode, orientation: GlyphOrientation, right_to_left: bool)
+                     -> Direction {
+    if writing_mode.is_vertical() && orientation == GlyphOrientation::Upright {
+        Direction::TopToBottom
+    } else if right_to_left {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    }
+}
+
+/// Returns true if justification may widen `ch`. Tabs are left alone to keep their stops.
+fn is_stretchable(ch: char) -> bool {
+    ch.is_whitespace() && ch != '\t'
+}
+
+fn is_hard_line_break(ch: char) -> bool {
+    matches!(ch, '\n' | '\r' | '\u{000b}' | '\u{000c}' | '\u{0085}' | '\u{2028}' | '\u{2029}')
+}
+
+/// Splits `char_range` into pieces, each assigned the first of `font` and `fallback_fonts` that
+/// has glyphs for its characters. Characters that no font covers stay with `font`.
+fn select_fonts(font: &Font,
+                fallback_fonts: &[Font],
+                chars: &[char],
+                grapheme_starts: &[bool],
+                char_range: Range<usize>)
+                -> Vec<(Font, Range<usize>)> {
+    if fallback_fonts.is_empty() {
+        return vec![(font.clone(), char_range)]
+    }
+
+    let fallback_fonts = fallback_fonts.iter().map(|fallback| fallback.to_size(font.size()));
+    let fonts: Vec<Font> = Some(font.clone()).into_iter().chain(fallback_fonts).collect();
+    let covers = |font_index: usize, ch: char| {
+        fonts[font_index].instance.face.parsed().glyph_index(ch).is_some()
+    };
+
+    let mut pieces: Vec<(usize, Range<usize>)> = vec![];
+    for char_index in char_range {
+        let ch = chars[char_index];
+        let current_font_index = pieces.last().map(|&(font_index, _)| font_index);
+        let font_index = match current_font_index {
+            // Keep grapheme clusters, and spaces, with the preceding text.
+            Some(current_font_index) if !grapheme_starts[char_index] ||
+                    (ch.is_whitespace() && covers(current_font_index, ch)) => {
+                current_font_index
+            }
+            _ => (0..fonts.len()).find(|&font_index| covers(font_index, ch)).unwrap_or(0),
+        };
+        match pieces.last_mut() {
+            Some(&mut (last_font_index, ref mut range)) if last_font_index == font_index => {
+                range.end = char_index + 1
+            }
+            _ => pieces.push((font_index, char_index..(char_index + 1))),
+        }
+    }
+
+    pieces.into_iter().map(|(font_index, range)| (fonts[font_index].clone(), range)).collect()
+}
+
+/// Shapes `chars`, whose first character is at `char_offset` within its paragraph. The glyphs of
+/// right-to-left text come back in visual order, last character first.
+fn shape(font: &Font, chars: &[char], char_offset: usize, direction: Direction)
+         -> Vec<ShapedGlyph> {
+    // Tabs are shaped as spaces; their advances are set when lines are broken.
+    let string: String = chars.iter().map(|&ch| if ch == '\t' { ' ' } else { ch }).collect();
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(&string);
+    buffer.guess_segment_properties();
+    buffer.set_direction(direction);
+    let glyph_buffer = rustybuzz::shape(font.instance.face.parsed(), &[], buffer);
+
+    // Clusters come back as byte offsets; convert them to character indices.
+    let mut char_indices = vec![0; string.len() + 1];
+    for (char_index, (byte_index, _)) in string.char_indices().enumerate() {
+        char_indices[byte_index] = char_index
+    }
+
+    let scale = font.scale();
+    glyph_buffer.glyph_infos().iter().zip(glyph_buffer.glyph_positions().iter()).filter_map(|
+            (info, position)| {
+        let cluster = char_indices[info.cluster as usize];
+        if is_hard_line_break(chars[cluster]) {
+            return None
+        }
+        // Vertical advances point down, but Y points up.
+        let advance = if direction == Direction::TopToBottom {
+            -position.y_advance
+        } else {
+            position.x_advance
+        };
+        Some(ShapedGlyph {
+            id: info.glyph_id as Glyph,
+            cluster: char_offset + cluster,
+            advance: advance as f32 * scale,
+            offset: Vector2D::new(position.x_offset as f32 * scale,
+                                  position.y_offset as f32 * scale),
+        })
+    }).collect()
+}
+
+/// Returns how many of `lines`, starting from the first, fit in `height` once the first is
+/// moved to the top.
+fn lines_fitting_in_height(lines: &[LineLayout], height: f32) -> usize {
+    let top = match lines.first() {
+        None => return 0,
+        Some(first_line) => first_line.top(),
+    };
+    lines.iter().take_while(|line| line.bottom() - top <= height).count()
+}
+
+/// Returns the distance from the top of the first of `lines` to the bottom of the last.
+fn lines_height(lines: &[LineLayout]) -> f32 {
+    match (lines.first(), lines.last()) {
+        (Some(first_line), Some(last_line)) => last_line.bottom() - first_line.top(),
+        _ => 0.0,
+    }
+}
+
+fn image_size(image_id: u32, callbacks: Option<&dyn LayoutCallbacks>) -> Size2D<f32> {
+    callbacks.and_then(|callbacks| callbacks.get_image_size(image_id))
+             .map(|size| Size2D::new(size.width as f32, size.height as f32))
+             .unwrap_or(Size2D::zero())
+}
+
+fn intersect_ranges(a: &Range<usize>, b: &Range<usize>) -> Range<usize> {
+    a.start.max(b.start)..a.end.min(b.end)
+}
+
+pub struct Frame {
+    lines: Vec<Arc<LineLayout>>,
+    style: ParagraphStyle,
+    /// The size and origin of this frame in flow coordinates.
+    virtual_size: Size2D<f32>,
+    origin: Point2D<f32>,
+    /// The rect that this frame was flowed into, in page coordinates.
+    page_rect: Rect<f32>,
+    writing_mode: WritingMode,
+    char_range: Range<usize>,
+    paragraph_index: usize,
+}
+
+impl Frame {
+    #[inline]
+    pub fn char_len(&self) -> usize {
+        self.char_range.end - self.char_range.start
+    }
+
+    /// Returns the range of characters of the paragraph that this frame holds. This is less than
+    /// the whole paragraph if it was split across pages.
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        self.char_range.clone()
+    }
+
+    /// Returns the index of the paragraph in the document that this frame holds.
+    #[inline]
+    pub fn paragraph_index(&self) -> usize {
+        self.paragraph_index
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        self.lines.iter().map(|line| {
+            let origin = self.origin + Vector2D::new(line.inline_offset, line.baseline);
+            Line {
+                origin: self.writing_mode.page_point(&self.page_rect, &origin),
+                line: line.clone(),
+            }
+        }).collect()
+    }
+
+    #[inline]
+    pub fn bounds(&self) -> Rect<f32> {
+        let flow_bounds = Rect::new(self.origin,
+                                    Size2D::new(self.virtual_size.width, self.block_size()));
+        self.writing_mode.page_rect(&self.page_rect, &flow_bounds)
+    }
+
+    #[inline]
+    pub fn height(&self) -> f32 {
+        self.bounds().size.height
+    }
+
+    /// Returns the caret of an empty paragraph: where its first line would start, on the side
+    /// that lines start on and as aligned, across the frame in the direction lines stack in.
+    pub(crate) fn empty_caret_rect(&self) -> Rect<f32> {
+        // An empty paragraph has no text to take its direction from.
+        let direction = match self.style.base_direction {
+            BaseDirection::RightToLeft => TextDirection::RightToLeft,
+            BaseDirection::Auto | BaseDirection::LeftToRight => TextDirection::LeftToRight,
+        };
+        let offset = self.style.empty_line_offset(direction, self.virtual_size.width);
+        let flow_rect = Rect::new(Point2D::new(self.origin.x + offset, self.origin.y),
+                                  Size2D::new(0.0, self.block_size()));
+        self.writing_mode.page_rect(&self.page_rect, &flow_rect)
+    }
+
+    /// Returns the extent of this frame in the direction that lines stack in: its width in
+    /// vertical writing modes.
+    pub fn block_size(&self) -> f32 {
+        match self.lines.last() {
+            None => 0.0,
+            Some(last_line) => last_line.bottom(),
+        }
+    }
+
+    /// Moves this frame to `offset` in the direction that lines stack in, in flow coordinates.
+    #[inline]
+    pub(crate) fn set_block_offset(&mut self, offset: f32) {
+        self.origin.y = offset
+    }
+
+    pub fn line_index_at_point(&self, point: &Point2D<f32>) -> Option<usize> {
+        self.lines().binary_search_by(|line| {
+            ::compare_bounds_and_point_in_block_direction(&line.typographic_bounding_rect(),
+                                                          point,
+                                                          self.writing_mode)
+        }).ok()
+    }
+
+    #[inline]
+    pub fn style(&self) -> &ParagraphStyle {
+        &self.style
+    }
+}
+
+#[derive(Clone)]
+struct LineLayout {
+    char_range: Range<usize>,
+    runs: Vec<Arc<RunLayout>>,
+    /// Whether each character of the paragraph, and its end, begins a grapheme cluster.
+    grapheme_starts: Arc<Vec<bool>>,
+    writing_mode: WritingMode,
+    bounds: TypographicBounds,
+    /// The distance from the left of the frame to this line's origin.
+    inline_offset: f32,
+    /// The distance from the top of the frame to this line's baseline.
+    baseline: f32,
+    /// The space added both above and below the text of this line to give it the paragraph's
+    /// line height. This is negative if the line height is smaller than the fonts ask for.
+    half_spacing: f32,
+}
+
+impl LineLayout {
+    /// Returns the index of the first character of the grapheme cluster containing the character
+    /// at `char_index`.
+    fn grapheme_start(&self, char_index: usize) -> usize {
+        let mut index = char_index.min(self.grapheme_starts.len() - 1);
+        while index > 0 && !self.grapheme_starts[index] {
+            index -= 1
+        }
+        index
+    }
+
+    /// Returns the index of the first character after the grapheme cluster containing the
+    /// character at `char_index`.
+    fn grapheme_end(&self, char_index: usize) -> usize {
+        let mut index = char_index + 1;
+        while index < self.grapheme_starts.len() - 1 && !self.grapheme_starts[index] {
+            index += 1
+        }
+        index
+    }
+
+    /// Returns the distance from the top of the frame to the top of this line.
+    #[inline]
+    fn top(&self) -> f32 {
+        self.baseline - self.bounds.ascent - self.half_spacing
+    }
+
+    /// Returns the distance from the top of the frame to the bottom of this line, not counting
+    /// its leading.
+    #[inline]
+    fn bottom(&self) -> f32 {
+        self.baseline + self.bounds.descent + self.half_spacing
+    }
+}
+
+pub struct Line {
+    line: Arc<LineLayout>,
+    pub origin: Point2D<f32>,
+}
+
+impl Line {
+    pub fn runs(&self) -> Vec<Run> {
+        self.line.runs.iter().map(|run| {
+            Run {
+                run: run.clone(),
+            }
+        }).collect()
+    }
+
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        self.line.char_range.clone()
+    }
+
+    pub fn typographic_bounding_rect(&self) -> Rect<f32> {
+        let bounds = self.typographic_bounds();
+        let thickness = bounds.ascent + bounds.descent;
+        match self.line.writing_mode {
+            WritingMode::HorizontalTb => {
+                Rect::new(Point2D::new(self.origin.x, self.origin.y - bounds.ascent),
+                          Size2D::new(bounds.width, thickness))
+            }
+            // The ascent is on the side that lines stack from.
+            WritingMode::VerticalRl => {
+                Rect::new(Point2D::new(self.origin.x - bounds.descent, self.origin.y),
+                          Size2D::new(thickness, bounds.width))
+            }
+            WritingMode::VerticalLr => {
+                Rect::new(Point2D::new(self.origin.x - bounds.ascent, self.origin.y),
+                          Size2D::new(thickness, bounds.width))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn typographic_bounds(&self) -> TypographicBounds {
+        self.line.bounds
+    }
+
+    /// Returns the character index at the grapheme cluster edge closest to `position`, which is
+    /// relative to the line origin. The right edge of a right-to-left cluster is its start.
+    /// Along vertical lines, only the Y coordinate counts.
+    pub fn char_index_for_position(&self, position: &Point2D<f32>) -> Option<usize> {
+        let offset = if self.line.writing_mode.is_vertical() { position.y } else { position.x };
+        let clusters = self.visual_clusters();
+        for cluster in &clusters {
+            // Pick the index at the nearer edge of the cluster under the position.
+            let is_left_half = offset < cluster.left + cluster.width * 0.5;
+            if is_left_half || offset < cluster.right() {
+                return Some(if is_left_half == cluster.right_to_left {
+                    self.line.grapheme_end(cluster.start)
+                } else {
+                    cluster.start
+                })
+            }
+        }
+        match clusters.last() {
+            Some(cluster) if cluster.right_to_left => Some(cluster.start),
+            _ => Some(self.line.char_range.end),
+        }
+    }
+
+    /// Returns the offset of the leading edge of the grapheme cluster containing the character
+    /// at `index` from the line origin. Past the last cluster, this is the trailing edge of the
+    /// cluster before.
+    pub fn inline_position_for_char_index(&self, index: usize) -> f32 {
+        let clusters = self.visual_clusters();
+        let start = self.line.grapheme_start(index);
+        if let Some(cluster) = clusters.iter().find(|cluster| cluster.start == start) {
+            return if cluster.right_to_left { cluster.right() } else { cluster.left }
+        }
+
+        // Characters without glyphs, such as line breaks, sit after the cluster before.
+        let mut start = start;
+        while start > self.line.char_range.start {
+            start = self.line.grapheme_start(start - 1);
+            if let Some(cluster) = clusters.iter().find(|cluster| cluster.start == start) {
+                return if cluster.right_to_left { cluster.left } else { cluster.right() }
+            }
+        }
+        0.0
+    }
+
+    /// Returns the extents of the grapheme clusters that start in `range` as offsets from the
+    /// line origin, from left to right, joining those that are next to each other. Text that
+    /// changes direction can take more than one extent.
+    pub fn inline_extents_for_char_range(&self, range: Range<usize>) -> Vec<Range<f32>> {
+        let mut extents: Vec<Range<f32>> = vec![];
+        for cluster in self.visual_clusters() {
+            if !range.contains(&cluster.start) {
+                continue
+            }
+            match extents.last_mut() {
+                Some(extent) if (extent.end - cluster.left).abs() < CLUSTER_ADJACENCY_EPSILON => {
+                    extent.end = cluster.right()
+                }
+                _ => extents.push(cluster.left..cluster.right()),
+            }
+        }
+        extents
+    }
+
+    /// Returns the grapheme clusters of this line from left to right.
+    fn visual_clusters(&self) -> Vec<ClusterExtent> {
+        let mut clusters: Vec<ClusterExtent> = vec![];
+        for run in &self.line.runs {
+            let right_to_left = run.direction == TextDirection::RightToLeft;
+            for glyph in &run.glyphs {
+                let start = self.line.grapheme_start(glyph.cluster);
+                match clusters.last_mut() {
+                    Some(cluster) if cluster.start == start => cluster.width += glyph.advance,
+                    _ => {
+                        clusters.push(ClusterExtent {
+                            start,
+                            left: glyph.pen,
+                            width: glyph.advance,
+                            right_to_left,
+                        })
+                    }
+                }
+            }
+        }
+        clusters
+    }
+}
+
+/// Where a grapheme cluster sits along a line. Along vertical lines, left means up.
+struct ClusterExtent {
+    /// The index of the first character of the cluster.
+    start: usize,
+    left: f32,
+    width: f32,
+    right_to_left: bool,
+}
+
+impl ClusterExtent {
+    #[inline]
+    fn right(&self) -> f32 {
+        self.left + self.width
+    }
+}
+
+struct RunLayout {
+    chars: Vec<char>,
+    char_range: Range<usize>,
+    /// The glyphs in visual order, from the start of the line.
+    glyphs: Vec<PositionedGlyph>,
+    formats: Vec<Format>,
+    direction: TextDirection,
+    orientation: GlyphOrientation,
+    bounds: TypographicBounds,
+}
+
+#[derive(Clone, Copy)]
+struct PositionedGlyph {
+    id: Glyph,
+    cluster: usize,
+    advance: f32,
+    /// The distance along the line from its origin to the start of this glyph's advance.
+    pen: f32,
+    /// The position of this glyph relative to the line origin, with Y pointing up.
+    position: Point2D<f32>,
+}
+
+pub struct Run {
+    run: Arc<RunLayout>,
+}
+
+impl Run {
+    #[inline]
+    pub fn glyph_count(&self) -> usize {
+        self.run.glyphs.len()
+    }
+
+    pub fn glyphs(&self) -> Vec<Glyph> {
+        self.run.glyphs.iter().map(|glyph| glyph.id).collect()
+    }
+
+    pub fn positions(&self) -> Vec<Point2D<f32>> {
+        self.run.glyphs.iter().map(|glyph| glyph.position).collect()
+    }
+
+    #[inline]
+    pub fn char_range(&self) -> Range<usize> {
+        self.run.char_range.clone()
+    }
+
+    /// Returns the text of each glyph's cluster, on the first glyph of the cluster, with none on
+    /// the rest.
+    pub fn glyph_strings(&self) -> Vec<String> {
+        let clusters: Vec<_> = self.run.glyphs.iter().map(|glyph| glyph.cluster).collect();
+        cluster_strings(&clusters, &self.run.chars, self.run.char_range.start)
+    }
+
+    pub fn formatting(&self) -> Vec<Format> {
+        self.run.formats.clone()
+    }
+
+    #[inline]
+    pub fn typographic_bounds(&self) -> TypographicBounds {
+        self.run.bounds
+    }
+
+    /// Returns the direction of this run's text. Its glyphs are in visual order either way.
+    #[inline]
+    pub fn direction(&self) -> TextDirection {
+        self.run.direction
+    }
+
+    #[inline]
+    pub fn glyph_orientation(&self) -> GlyphOrientation {
+        self.run.orientation
+    }
+
+    /// Returns the distance along the line from its origin to the start of this run.
+    pub(crate) fn inline_offset(&self) -> f32 {
+        self.run.glyphs.first().map_or(0.0, |glyph| glyph.pen)
+    }
+}
+
+#[derive(Clone)]
+pub struct Font {
+    instance: Arc<FontInstance>,
+}
+
+struct FontInstance {
+    face: Arc<FontFace>,
+    /// All faces of this font's family that were available when it was loaded, used to find
+    /// bold and italic variants.
+    family_faces: Arc<Vec<Arc<FontFace>>>,
+    size: f32,
+}
+
+impl Font {
+    pub(crate) fn from_face(face: Arc<FontFace>, family_faces: Vec<Arc<FontFace>>, size: f32)
+                            -> Font {
+        Font {
+            instance: Arc::new(FontInstance {
+                face,
+                family_faces: Arc::new(family_faces),
+                size,
+            }),
+        }
+    }
+
+    /// Returns the system's serif font, or the bundled DejaVu Serif if there's none.
+    #[inline]
+    pub fn default_serif() -> Font {
+        DEFAULT_SERIF_FONT.clone()
+    }
+
+    /// Returns the system's monospace font, or the bundled DejaVu Sans Mono if there's none.
+    #[inline]
+    pub fn default_monospace() -> Font {
+        DEFAULT_MONOSPACE_FONT.clone()
+    }
+
+    #[inline]
+    pub fn id(&self) -> FontId {
+        FontId(&*self.instance as *const FontInstance as usize)
+    }
+
+    #[inline]
+    pub fn face_id(&self) -> FontFaceId {
+        FontFaceId(&*self.instance.face as *const FontFace as usize)
+    }
+
+    #[inline]
+    pub fn size(&self) -> f32 {
+        self.instance.size
+    }
+
+    pub fn to_size(&self, new_size: f32) -> Font {
+        Font {
+            instance: Arc::new(FontInstance {
+                face: self.instance.face.clone(),
+                family_faces: self.instance.family_faces.clone(),
+                size: new_size,
+            }),
+        }
+    }
+
+    pub fn to_bold(&self) -> Option<Font> {
+        let style = self.instance.face.style;
+        self.to_variant(FONT_WEIGHT_BOLD, style).filter(|font| font.instance.face.weight >= 600)
+    }
+
+    pub fn to_italic(&self) -> Option<Font> {
+        let weight = self.instance.face.weight;
+        self.to_variant(weight, FontStyle::Italic).filter(|font| {
+            font.instance.face.style != FontStyle::Normal
+        })
+    }
+
+    fn to_variant(&self, weight: u16, style: FontStyle) -> Option<Font> {
+        let face = font::match_face(&self.instance.family_faces, weight, style)?;
+        Some(Font {
+            instance: Arc::new(FontInstance {
+                face,
+                family_faces: self.instance.family_faces.clone(),
+                size: self.instance.size,
+            }),
+        })
+    }
+
+    /// Emits the outline of `glyph` to `builder`, in points at this font's size with Y pointing
+    /// up.
+    pub(crate) fn outline_glyph(&self, glyph: Glyph, builder: &mut dyn OutlineBuilder) {
+        let mut builder = ScaledOutlineBuilder {
+            builder,
+            scale: self.scale(),
+        };
+        self.instance.face.parsed().outline_glyph(GlyphId(glyph), &mut builder);
+    }
+
+    /// Returns the horizontal advance of `glyph`, in points at this font's size.
+    pub(crate) fn glyph_advance(&self, glyph: Glyph) -> f32 {
+        let advance = self.instance.face.parsed().glyph_hor_advance(GlyphId(glyph));
+        advance.unwrap_or(0) as f32 * self.scale()
+    }
+
+    #[inline]
+    fn scale(&self) -> f32 {
+        self.instance.size / self.instance.face.units_per_em
+    }
+
+    fn metrics(&self) -> TypographicBounds {
+        let (face, scale) = (&self.instance.face, self.scale());
+        TypographicBounds {
+            width: 0.0,
+            ascent: face.ascender * scale,
+            descent: -face.descender * scale,
+            leading: face.line_gap * scale,
+        }
+    }
+}
+
+struct ScaledOutlineBuilder<'a> {
+    builder: &'a mut dyn OutlineBuilder,
+    scale: f32,
+}
+
+impl<'a> OutlineBuilder for ScaledOutlineBuilder<'a> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.builder.move_to(x * self.scale, y * self.scale)
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.builder.line_to(x * self.scale, y * self.scale)
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let scale = self.scale;
+        self.builder.quad_to(x1 * scale, y1 * scale, x * scale, y * scale)
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let scale = self.scale;
+        self.builder.curve_to(x1 * scale, y1 * scale, x2 * scale, y2 * scale, x * scale, y * scale)
+    }
+
+    fn close(&mut self) {
+        self.builder.close()
+    }
+}
+
+/// Loads the first of `paths` that exists, along with the other faces of its family that live
+/// next to it (e.g. `DejaVuSerif-Bold.ttf` next to `DejaVuSerif.ttf`), or else the face in
+/// `bundled_data`.
+fn default_font(paths: &[&str], bundled_data: &[u8], size: f32) -> Font {
+    paths.iter().filter_map(|path| {
+        let path = Path::new(path);
+        let mut collection = FontCollection::new();
+        collection.add_path(path).ok()?;
+
+        let stem = path.file_stem()?.to_str()?;
+        let prefix = stem.split('-').next().unwrap();
+        if let Ok(entries) = fs::read_dir(path.parent()?) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let sibling_path = entry.path();
+                let is_sibling = sibling_path != path &&
+                    sibling_path.file_stem()
+                                .and_then(|stem| stem.to_str())
+                                .is_some_and(|stem| stem.starts_with(prefix));
+                // Skip siblings that fail to load.
+                if is_sibling {
+                    collection.add_path(&sibling_path).ok();
+                }
+            }
+        }
+
+        let family = collection.families().first()?.to_string();
+        collection.select(&family, FONT_WEIGHT_NORMAL, FontStyle::Normal, size)
+    }).next().unwrap_or_else(|| {
+        Font::from_bytes(bundled_data.to_vec(), 0, size).expect("default_font(): Bad bundled font!")
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub struct FontFaceId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub struct FontId(usize);
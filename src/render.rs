@@ -0,0 +1,211 @@
+// pilcrow/src/render.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rasterizing laid-out sections into bitmaps on the CPU, for previews and tests.
+
+use euclid::{self, Point2D};
+use std::collections::HashMap;
+use tiny_skia::{ColorU8, FillRule, FilterQuality, Paint, Path, PathBuilder, Pixmap};
+use tiny_skia::{PixmapPaint, Rect, Transform};
+use ttf_parser::OutlineBuilder;
+
+use format::{Color, Format};
+use {Font, FontId, Glyph, GlyphOrientation, ParagraphContent, Section, WritingMode};
+
+/// The color of text with no `Format::Color`, and of rules.
+pub(crate) const DEFAULT_COLOR: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+
+/// The thickness of the line drawn for `ParagraphContent::Rule` paragraphs, in points.
+pub(crate) const RULE_THICKNESS: f32 = 1.0;
+
+impl Section {
+    /// Rasterizes this section into a `width` × `height` bitmap of unpremultiplied RGBA pixels,
+    /// with rows from top to bottom and a transparent background. Layout coordinates are
+    /// multiplied by `scale`, so 2.0 renders at twice the resolution.
+    ///
+    /// Images are drawn with the pixels from `LayoutCallbacks::get_image_rgba()` of the callbacks
+    /// that this section was laid out with; images without pixels are left blank.
+    pub fn render_to_rgba(&self, width: u32, height: u32, scale: f32) -> Vec<u8> {
+        let mut pixmap = match Pixmap::new(width, height) {
+            None => return vec![],
+            Some(pixmap) => pixmap,
+        };
+        let transform = Transform::from_scale(scale, scale);
+        let mut glyph_paths: HashMap<(FontId, Glyph), Option<Path>> = HashMap::new();
+
+        for rule in &self.column_rules {
+            let rect = Rect::from_xywh(rule.origin.x,
+                                       rule.origin.y,
+                                       rule.size.width,
+                                       rule.size.height);
+            if let Some(rect) = rect {
+                pixmap.fill_rect(rect, &paint(DEFAULT_COLOR), transform, None)
+            }
+        }
+
+        for frame in &self.frames {
+            if frame.style().content == ParagraphContent::Rule {
+                let (start, end) = rule_line(&frame.bounds(), self.writing_mode);
+                let half_thickness = RULE_THICKNESS * 0.5;
+                let rect = Rect::from_ltrb(start.x.min(end.x - half_thickness),
+                                           start.y.min(end.y - half_thickness),
+                                           end.x.max(start.x + half_thickness),
+                                           end.y.max(start.y + half_thickness));
+                if let Some(rect) = rect {
+                    pixmap.fill_rect(rect, &paint(DEFAULT_COLOR), transform, None)
+                }
+                continue
+            }
+
+            for line in frame.lines() {
+                for run in line.runs() {
+                    let formats = run.formatting();
+                    let positions = run.positions();
+
+                    if let Some(image_id) = formats.iter().filter_map(Format::image).next() {
+                        if let Some(position) = positions.first() {
+                            let origin = Point2D::new(line.origin.x + position.x,
+                                                      line.origin.y - position.y);
+                            self.draw_image(&mut pixmap, image_id, &origin, transform)
+                        }
+                        continue
+                    }
+
+                    let font = formats.iter()
+                                      .filter_map(Format::font)
+                                      .next()
+                                      .unwrap_or_else(Font::default_serif);
+                    let color = formats.iter().filter_map(Format::color).next();
+                    let paint = paint(color.unwrap_or(DEFAULT_COLOR));
+
+                    for (glyph, position) in run.glyphs().into_iter().zip(positions) {
+                        let path = glyph_paths.entry((font.id(), glyph)).or_insert_with(|| {
+                            glyph_path(&font, glyph)
+                        });
+                        if let Some(ref path) = *path {
+                            // Glyph outlines have Y pointing up; the bitmap has it pointing down.
+                            // Rotated glyphs have it pointing right.
+                            let (x, y) = (line.origin.x + position.x, line.origin.y - position.y);
+                            let glyph_transform = match run.glyph_orientation() {
+                                GlyphOrientation::Upright => {
+                                    Transform::from_row(1.0, 0.0, 0.0, -1.0, x, y)
+                                }
+                                GlyphOrientation::Rotated => {
+                                    Transform::from_row(0.0, 1.0, 1.0, 0.0, x, y)
+                                }
+                            };
+                            pixmap.fill_path(path,
+                                             &paint,
+                                             FillRule::Winding,
+                                             glyph_transform.post_concat(transform),
+                                             None)
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut rgba = Vec::with_capacity(pixmap.data().len());
+        for pixel in pixmap.pixels() {
+            let pixel = pixel.demultiply();
+            rgba.extend_from_slice(&[pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]);
+        }
+        rgba
+    }
+
+    /// Draws image `image_id` with its bottom left corner at `origin`.
+    fn draw_image(&self,
+                  pixmap: &mut Pixmap,
+                  image_id: u32,
+                  origin: &Point2D<f32>,
+                  transform: Transform) {
+        let callbacks = match self.callbacks {
+            None => return,
+            Some(ref callbacks) => callbacks,
+        };
+        let (size, pixels) = match (callbacks.get_image_size(image_id),
+                                    callbacks.get_image_rgba(image_id)) {
+            (Some(size), Some(pixels)) => (size, pixels),
+            _ => return,
+        };
+        let mut image = match Pixmap::new(size.width, size.height) {
+            None => return,
+            Some(image) => image,
+        };
+        if pixels.len() != image.data().len() {
+            return
+        }
+        for (pixel, rgba) in image.pixels_mut().iter_mut().zip(pixels.chunks(4)) {
+            *pixel = ColorU8::from_rgba(rgba[0], rgba[1], rgba[2], rgba[3]).premultiply()
+        }
+
+        let paint = PixmapPaint {
+            quality: FilterQuality::Bilinear,
+            ..PixmapPaint::default()
+        };
+        let image_transform = Transform::from_translate(origin.x, origin.y - size.height as f32);
+        pixmap.draw_pixmap(0,
+                           0,
+                           image.as_ref(),
+                           &paint,
+                           image_transform.post_concat(transform),
+                           None)
+    }
+}
+
+/// Returns the ends of the rule drawn through the middle of a `ParagraphContent::Rule` frame with
+/// `bounds`. It runs along the lines of `writing_mode`.
+pub(crate) fn rule_line(bounds: &euclid::Rect<f32>, writing_mode: WritingMode)
+                        -> (Point2D<f32>, Point2D<f32>) {
+    if writing_mode.is_vertical() {
+        let x = bounds.origin.x + bounds.size.width * 0.5;
+        (Point2D::new(x, bounds.origin.y), Point2D::new(x, bounds.max_y()))
+    } else {
+        let y = bounds.origin.y + bounds.size.height * 0.5;
+        (Point2D::new(bounds.origin.x, y), Point2D::new(bounds.max_x(), y))
+    }
+}
+
+fn paint(color: Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(color.r, color.g, color.b, color.a);
+    paint
+}
+
+/// Returns the outline of `glyph` in points, or `None` if it has no outline (e.g. a space).
+fn glyph_path(font: &Font, glyph: Glyph) -> Option<Path> {
+    let mut builder = GlyphPathBuilder(PathBuilder::new());
+    font.outline_glyph(glyph, &mut builder);
+    builder.0.finish()
+}
+
+struct GlyphPathBuilder(PathBuilder);
+
+impl OutlineBuilder for GlyphPathBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y)
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y)
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y)
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y)
+    }
+
+    fn close(&mut self) {
+        self.0.close()
+    }
+}
@@ -0,0 +1,247 @@
+// pilcrow/src/segmentation.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding where lines may break, following UAX #14, and where grapheme clusters begin,
+//! following UAX #29.
+//!
+//! Line breaking follows the default rules of Unicode 15.0, with the regular expression tailoring
+//! of the numeric rule (LB25) that the Unicode line breaking tests use. Complex-context
+//! characters, such as Thai, are treated as letters, so they only break at spaces.
+
+use unicode_linebreak::BreakClass::{self, After as BA, Alphabetic as AL, Ambiguous as AI};
+use unicode_linebreak::BreakClass::{Before as BB, BeforeAndAfter as B2, CarriageReturn as CR};
+use unicode_linebreak::BreakClass::{CloseParenthesis as CP, ClosePunctuation as CL};
+use unicode_linebreak::BreakClass::{CombiningMark as CM, ComplexContext as SA};
+use unicode_linebreak::BreakClass::{ConditionalJapaneseStarter as CJ, Contingent as CB};
+use unicode_linebreak::BreakClass::{EmojiBase as EB, EmojiModifier as EM, Exclamation as EX};
+use unicode_linebreak::BreakClass::{HangulLJamo as JL, HangulLvSyllable as H2};
+use unicode_linebreak::BreakClass::{HangulLvtSyllable as H3, HangulTJamo as JT};
+use unicode_linebreak::BreakClass::{HangulVJamo as JV, HebrewLetter as HL, Hyphen as HY};
+use unicode_linebreak::BreakClass::{Ideographic as ID, InfixSeparator as IS, Inseparable as IN};
+use unicode_linebreak::BreakClass::{LineFeed as LF, Mandatory as BK, NextLine as NL};
+use unicode_linebreak::BreakClass::{NonBreakingGlue as GL, NonStarter as NS, Numeric as NU};
+use unicode_linebreak::BreakClass::{OpenPunctuation as OP, Postfix as PO, Prefix as PR};
+use unicode_linebreak::BreakClass::{Quotation as QU, RegionalIndicator as RI, Space as SP};
+use unicode_linebreak::BreakClass::{Surrogate as SG, Symbol as SY, Unknown as XX};
+use unicode_linebreak::BreakClass::{WordJoiner as WJ, ZeroWidthJoiner as ZWJ};
+use unicode_linebreak::BreakClass::{ZeroWidthSpace as ZW};
+use unicode_properties::{GeneralCategory, UnicodeGeneralCategory};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BreakOpportunity {
+    /// The index of the character that would begin the next line.
+    pub char_index: usize,
+    pub mandatory: bool,
+}
+
+/// Tracks the numeric expression, if any, that ends at the current character.
+#[derive(Clone, Copy, PartialEq)]
+enum Numeric {
+    None,
+    /// A number followed by any number of infix separators, symbols and further digits.
+    Number,
+    /// A number followed by closing punctuation.
+    Closed,
+}
+
+/// Returns the places where a line may end in `chars`, in increasing order. The end of the text
+/// is always a mandatory break.
+pub fn line_break_opportunities(chars: &[char]) -> Vec<BreakOpportunity> {
+    let mut opportunities = vec![];
+    let classes: Vec<BreakClass> = chars.iter().map(|&ch| resolved_class(ch)).collect();
+    if classes.is_empty() {
+        return vec![BreakOpportunity { char_index: 0, mandatory: true }]
+    }
+
+    // The class and character before the break, treating combining marks as part of the
+    // character they follow (LB9) and as letters where they follow nothing (LB10).
+    let mut prev = effective_class(classes[0]);
+    let mut prev_char = chars[0];
+    // The class before `prev`, and the last class other than a space.
+    let mut prev_prev = None;
+    let mut before_spaces = prev;
+    let mut regional_indicator_count = if prev == RI { 1 } else { 0 };
+    let mut numeric = if prev == NU { Numeric::Number } else { Numeric::None };
+
+    for char_index in 1..chars.len() {
+        let (prev_raw, class) = (classes[char_index - 1], classes[char_index]);
+
+        // LB4 to LB8a.
+        let mut decision = match (prev_raw, class) {
+            (BK, _) | (LF, _) | (NL, _) => Some(true),
+            (CR, LF) => Some(false),
+            (CR, _) => Some(true),
+            (_, BK) | (_, CR) | (_, LF) | (_, NL) | (_, SP) | (_, ZW) => Some(false),
+            _ if before_spaces == ZW => Some(true),
+            (ZWJ, _) => Some(false),
+            _ => None,
+        };
+
+        // LB9: Attach combining marks and joiners to the character they follow.
+        let is_attached = match prev {
+            BK | CR | LF | NL | SP | ZW => false,
+            _ => class == CM || class == ZWJ,
+        };
+        if decision.is_none() && is_attached {
+            decision = Some(false)
+        }
+        let mandatory = decision == Some(true) && matches!(prev_raw, BK | CR | LF | NL);
+        let is_break = match decision {
+            Some(is_break) => is_break,
+            None => {
+                let class = effective_class(class);
+                let next = || next_class(&classes, char_index + 1);
+                pair_breaks(prev, prev_char, prev_prev, before_spaces, regional_indicator_count,
+                            numeric, class, chars[char_index], next)
+            }
+        };
+        if is_break {
+            opportunities.push(BreakOpportunity { char_index, mandatory })
+        }
+
+        if is_attached {
+            continue
+        }
+        let class = effective_class(class);
+        regional_indicator_count = match (prev, class) {
+            (RI, RI) => regional_indicator_count + 1,
+            (_, RI) => 1,
+            _ => 0,
+        };
+        numeric = match (numeric, class) {
+            (_, NU) => Numeric::Number,
+            (Numeric::Number, SY) | (Numeric::Number, IS) => Numeric::Number,
+            (Numeric::Number, CL) | (Numeric::Number, CP) => Numeric::Closed,
+            _ => Numeric::None,
+        };
+        if class != SP {
+            before_spaces = class
+        }
+        prev_prev = Some(prev);
+        prev = class;
+        prev_char = chars[char_index];
+    }
+
+    opportunities.push(BreakOpportunity { char_index: chars.len(), mandatory: true });
+    opportunities
+}
+
+/// Applies LB11 through LB31 to the pair of `prev` and `class`.
+#[allow(clippy::too_many_arguments)]
+fn pair_breaks<F>(prev: BreakClass,
+                  prev_char: char,
+                  prev_prev: Option<BreakClass>,
+                  before_spaces: BreakClass,
+                  regional_indicator_count: usize,
+                  numeric: Numeric,
+                  class: BreakClass,
+                  ch: char,
+                  next: F)
+                  -> bool
+                  where F: Fn() -> Option<BreakClass> {
+    match (prev, class) {
+        // LB11 to LB13.
+        (WJ, _) | (_, WJ) | (GL, _) => false,
+        (_, GL) if !matches!(prev, SP | BA | HY) => false,
+        (_, CL) | (_, CP) | (_, EX) | (_, IS) | (_, SY) => false,
+        // LB14 to LB17.
+        _ if before_spaces == OP => false,
+        (_, OP) if before_spaces == QU => false,
+        (_, NS) if before_spaces == CL || before_spaces == CP => false,
+        (_, B2) if before_spaces == B2 => false,
+        // LB18 to LB22.
+        (SP, _) => true,
+        (QU, _) | (_, QU) => false,
+        (CB, _) | (_, CB) => true,
+        (_, BA) | (_, HY) | (_, NS) | (BB, _) => false,
+        (HY, _) | (BA, _) if prev_prev == Some(HL) => false,
+        (SY, HL) | (_, IN) => false,
+        // LB23 and LB24.
+        (AL, NU) | (HL, NU) | (NU, AL) | (NU, HL) => false,
+        (PR, ID) | (PR, EB) | (PR, EM) | (ID, PO) | (EB, PO) | (EM, PO) => false,
+        (PR, AL) | (PR, HL) | (PO, AL) | (PO, HL) => false,
+        (AL, PR) | (AL, PO) | (HL, PR) | (HL, PO) => false,
+        // LB25, in its regular expression form.
+        (PR, NU) | (PO, NU) | (OP, NU) | (HY, NU) => false,
+        (PR, OP) | (PO, OP) if next() == Some(NU) => false,
+        (_, NU) if numeric == Numeric::Number => false,
+        (_, PO) | (_, PR) if numeric != Numeric::None => false,
+        // LB26 and LB27.
+        (JL, JL) | (JL, JV) | (JL, H2) | (JL, H3) => false,
+        (JV, JV) | (JV, JT) | (H2, JV) | (H2, JT) | (JT, JT) | (H3, JT) => false,
+        (JL, PO) | (JV, PO) | (JT, PO) | (H2, PO) | (H3, PO) => false,
+        (PR, JL) | (PR, JV) | (PR, JT) | (PR, H2) | (PR, H3) => false,
+        // LB28 to LB30.
+        (AL, AL) | (AL, HL) | (HL, AL) | (HL, HL) | (IS, AL) | (IS, HL) => false,
+        (AL, OP) | (HL, OP) | (NU, OP) if !is_east_asian_wide(ch) => false,
+        (CP, AL) | (CP, HL) | (CP, NU) if !is_east_asian_wide(prev_char) => false,
+        // LB30a and LB30b.
+        (RI, RI) => regional_indicator_count.is_multiple_of(2),
+        (EB, EM) => false,
+        (_, EM) => !is_unassigned_pictographic(prev_char),
+        // LB31.
+        _ => true,
+    }
+}
+
+/// Resolves the classes that UAX #14 leaves to the implementation (LB1).
+fn resolved_class(ch: char) -> BreakClass {
+    match unicode_linebreak::break_property(ch as u32) {
+        AI | SG | XX => AL,
+        SA => match ch.general_category() {
+            GeneralCategory::NonspacingMark | GeneralCategory::SpacingMark => CM,
+            _ => AL,
+        },
+        CJ => NS,
+        class => class,
+    }
+}
+
+/// Treats a combining mark or joiner that isn't attached to a character as a letter (LB10).
+fn effective_class(class: BreakClass) -> BreakClass {
+    match class {
+        CM | ZWJ => AL,
+        class => class,
+    }
+}
+
+/// Returns the class of the character at or after `index` that isn't attached to the one before.
+fn next_class(classes: &[BreakClass], index: usize) -> Option<BreakClass> {
+    classes[index.min(classes.len())..].iter().cloned().find(|&class| class != CM && class != ZWJ)
+}
+
+/// Returns true if the opening or closing punctuation `ch` has an East Asian Width of fullwidth,
+/// wide or halfwidth.
+fn is_east_asian_wide(ch: char) -> bool {
+    matches!(ch, '\u{2329}' | '\u{3008}'..='\u{301d}' | '\u{fe17}' | '\u{fe35}'..='\u{fe47}' |
+             '\u{fe59}'..='\u{fe5d}' | '\u{ff08}' | '\u{ff3b}' | '\u{ff5b}' | '\u{ff5f}' |
+             '\u{ff62}')
+}
+
+/// Returns true if `ch` is an unassigned code point reserved for pictographs.
+fn is_unassigned_pictographic(ch: char) -> bool {
+    matches!(ch, '\u{1f000}'..='\u{1faff}' | '\u{1fc00}'..='\u{1fffd}') &&
+        ch.general_category() == GeneralCategory::Unassigned
+}
+
+/// Returns the index of each character in `chars` that begins an extended grapheme cluster,
+/// followed by the length of `chars`.
+pub fn grapheme_boundaries(chars: &[char]) -> Vec<usize> {
+    let string: String = chars.iter().collect();
+    let mut boundaries = vec![];
+    let mut char_index = 0;
+    for grapheme in string.graphemes(true) {
+        boundaries.push(char_index);
+        char_index += grapheme.chars().count()
+    }
+    boundaries.push(char_index);
+    boundaries
+}
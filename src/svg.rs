@@ -0,0 +1,240 @@
+// pilcrow/src/svg.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Serializing laid-out sections to SVG.
+//!
+//! Glyphs are emitted as outlines, defined once each and placed with `<use>`, so the output
+//! doesn't depend on the viewer having the fonts. Numbers are rounded to hundredths of a point
+//! and IDs are assigned in document order, so the same layout always produces the same text.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use ttf_parser::OutlineBuilder;
+
+use format::{Color, Format};
+use render::{self, DEFAULT_COLOR, RULE_THICKNESS};
+use {Font, FontId, Glyph, GlyphOrientation, ParagraphContent, Section};
+
+impl Section {
+    /// Returns an SVG document of the given size, in points, containing this section.
+    ///
+    /// Images become empty `<image>` elements with a `data-image-id` attribute, sized with
+    /// `LayoutCallbacks::get_image_size()`, for the caller to fill in.
+    pub fn to_svg(&self, width: f32, height: f32) -> String {
+        let mut glyph_ids: HashMap<(FontId, Glyph), Option<usize>> = HashMap::new();
+        let mut defs = String::new();
+        let mut body = String::new();
+
+        for rule in &self.column_rules {
+            writeln!(body,
+                     "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                     number(rule.origin.x),
+                     number(rule.origin.y),
+                     number(rule.size.width),
+                     number(rule.size.height),
+                     hex_color(DEFAULT_COLOR)).unwrap();
+        }
+
+        for frame in &self.frames {
+            if frame.style().content == ParagraphContent::Rule {
+                let (start, end) = render::rule_line(&frame.bounds(), self.writing_mode);
+                writeln!(body,
+                         "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" \
+                          stroke-width=\"{}\"/>",
+                         number(start.x),
+                         number(start.y),
+                         number(end.x),
+                         number(end.y),
+                         hex_color(DEFAULT_COLOR),
+                         number(RULE_THICKNESS)).unwrap();
+                continue
+            }
+
+            for line in frame.lines() {
+                for run in line.runs() {
+                    let formats = run.formatting();
+                    let positions = run.positions();
+
+                    let link = formats.iter().filter_map(|format| format.link()).next();
+                    if let Some((_, url)) = link {
+                        writeln!(body, "<a xlink:href=\"{}\">", escape(url)).unwrap();
+                    }
+
+                    if let Some(image_id) = formats.iter().filter_map(Format::image).next() {
+                        let size = self.callbacks.as_ref().and_then(|callbacks| {
+                            callbacks.get_image_size(image_id)
+                        });
+                        if let (Some(size), Some(position)) = (size, positions.first()) {
+                            writeln!(body,
+                                     "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                                      data-image-id=\"{}\"/>",
+                                     number(line.origin.x + position.x),
+                                     number(line.origin.y - position.y - size.height as f32),
+                                     size.width,
+                                     size.height,
+                                     image_id).unwrap();
+                        }
+                    } else {
+                        let font = formats.iter()
+                                          .filter_map(Format::font)
+                                          .next()
+                                          .unwrap_or_else(Font::default_serif);
+                        let color = formats.iter()
+                                           .filter_map(Format::color)
+                                           .next()
+                                           .unwrap_or(DEFAULT_COLOR);
+                        write!(body, "<g fill=\"{}\"", hex_color(color)).unwrap();
+                        if color.a != 255 {
+                            write!(body, " fill-opacity=\"{}\"", number(color.a_f32())).unwrap();
+                        }
+                        body.push_str(">\n");
+
+                        for (glyph, position) in run.glyphs().into_iter().zip(positions) {
+                            let next_id = glyph_ids.len();
+                            let id = *glyph_ids.entry((font.id(), glyph)).or_insert_with(|| {
+                                let path_data = glyph_path_data(&font, glyph)?;
+                                writeln!(defs,
+                                         "<path id=\"glyph-{}\" d=\"{}\"/>",
+                                         next_id,
+                                         path_data).unwrap();
+                                Some(next_id)
+                            });
+                            let (x, y) = (number(line.origin.x + position.x),
+                                          number(line.origin.y - position.y));
+                            match (id, run.glyph_orientation()) {
+                                (None, _) => {}
+                                (Some(id), GlyphOrientation::Upright) => {
+                                    writeln!(body,
+                                             "<use xlink:href=\"#glyph-{}\" x=\"{}\" y=\"{}\"/>",
+                                             id,
+                                             x,
+                                             y).unwrap();
+                                }
+                                // Turn the outline, which has Y pointing down, so that its up
+                                // points right.
+                                (Some(id), GlyphOrientation::Rotated) => {
+                                    writeln!(body,
+                                             "<use xlink:href=\"#glyph-{}\" \
+                                              transform=\"matrix(0 1 -1 0 {} {})\"/>",
+                                             id,
+                                             x,
+                                             y).unwrap();
+                                }
+                            }
+                        }
+
+                        body.push_str("</g>\n");
+                    }
+
+                    if link.is_some() {
+                        body.push_str("</a>\n");
+                    }
+                }
+            }
+        }
+
+        let mut svg = String::new();
+        writeln!(svg,
+                 "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                  xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{0}\" height=\"{1}\" \
+                  viewBox=\"0 0 {0} {1}\">",
+                 number(width),
+                 number(height)).unwrap();
+        if !defs.is_empty() {
+            svg.push_str("<defs>\n");
+            svg.push_str(&defs);
+            svg.push_str("</defs>\n");
+        }
+        svg.push_str(&body);
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Returns the outline of `glyph` as SVG path data with Y pointing down, or `None` if it has no
+/// outline (e.g. a space).
+fn glyph_path_data(font: &Font, glyph: Glyph) -> Option<String> {
+    let mut builder = PathDataBuilder(String::new());
+    font.outline_glyph(glyph, &mut builder);
+    if builder.0.is_empty() {
+        None
+    } else {
+        Some(builder.0)
+    }
+}
+
+struct PathDataBuilder(String);
+
+impl PathDataBuilder {
+    fn push_command(&mut self, command: char, points: &[(f32, f32)]) {
+        if !self.0.is_empty() {
+            self.0.push(' ')
+        }
+        self.0.push(command);
+        for (index, &(x, y)) in points.iter().enumerate() {
+            if index > 0 {
+                self.0.push(' ')
+            }
+            write!(self.0, "{} {}", number(x), number(-y)).unwrap();
+        }
+    }
+}
+
+impl OutlineBuilder for PathDataBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.push_command('M', &[(x, y)])
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.push_command('L', &[(x, y)])
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.push_command('Q', &[(x1, y1), (x, y)])
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.push_command('C', &[(x1, y1), (x2, y2), (x, y)])
+    }
+
+    fn close(&mut self) {
+        self.push_command('Z', &[])
+    }
+}
+
+/// Formats `value` rounded to two decimal places, without trailing zeros.
+pub(crate) fn number(value: f32) -> String {
+    let string = format!("{:.2}", value);
+    let string = string.trim_end_matches('0').trim_end_matches('.');
+    if string == "-0" {
+        "0".to_owned()
+    } else {
+        string.to_owned()
+    }
+}
+
+fn hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for ch in string.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,118 @@
+// pilcrow/tests/bidi.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{BaseDirection, Document, Framesetter, Paragraph, ParagraphStyle, TextAlignment};
+use pilcrow::TextDirection::{self, LeftToRight, RightToLeft};
+use std::ops::Range;
+
+/// Two Hebrew words around a Latin one.
+const HEBREW_TEXT: &str = "\u{5e9}\u{5dc}\u{5d5}\u{5dd} abc \u{5e2}\u{5d5}\u{5dc}\u{5dd}";
+
+/// Latin words around Hebrew words with a number between them.
+const LATIN_TEXT: &str = "one \u{5e9}\u{5dc}\u{5d5}\u{5dd} 12 \u{5e2}\u{5d5} two";
+
+const WIDTH: f32 = 300.0;
+
+fn layout(text: &str, style: ParagraphStyle) -> pilcrow::Line {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string(text, style));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(WIDTH, 1000.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+    let mut lines = section.frames()[0].lines();
+    assert_eq!(lines.len(), 1);
+    lines.pop().unwrap()
+}
+
+fn layout_with_direction(text: &str, base_direction: BaseDirection) -> pilcrow::Line {
+    layout(text, ParagraphStyle { base_direction, ..ParagraphStyle::default() })
+}
+
+/// Returns the character range and direction of each run of `line`, from left to right.
+fn visual_runs(line: &pilcrow::Line) -> Vec<(Range<usize>, TextDirection)> {
+    line.runs().iter().map(|run| (run.char_range(), run.direction())).collect()
+}
+
+fn is_flush_right(line: &pilcrow::Line) -> bool {
+    (line.origin.x + line.typographic_bounds().width - WIDTH).abs() < 0.01
+}
+
+#[test]
+fn runs_are_in_visual_order() {
+    // The spaces between the Hebrew words belong to the right-to-left runs around them.
+    let line = layout_with_direction(HEBREW_TEXT, BaseDirection::Auto);
+    assert_eq!(visual_runs(&line),
+               vec![(8..13, RightToLeft), (5..8, LeftToRight), (0..5, RightToLeft)]);
+
+    // Numbers read left to right inside right-to-left text.
+    let line = layout_with_direction(LATIN_TEXT, BaseDirection::Auto);
+    assert_eq!(visual_runs(&line), vec![
+        (0..4, LeftToRight),
+        (11..14, RightToLeft),
+        (9..11, LeftToRight),
+        (4..9, RightToLeft),
+        (14..18, LeftToRight),
+    ]);
+}
+
+#[test]
+fn glyphs_of_right_to_left_runs_run_from_right_to_left() {
+    let line = layout_with_direction(HEBREW_TEXT, BaseDirection::Auto);
+    let positions: Vec<f32> = (0..4).map(|index| line.inline_position_for_char_index(index))
+                                    .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", positions);
+
+    // The end of the paragraph is at the left.
+    assert_eq!(line.inline_position_for_char_index(HEBREW_TEXT.chars().count()), 0.0);
+}
+
+#[test]
+fn base_direction_decides_the_start_of_lines() {
+    let line = layout_with_direction(HEBREW_TEXT, BaseDirection::Auto);
+    assert!(is_flush_right(&line));
+    let line = layout_with_direction(LATIN_TEXT, BaseDirection::Auto);
+    assert_eq!(line.origin.x, 0.0);
+
+    // An explicit direction overrides the text.
+    let line = layout_with_direction(HEBREW_TEXT, BaseDirection::LeftToRight);
+    assert_eq!(line.origin.x, 0.0);
+    assert_eq!(visual_runs(&line),
+               vec![(0..4, RightToLeft), (4..9, LeftToRight), (9..13, RightToLeft)]);
+    let line = layout_with_direction("abc", BaseDirection::RightToLeft);
+    assert!(is_flush_right(&line));
+    assert_eq!(visual_runs(&line), vec![(0..3, LeftToRight)]);
+
+    // End alignment is on the left in right-to-left paragraphs.
+    let style = ParagraphStyle {
+        alignment: TextAlignment::End,
+        last_line_alignment: TextAlignment::End,
+        ..ParagraphStyle::default()
+    };
+    assert_eq!(layout(HEBREW_TEXT, style).origin.x, 0.0);
+}
+
+#[test]
+fn hit_testing_finds_characters_across_direction_changes() {
+    for &text in &[HEBREW_TEXT, LATIN_TEXT] {
+        let line = layout_with_direction(text, BaseDirection::Auto);
+        let runs = visual_runs(&line);
+        for index in 0..text.chars().count() {
+            // Just inside the leading edge of the character, which is its right edge if it's
+            // right-to-left.
+            let &(_, direction) = runs.iter().find(|run| run.0.contains(&index)).unwrap();
+            let x = line.inline_position_for_char_index(index);
+            let x = if direction == RightToLeft { x - 0.5 } else { x + 0.5 };
+            assert_eq!(line.char_index_for_position(&Point2D::new(x, 0.0)), Some(index));
+        }
+    }
+}
@@ -0,0 +1,336 @@
+// pilcrow/tests/document.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate pilcrow;
+
+use pilcrow::{Color, Document, Format, FormatKind, Paragraph, ParagraphStyle, TextLocation};
+use std::cmp;
+
+fn red() -> Color {
+    Color::new(255, 0, 0, 255)
+}
+
+fn blue() -> Color {
+    Color::new(0, 0, 255, 255)
+}
+
+/// Returns the colors of the character at `position`, or, in an empty paragraph, those that
+/// typing takes.
+fn colors_at(paragraph: &mut Paragraph, position: usize) -> Vec<Color> {
+    let end = cmp::min(position + 1, paragraph.char_len());
+    paragraph.edit_range(position..end).format_stack().iter().filter_map(Format::color).collect()
+}
+
+fn red_and_blue_paragraph() -> Paragraph {
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_format(Format::from_color(red()));
+        cursor.push_string("red ");
+        cursor.pop_format();
+        cursor.push_format(Format::from_color(blue()));
+        cursor.push_string("blue");
+        cursor.commit();
+    }
+    paragraph
+}
+
+#[test]
+fn edit_at_inserts_in_the_middle() {
+    let mut paragraph = Paragraph::from_string("Hello world", ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(5);
+        cursor.push_string(",");
+        cursor.commit();
+    }
+    let mut buffer = String::new();
+    paragraph.copy_string_in_range(&mut buffer, 0..paragraph.char_len());
+    assert_eq!(buffer, "Hello, world");
+    assert_eq!(paragraph.char_len(), 12);
+}
+
+#[test]
+fn edit_at_inherits_and_preserves_formats() {
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_format(Format::from_color(red()));
+        cursor.push_string("red ");
+        cursor.pop_format();
+        cursor.push_format(Format::from_color(blue()));
+        cursor.push_string("blue");
+        cursor.commit();
+    }
+
+    assert_eq!(colors_at(&mut paragraph, 1), vec![red()]);
+    assert_eq!(colors_at(&mut paragraph, 5), vec![blue()]);
+
+    // Text inserted inside a span picks up that span's formatting and splits it.
+    {
+        let mut cursor = paragraph.edit_at(1);
+        assert_eq!(cursor.format_stack().len(), 1);
+        cursor.push_format(Format::from_color(blue()));
+        cursor.push_string("é");
+        cursor.commit();
+    }
+    assert_eq!(colors_at(&mut paragraph, 0), vec![red()]);
+    assert_eq!(colors_at(&mut paragraph, 1), vec![red(), blue()]);
+    assert_eq!(colors_at(&mut paragraph, 2), vec![red()]);
+    assert_eq!(colors_at(&mut paragraph, 6), vec![blue()]);
+}
+
+#[test]
+fn edit_at_and_replace_range_agree_at_a_format_boundary() {
+    let mut paragraph = red_and_blue_paragraph();
+    {
+        let mut cursor = paragraph.edit_at(4);
+        let colors: Vec<Color> = cursor.format_stack().iter().filter_map(Format::color).collect();
+        assert_eq!(colors, vec![red()]);
+        cursor.push_string("x");
+        cursor.commit();
+    }
+
+    let mut document = Document::new();
+    document.append_paragraph(red_and_blue_paragraph());
+    document.replace_range(TextLocation::new(0, 4)..TextLocation::new(0, 4), "x");
+
+    assert_eq!(colors_at(&mut paragraph, 4), vec![red()]);
+    assert_eq!(colors_at(&mut document.paragraphs_mut()[0], 4), vec![red()]);
+}
+
+#[test]
+fn copy_string_in_range_uses_character_indices() {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("naïve café", ParagraphStyle::default()));
+    document.append_paragraph(Paragraph::from_string("日本語", ParagraphStyle::default()));
+    assert_eq!(document.copy_string_in_range(TextLocation::new(0, 6)..TextLocation::new(1, 2)),
+               "café\n日本");
+    assert_eq!(document.copy_string(), "naïve café\n日本語");
+    assert_eq!(document.paragraphs()[0].word_range_at_char_index(2), 0..5);
+}
+
+#[test]
+fn edit_range_replaces_characters_and_keeps_formats_around_them() {
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_format(Format::from_color(red()));
+        cursor.push_string("red ");
+        cursor.pop_format();
+        cursor.push_format(Format::from_color(blue()));
+        cursor.push_string("blue");
+        cursor.commit();
+    }
+
+    // The new text takes the formats of the first character replaced.
+    {
+        let mut cursor = paragraph.edit_range(2..6);
+        cursor.push_string("é");
+        cursor.commit();
+    }
+    let mut buffer = String::new();
+    paragraph.copy_string_in_range(&mut buffer, 0..paragraph.char_len());
+    assert_eq!(buffer, "reéue");
+    assert_eq!(colors_at(&mut paragraph, 2), vec![red()]);
+    assert_eq!(colors_at(&mut paragraph, 3), vec![blue()]);
+
+    // Committing without pushing anything deletes.
+    paragraph.edit_range(0..3).commit();
+    assert_eq!(paragraph.char_len(), 2);
+    assert_eq!(colors_at(&mut paragraph, 0), vec![blue()]);
+}
+
+#[test]
+fn replace_range_merges_the_paragraphs_it_spans() {
+    let mut document = Document::new();
+    let style = ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() };
+    document.append_paragraph(Paragraph::from_string("one two", style));
+    document.append_paragraph(Paragraph::from_string("three", ParagraphStyle::default()));
+    document.append_paragraph(Paragraph::from_string("four five", ParagraphStyle::default()));
+
+    let range = document.replace_range(TextLocation::new(0, 4)..TextLocation::new(2, 5), "2 ");
+    assert_eq!(range, TextLocation::new(0, 4)..TextLocation::new(0, 6));
+    assert_eq!(document.copy_string(), "one 2 five");
+    assert_eq!(document.paragraphs().len(), 1);
+    assert_eq!(document.paragraphs()[0].style().first_line_indent, 10.0);
+
+    // Deleting across a paragraph boundary joins the paragraphs.
+    document.append_paragraph(Paragraph::from_string("six", ParagraphStyle::default()));
+    document.delete_range(TextLocation::new(0, 10)..TextLocation::new(1, 0));
+    assert_eq!(document.copy_string(), "one 2 fivesix");
+    assert_eq!(document.paragraphs().len(), 1);
+}
+
+#[test]
+fn replace_range_starts_paragraphs_at_newlines() {
+    let mut document = Document::new();
+    let style = ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() };
+    document.append_paragraph(Paragraph::from_string("ab", style));
+
+    let range = document.replace_range(TextLocation::new(0, 1)..TextLocation::new(0, 1),
+                                       "1\n22\n333");
+    assert_eq!(range, TextLocation::new(0, 1)..TextLocation::new(2, 3));
+    assert_eq!(document.copy_string(), "a1\n22\n333b");
+    assert!(document.paragraphs().iter().all(|paragraph| {
+        paragraph.style().first_line_indent == 10.0
+    }));
+
+    // What's copied from a range can be pasted back over it.
+    let entire_range = document.entire_range();
+    let string = document.copy_string_in_range(entire_range.clone());
+    assert_eq!(document.replace_range(entire_range.clone(), &string), entire_range);
+    assert_eq!(document.copy_string(), "a1\n22\n333b");
+
+    // An empty document gets a paragraph to hold the text.
+    let mut document = Document::new();
+    document.replace_range(TextLocation::beginning()..TextLocation::beginning(), "x\ny");
+    assert_eq!(document.copy_string(), "x\ny");
+}
+
+#[test]
+fn typing_takes_the_formats_of_the_character_before_the_caret() {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("one two three", ParagraphStyle::default()));
+    document.add_format(TextLocation::new(0, 4)..TextLocation::new(0, 7),
+                        Format::from_color(red()));
+
+    let colors_at = |document: &Document, character_index| {
+        document.formats_at(TextLocation::new(0, character_index))
+                .iter()
+                .filter_map(Format::color)
+                .collect::<Vec<_>>()
+    };
+
+    // Right after the span, typing continues it; right before it, it doesn't.
+    document.replace_range(TextLocation::new(0, 7)..TextLocation::new(0, 7), "s");
+    document.replace_range(TextLocation::new(0, 4)..TextLocation::new(0, 4), "x");
+    assert_eq!(document.copy_string(), "one xtwos three");
+    assert_eq!(colors_at(&document, 4), vec![]);
+    assert_eq!(colors_at(&document, 8), vec![red()]);
+    assert_eq!(colors_at(&document, 9), vec![]);
+
+    // At the start of a paragraph, there's only the character after the caret.
+    document.add_format(TextLocation::new(0, 0)..TextLocation::new(0, 3),
+                        Format::from_color(blue()));
+    document.replace_range(TextLocation::new(0, 0)..TextLocation::new(0, 0), "a");
+    assert_eq!(colors_at(&document, 0), vec![blue()]);
+}
+
+#[test]
+fn split_paragraph_at_carries_style_and_formats() {
+    let mut document = Document::new();
+    let style = ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() };
+    let mut paragraph = Paragraph::new(style);
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_string("plain ");
+        cursor.push_format(Format::from_color(red()));
+        cursor.push_string("red");
+        cursor.commit();
+    }
+    document.append_paragraph(paragraph);
+
+    document.split_paragraph_at(TextLocation::new(0, 7));
+    assert_eq!(document.copy_string(), "plain r\ned");
+    assert_eq!(document.paragraphs()[1].style().first_line_indent, 10.0);
+    assert_eq!(colors_at(&mut document.paragraphs_mut()[1], 0), vec![red()]);
+
+    // A paragraph split off at the end is empty, but typing in it continues the formatting.
+    document.split_paragraph_at(TextLocation::new(1, 2));
+    assert_eq!(document.paragraphs().len(), 3);
+    assert_eq!(document.paragraphs()[2].char_len(), 0);
+    assert_eq!(colors_at(&mut document.paragraphs_mut()[2], 0), vec![red()]);
+}
+
+#[test]
+fn merge_paragraph_with_next_undoes_a_split() {
+    let mut document = Document::new();
+    let style = ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() };
+    document.append_paragraph(Paragraph::from_string("one", style));
+    document.append_paragraph(Paragraph::from_string("two", ParagraphStyle::default()));
+
+    assert_eq!(document.merge_paragraph_with_next(0), TextLocation::new(0, 3));
+    assert_eq!(document.copy_string(), "onetwo");
+    assert_eq!(document.paragraphs()[0].style().first_line_indent, 10.0);
+
+    document.split_paragraph_at(TextLocation::new(0, 3));
+    assert_eq!(document.copy_string(), "one\ntwo");
+}
+
+#[test]
+fn paragraphs_can_be_inserted_and_removed_anywhere() {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("one", ParagraphStyle::default()));
+    document.append_paragraph(Paragraph::from_string("three", ParagraphStyle::default()));
+
+    document.insert_paragraph(1, Paragraph::from_string("two", ParagraphStyle::default()));
+    document.insert_paragraph(0, Paragraph::from_string("zero", ParagraphStyle::default()));
+    assert_eq!(document.copy_string(), "zero\none\ntwo\nthree");
+
+    let paragraph = document.remove_paragraph(2);
+    assert_eq!(paragraph.char_len(), 3);
+    assert_eq!(document.copy_string(), "zero\none\nthree");
+}
+
+#[test]
+fn add_format_replaces_formats_of_the_same_kind_across_paragraphs() {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("one two", ParagraphStyle::default()));
+    document.append_paragraph(Paragraph::from_string("three", ParagraphStyle::default()));
+    document.add_format(TextLocation::new(0, 4)..TextLocation::new(1, 2),
+                        Format::from_color(red()));
+    document.add_format(TextLocation::new(1, 0)..TextLocation::new(1, 5),
+                        Format::from_link(1, "https://example.com/".to_owned()));
+
+    let kinds_at = |document: &Document, paragraph_index, character_index| {
+        document.formats_at(TextLocation::new(paragraph_index, character_index))
+                .iter()
+                .map(Format::kind)
+                .collect::<Vec<_>>()
+    };
+    assert_eq!(kinds_at(&document, 0, 3), vec![]);
+    assert_eq!(kinds_at(&document, 0, 4), vec![FormatKind::Color]);
+    assert_eq!(kinds_at(&document, 1, 1), vec![FormatKind::Color, FormatKind::Link]);
+    assert_eq!(kinds_at(&document, 1, 2), vec![FormatKind::Link]);
+
+    // A format of a kind that's already there takes its place.
+    document.add_format(TextLocation::new(0, 0)..TextLocation::new(1, 5),
+                        Format::from_color(blue()));
+    for &(paragraph_index, character_index) in &[(0, 0), (0, 4), (1, 1), (1, 4)] {
+        let location = TextLocation::new(paragraph_index, character_index);
+        let colors: Vec<Color> =
+            document.formats_at(location).iter().filter_map(Format::color).collect();
+        assert_eq!(colors, vec![blue()]);
+    }
+    assert_eq!(document.copy_string(), "one two\nthree");
+}
+
+#[test]
+fn remove_format_clears_only_one_kind() {
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("one two three", ParagraphStyle::default()));
+    let entire_range = document.entire_range();
+    document.add_format(entire_range.clone(), Format::from_color(red()));
+    document.add_format(TextLocation::new(0, 4)..TextLocation::new(0, 7),
+                        Format::from_link(1, "https://example.com/".to_owned()));
+
+    document.remove_format(entire_range, FormatKind::Link);
+    assert_eq!(colors_at(&mut document.paragraphs_mut()[0], 5), vec![red()]);
+    assert!(document.formats_at(TextLocation::new(0, 5)).iter().all(|format| {
+        format.link().is_none()
+    }));
+
+    // Format changes are undone like any other edit.
+    document.undo();
+    assert!(document.formats_at(TextLocation::new(0, 5))[1].link().is_some());
+    document.undo();
+    document.undo();
+    assert!(document.formats_at(TextLocation::new(0, 5)).is_empty());
+}
@@ -0,0 +1,174 @@
+// pilcrow/tests/font.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{Document, FONT_WEIGHT_BOLD, FONT_WEIGHT_NORMAL, Font, FontCollection, FontStyle};
+use pilcrow::{Format, Framesetter, Paragraph, ParagraphStyle};
+use std::fs;
+use std::path::PathBuf;
+
+fn fonts_directory() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fonts")
+}
+
+/// Wraps the TrueType font `font` in a font collection, followed by `bad_face_count` faces that
+/// point past the end of the data.
+fn font_collection(font: &[u8], bad_face_count: u32) -> Vec<u8> {
+    let face_count = 1 + bad_face_count;
+    let header_len = 12 + 4 * face_count;
+    let mut data = b"ttcf".to_vec();
+    data.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    data.extend_from_slice(&face_count.to_be_bytes());
+    data.extend_from_slice(&header_len.to_be_bytes());
+    for _ in 0..bad_face_count {
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+    }
+
+    // Table offsets in a collection count from the start of the file.
+    let mut font = font.to_vec();
+    let table_count = u16::from_be_bytes([font[4], font[5]]) as usize;
+    for table_index in 0..table_count {
+        let offset_index = 12 + 16 * table_index + 8;
+        let mut offset = [0; 4];
+        offset.copy_from_slice(&font[offset_index..(offset_index + 4)]);
+        let offset = u32::from_be_bytes(offset) + header_len;
+        font[offset_index..(offset_index + 4)].copy_from_slice(&offset.to_be_bytes());
+    }
+    data.extend_from_slice(&font);
+    data
+}
+
+#[test]
+fn collection_scans_directory() {
+    let collection = FontCollection::from_directory(fonts_directory()).unwrap();
+    let families = collection.families();
+    assert!(families.contains(&"DejaVu Serif"));
+    assert!(families.contains(&"DejaVu Sans Mono"));
+    assert_eq!(families.len(), 2);
+}
+
+#[test]
+fn collection_skips_files_that_fail_to_load() {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("broken_fonts");
+    fs::create_dir_all(&directory).unwrap();
+    fs::copy(fonts_directory().join("DejaVuSerif.ttf"), directory.join("DejaVuSerif.ttf"))
+        .unwrap();
+    fs::write(directory.join("Broken.ttf"), b"not a font").unwrap();
+
+    let collection = FontCollection::from_directory(&directory).unwrap();
+    assert_eq!(collection.families(), vec!["DejaVu Serif"]);
+}
+
+#[test]
+fn collections_with_a_bad_face_add_no_faces() {
+    let font = fs::read(fonts_directory().join("DejaVuSerif.ttf")).unwrap();
+    let mut collection = FontCollection::new();
+    collection.add_bytes(font_collection(&font, 0)).unwrap();
+    assert_eq!(collection.families(), vec!["DejaVu Serif"]);
+
+    let mut collection = FontCollection::new();
+    assert!(collection.add_bytes(font_collection(&font, 1)).is_err());
+    assert!(collection.families().is_empty());
+}
+
+#[test]
+fn collection_matches_weight_and_style() {
+    let collection = FontCollection::from_directory(fonts_directory()).unwrap();
+    let regular = collection.select("dejavu serif", FONT_WEIGHT_NORMAL, FontStyle::Normal, 16.0)
+                            .unwrap();
+    let bold = collection.select("DejaVu Serif", FONT_WEIGHT_BOLD, FontStyle::Normal, 16.0)
+                         .unwrap();
+    let semibold = collection.select("DejaVu Serif", 600, FontStyle::Normal, 16.0).unwrap();
+    let light = collection.select("DejaVu Serif", 300, FontStyle::Normal, 16.0).unwrap();
+    assert_ne!(regular.face_id(), bold.face_id());
+    assert_eq!(semibold.face_id(), bold.face_id());
+    assert_eq!(light.face_id(), regular.face_id());
+    assert_eq!(bold.size(), 16.0);
+
+    // There's no bold italic face, so the italic face wins over the bold upright one.
+    let bold_italic = collection.select("DejaVu Serif", FONT_WEIGHT_BOLD, FontStyle::Italic, 16.0)
+                                .unwrap();
+    let italic = collection.select("DejaVu Serif", FONT_WEIGHT_NORMAL, FontStyle::Italic, 16.0)
+                           .unwrap();
+    assert_eq!(bold_italic.face_id(), italic.face_id());
+
+    assert!(collection.select("Comic Sans", FONT_WEIGHT_NORMAL, FontStyle::Normal, 16.0)
+                      .is_none());
+}
+
+#[test]
+fn variants_come_from_the_collection() {
+    let collection = FontCollection::from_directory(fonts_directory()).unwrap();
+    let regular = collection.select("DejaVu Serif", FONT_WEIGHT_NORMAL, FontStyle::Normal, 16.0)
+                            .unwrap();
+    let bold = regular.to_bold().unwrap();
+    assert_ne!(bold.face_id(), regular.face_id());
+    assert_ne!(regular.to_italic().unwrap().face_id(), regular.face_id());
+    assert_eq!(regular.to_size(24.0).to_bold().unwrap().face_id(), bold.face_id());
+
+    let monospace = collection.select("DejaVu Sans Mono",
+                                      FONT_WEIGHT_NORMAL,
+                                      FontStyle::Normal,
+                                      12.0).unwrap();
+    assert!(monospace.to_bold().is_none());
+    assert!(monospace.to_italic().is_none());
+}
+
+#[test]
+fn fonts_load_from_bytes_and_paths() {
+    let path = fonts_directory().join("DejaVuSerif.ttf");
+    let from_path = Font::from_path(&path, 0, 12.0).unwrap();
+    assert_eq!(from_path.size(), 12.0);
+    assert!(from_path.to_bold().is_none());
+
+    let from_bytes = Font::from_bytes(fs::read(&path).unwrap(), 0, 12.0).unwrap();
+    assert_ne!(from_bytes.face_id(), from_path.face_id());
+
+    assert!(Font::from_bytes(b"not a font".to_vec(), 0, 12.0).is_err());
+    assert!(Font::from_path(&path, 1, 12.0).is_err());
+    assert!(Font::from_path(fonts_directory().join("missing.ttf"), 0, 12.0).is_err());
+}
+
+#[test]
+fn missing_glyphs_fall_back_to_document_fonts() {
+    let directory = fonts_directory();
+    let monospace = Font::from_path(directory.join("DejaVuSansMono.ttf"), 0, 12.0).unwrap();
+    let serif = Font::from_path(directory.join("DejaVuSerif.ttf"), 0, 20.0).unwrap();
+
+    // DejaVu Sans Mono has no glyph for U+01C4 LATIN CAPITAL LETTER DZ WITH CARON.
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_format(Format::from_font(monospace.clone()));
+        cursor.push_string("ab \u{1c4} cd");
+        cursor.commit();
+    }
+    let mut document = Document::new();
+    document.append_paragraph(paragraph);
+    document.style_mut().fallback_fonts.push(serif.clone());
+
+    let framesetter = Framesetter::new(&document);
+    let rect = Rect::new(Point2D::zero(), Size2D::new(500.0, 500.0));
+    let section = framesetter.layout_in_rect(&rect, None);
+    let runs = section.frames()[0].lines()[0].runs();
+    let run_fonts: Vec<(Font, _)> = runs.iter().map(|run| {
+        let font = run.formatting().iter().filter_map(|format| format.font()).next().unwrap();
+        (font, run.char_range())
+    }).collect();
+    assert_eq!(run_fonts.len(), 3);
+    assert_eq!(run_fonts[0].0.face_id(), monospace.face_id());
+    assert_eq!(run_fonts[1].0.face_id(), serif.face_id());
+    assert_eq!(run_fonts[1].0.size(), 12.0);
+    assert_eq!(run_fonts[1].1.start, 3);
+    assert_eq!(run_fonts[2].0.face_id(), monospace.face_id());
+}
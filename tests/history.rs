@@ -0,0 +1,177 @@
+// pilcrow/tests/history.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate pilcrow;
+
+use pilcrow::{Color, Document, Format, Paragraph, ParagraphStyle, TextLocation};
+
+fn location(paragraph_index: usize, character_index: usize) -> TextLocation {
+    TextLocation::new(paragraph_index, character_index)
+}
+
+fn indented() -> ParagraphStyle {
+    ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() }
+}
+
+fn document() -> Document {
+    let mut document = Document::new();
+    let mut paragraph = Paragraph::new(indented());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_string("one ");
+        cursor.push_format(Format::from_color(Color::new(255, 0, 0, 255)));
+        cursor.push_string("two");
+        cursor.commit();
+    }
+    document.append_paragraph(paragraph);
+    document.append_paragraph(Paragraph::from_string("three", ParagraphStyle::default()));
+    document
+}
+
+fn indents(document: &Document) -> Vec<f32> {
+    document.paragraphs().iter().map(|paragraph| paragraph.style().first_line_indent).collect()
+}
+
+fn type_string(document: &mut Document, mut location: TextLocation, string: &str) {
+    for ch in string.chars() {
+        location = document.replace_range(location..location, &ch.to_string()).end
+    }
+}
+
+#[test]
+fn typing_is_undone_a_word_at_a_time() {
+    let mut document = Document::new();
+    type_string(&mut document, location(0, 0), "Hello, world");
+    assert_eq!(document.copy_string(), "Hello, world");
+
+    assert_eq!(document.undo(), Some(location(0, 7)..location(0, 7)));
+    assert_eq!(document.copy_string(), "Hello, ");
+    assert_eq!(document.undo(), Some(location(0, 0)..location(0, 0)));
+    assert_eq!(document.copy_string(), "");
+    assert_eq!(document.undo(), None);
+
+    assert_eq!(document.redo(), Some(location(0, 0)..location(0, 7)));
+    assert_eq!(document.redo(), Some(location(0, 7)..location(0, 12)));
+    assert_eq!(document.copy_string(), "Hello, world");
+    assert!(!document.can_redo());
+
+    // Typing somewhere else starts a new unit.
+    type_string(&mut document, location(0, 5), "!!");
+    assert_eq!(document.copy_string(), "Hello!!, world");
+    document.undo();
+    assert_eq!(document.copy_string(), "Hello, world");
+}
+
+#[test]
+fn undo_restores_merged_paragraphs_with_their_styles_and_formats() {
+    let mut document = document();
+    document.replace_range(location(0, 5)..location(1, 2), "X\nY");
+    assert_eq!(document.copy_string(), "one tX\nYree");
+    assert_eq!(indents(&document), vec![10.0, 10.0]);
+
+    assert_eq!(document.undo(), Some(location(0, 5)..location(1, 2)));
+    assert_eq!(document.copy_string(), "one two\nthree");
+    assert_eq!(indents(&document), vec![10.0, 0.0]);
+    assert_eq!(document.paragraphs_mut()[0].edit_at(5).format_stack().len(), 1);
+
+    assert_eq!(document.redo(), Some(location(0, 5)..location(1, 1)));
+    assert_eq!(document.copy_string(), "one tX\nYree");
+
+    // Making an edit forgets what could have been redone.
+    document.undo();
+    document.delete_range(location(0, 0)..location(0, 4));
+    assert!(!document.can_redo());
+    assert_eq!(document.redo(), None);
+}
+
+#[test]
+fn paragraph_operations_are_undone() {
+    let mut document = document();
+    document.split_paragraph_at(location(0, 4));
+    document.merge_paragraph_with_next(1);
+    document.insert_paragraph(0, Paragraph::from_string("zero", ParagraphStyle::default()));
+    assert_eq!(document.copy_string(), "zero\none \ntwothree");
+    assert_eq!(indents(&document), vec![0.0, 10.0, 10.0]);
+
+    document.undo();
+    assert_eq!(document.copy_string(), "one \ntwothree");
+    document.undo();
+    assert_eq!(document.copy_string(), "one \ntwo\nthree");
+    document.undo();
+    assert_eq!(document.copy_string(), "one two\nthree");
+    assert_eq!(indents(&document), vec![10.0, 0.0]);
+
+    document.redo();
+    document.redo();
+    document.redo();
+    assert_eq!(document.copy_string(), "zero\none \ntwothree");
+    assert_eq!(indents(&document), vec![0.0, 10.0, 10.0]);
+}
+
+#[test]
+fn groups_are_undone_together() {
+    let mut document = document();
+    document.begin_undo_group();
+    document.delete_range(location(0, 0)..location(0, 4));
+    document.begin_undo_group();
+    type_string(&mut document, location(0, 0), "2 ");
+    document.end_undo_group();
+    document.split_paragraph_at(location(0, 5));
+    document.end_undo_group();
+    assert_eq!(document.copy_string(), "2 two\n\nthree");
+
+    // The range returned covers everything that the group's edits changed.
+    assert_eq!(document.undo(), Some(location(0, 0)..location(0, 7)));
+    assert_eq!(document.copy_string(), "one two\nthree");
+    assert!(!document.can_undo());
+    assert_eq!(document.redo(), Some(location(0, 0)..location(1, 0)));
+    assert_eq!(document.copy_string(), "2 two\n\nthree");
+}
+
+#[test]
+fn undo_returns_the_range_that_all_of_a_group_changed() {
+    let mut document = document();
+    document.begin_undo_group();
+    document.replace_range(location(0, 0)..location(0, 3), "1");
+    document.replace_range(location(1, 0)..location(1, 5), "3");
+    document.end_undo_group();
+    assert_eq!(document.copy_string(), "1 two\n3");
+
+    // Later steps move the ranges of the earlier ones.
+    assert_eq!(document.undo(), Some(location(0, 0)..location(1, 5)));
+    assert_eq!(document.copy_string(), "one two\nthree");
+    assert_eq!(document.redo(), Some(location(0, 0)..location(1, 1)));
+    assert_eq!(document.copy_string(), "1 two\n3");
+
+    document.begin_undo_group();
+    document.insert_paragraph(0, Paragraph::from_string("zero", ParagraphStyle::default()));
+    document.replace_range(location(1, 0)..location(1, 1), "one");
+    document.end_undo_group();
+    assert_eq!(document.undo(), Some(location(0, 0)..location(0, 1)));
+    assert_eq!(document.redo(), Some(location(0, 0)..location(1, 3)));
+}
+
+#[test]
+fn undoing_the_first_edit_of_an_empty_document_removes_its_paragraph() {
+    let mut document = Document::new();
+    document.replace_range(location(0, 0)..location(0, 0), "");
+    assert!(document.paragraphs().is_empty());
+    assert!(!document.can_undo());
+
+    type_string(&mut document, location(0, 0), "Hi");
+    assert_eq!(document.paragraphs().len(), 1);
+    document.undo();
+    assert!(document.paragraphs().is_empty());
+    assert!(!document.can_undo());
+
+    document.redo();
+    assert_eq!(document.copy_string(), "Hi");
+    assert_eq!(document.paragraphs().len(), 1);
+}
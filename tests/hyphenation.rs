@@ -0,0 +1,87 @@
+// pilcrow/tests/hyphenation.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::hyphenation::Hyphenator;
+use pilcrow::{Document, Framesetter, Paragraph, ParagraphStyle};
+
+/// The patterns from Liang's thesis that hyphenate "hyphenation".
+const PATTERNS: &str = "hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n";
+
+const TEXT: &str = "A hyphenation of hyphenation hyphenation hyphenation";
+
+fn chars(string: &str) -> Vec<char> {
+    string.chars().collect()
+}
+
+fn layout(language: &str, hyphenate: bool, hyphenation_zone: f32) -> Vec<pilcrow::Line> {
+    Hyphenator::new(PATTERNS, "").register("x-pilcrow-test");
+    let style = ParagraphStyle {
+        language: Some(language.to_owned()),
+        hyphenate,
+        hyphenation_zone,
+        ..ParagraphStyle::default()
+    };
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string(TEXT, style));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(110.0, 1000.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+    assert_eq!(document.copy_string(), TEXT);
+    section.frames()[0].lines()
+}
+
+/// Returns the lines that end in the middle of a word.
+fn hyphenated_lines(lines: &[pilcrow::Line]) -> Vec<&pilcrow::Line> {
+    let text = chars(TEXT);
+    lines.iter().filter(|line| {
+        let end = line.char_range().end;
+        end < text.len() && text[end - 1].is_alphabetic() && text[end].is_alphabetic()
+    }).collect()
+}
+
+#[test]
+fn patterns_find_hyphenation_points() {
+    let hyphenator = Hyphenator::new(PATTERNS, "ta-ble");
+    assert_eq!(hyphenator.hyphenate(&chars("hyphenation")), vec![2, 6]);
+    assert_eq!(hyphenator.hyphenate(&chars("Hyphenation")), vec![2, 6]);
+    assert_eq!(hyphenator.hyphenate(&chars("table")), vec![2]);
+
+    let mut hyphenator = Hyphenator::new(PATTERNS, "");
+    hyphenator.left_min = 3;
+    assert_eq!(hyphenator.hyphenate(&chars("hyphenation")), vec![6]);
+}
+
+#[test]
+fn layout_hyphenates_with_a_visible_hyphen() {
+    let lines = layout("x-pilcrow-test", true, 0.0);
+    let hyphenated = hyphenated_lines(&lines);
+    assert!(!hyphenated.is_empty());
+    for line in hyphenated {
+        // The hyphen is an extra glyph after the last character.
+        let runs = line.runs();
+        let last_run = runs.last().unwrap();
+        let char_count = last_run.char_range().end - last_run.char_range().start;
+        assert_eq!(last_run.glyph_count(), char_count + 1);
+        assert!(line.typographic_bounds().width <= 110.0);
+    }
+}
+
+#[test]
+fn hyphenation_needs_the_toggle_a_language_and_room() {
+    assert!(hyphenated_lines(&layout("x-pilcrow-test", false, 0.0)).is_empty());
+    assert!(hyphenated_lines(&layout("x-unregistered", true, 0.0)).is_empty());
+    assert!(hyphenated_lines(&layout("x-pilcrow-test", true, 1000.0)).is_empty());
+
+    // Subtags fall back to the registered tag.
+    assert!(!hyphenated_lines(&layout("x-pilcrow-test-variant", true, 0.0)).is_empty());
+}
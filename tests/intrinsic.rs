@@ -0,0 +1,128 @@
+// pilcrow/tests/intrinsic.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, SideOffsets2D, Size2D};
+use pilcrow::{Document, Framesetter, Paragraph, ParagraphStyle, Section};
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+/// The widest line is the second.
+const LINES_TEXT: &str = "Short line.\nA rather longer line of text.\nMedium line.";
+
+/// Slack for the rounding in line widths.
+const EPSILON: f32 = 0.01;
+
+fn document(texts: &[&str], style: ParagraphStyle) -> Document {
+    let mut document = Document::new();
+    for text in texts {
+        document.append_paragraph(Paragraph::from_string(text, style.clone()));
+    }
+    document
+}
+
+fn layout(document: &Document, width: f32) -> Section {
+    let rect = Rect::new(Point2D::zero(), Size2D::new(width, 1000.0));
+    Framesetter::new(document).layout_in_rect(&rect, None)
+}
+
+fn line_count(section: &Section) -> usize {
+    section.frames().iter().map(|frame| frame.lines().len()).sum()
+}
+
+fn widest_line(section: &Section) -> f32 {
+    section.frames().iter().flat_map(|frame| frame.lines()).map(|line| {
+        line.typographic_bounds().width
+    }).fold(0.0, f32::max)
+}
+
+fn breaks_only_between_words(section: &Section) -> bool {
+    let chars: Vec<char> = TEXT.chars().collect();
+    section.frames().iter().flat_map(|frame| frame.lines()).all(|line| {
+        let end = line.char_range().end;
+        end == chars.len() || chars[end - 1] == ' '
+    })
+}
+
+#[test]
+fn max_content_width_holds_the_widest_hard_line() {
+    let document = document(&[TEXT, LINES_TEXT], ParagraphStyle::default());
+    let widths = Framesetter::new(&document).intrinsic_widths(None);
+    assert!(widths.min_content > 0.0 && widths.min_content < widths.max_content);
+
+    // Nothing wraps at the max-content width, but the widest line does when it's any narrower.
+    let section = layout(&document, widths.max_content);
+    assert_eq!(line_count(&section), 4);
+    assert!((widest_line(&section) - widths.max_content).abs() < EPSILON);
+    assert_eq!(line_count(&layout(&document, widths.max_content - 1.0)), 5);
+}
+
+#[test]
+fn min_content_width_holds_the_widest_word() {
+    let document = document(&[TEXT], ParagraphStyle::default());
+    let widths = Framesetter::new(&document).intrinsic_widths(None);
+    let word_widths = Framesetter::new(&document).paragraph_intrinsic_widths(0, None);
+    assert_eq!(widths, word_widths);
+
+    // No word is broken at the min-content width, but the widest one is when it's any narrower.
+    assert!(breaks_only_between_words(&layout(&document, widths.min_content)));
+    assert!(!breaks_only_between_words(&layout(&document, widths.min_content - 1.0)));
+}
+
+#[test]
+fn intrinsic_widths_include_margins_and_indents() {
+    let plain_document = document(&[TEXT], ParagraphStyle::default());
+    let plain_widths = Framesetter::new(&plain_document).intrinsic_widths(None);
+
+    let style = ParagraphStyle {
+        margin: SideOffsets2D::new(0.0, 7.0, 0.0, 3.0),
+        first_line_indent: 20.0,
+        hanging_indent: 5.0,
+        ..ParagraphStyle::default()
+    };
+    let mut document = document(&[TEXT], style);
+    document.style_mut().margin = SideOffsets2D::new(1.0, 11.0, 1.0, 13.0);
+    let framesetter = Framesetter::new(&document);
+
+    // The first line is the only one, and the most indented, at the max-content width.
+    let paragraph_widths = framesetter.paragraph_intrinsic_widths(0, None);
+    assert!((paragraph_widths.max_content - (plain_widths.max_content + 30.0)).abs() < EPSILON);
+    assert!(paragraph_widths.min_content >= plain_widths.min_content + 15.0 - EPSILON);
+    assert!(paragraph_widths.min_content <= plain_widths.min_content + 30.0 + EPSILON);
+
+    let widths = framesetter.intrinsic_widths(None);
+    assert!((widths.min_content - (paragraph_widths.min_content + 24.0)).abs() < EPSILON);
+    assert!((widths.max_content - (paragraph_widths.max_content + 24.0)).abs() < EPSILON);
+}
+
+#[test]
+fn height_for_width_matches_layout() {
+    let style = ParagraphStyle {
+        margin: SideOffsets2D::new(4.0, 2.0, 6.0, 2.0),
+        ..ParagraphStyle::default()
+    };
+    let mut document = document(&[TEXT, LINES_TEXT, TEXT], style);
+    document.style_mut().margin = SideOffsets2D::new(10.0, 5.0, 20.0, 5.0);
+    let framesetter = Framesetter::new(&document);
+
+    for &width in &[120.0, 250.0, 1000.0] {
+        let section = layout(&document, width);
+        let last_frame_bottom = section.frames().last().unwrap().bounds().max_y();
+        let expected_height = last_frame_bottom + 6.0 + 20.0;
+        let height = framesetter.height_for_width(width, None);
+        assert!((height - expected_height).abs() < EPSILON, "{} != {}", height, expected_height);
+
+        let paragraph_height = framesetter.paragraph_height_for_width(1, width - 10.0, None);
+        let frame = &section.frames()[1];
+        assert!((paragraph_height - (frame.block_size() + 10.0)).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,538 @@
+// pilcrow/tests/layout.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, SideOffsets2D, Size2D};
+use pilcrow::markdown::{BlockSelector, InlineSelector, MarkdownParser};
+use pilcrow::{BaseDirection, Document, FONT_WEIGHT_NORMAL, FontCollection, FontStyle};
+use pilcrow::{Framesetter, LineHeight, Paragraph, ParagraphStyle, TabAlignment, TabStop};
+use pilcrow::{TextAlignment, TextLocation};
+use std::path::PathBuf;
+
+const MARKDOWN: &str = "# Heading\n\nThe quick brown fox jumps over the lazy dog. The quick brown \
+                        fox jumps over the lazy dog.\n\n---\n\n* One\n* Two `code`\n";
+
+/// Returns a parser that sets text in the fonts in `tests/fonts`, not the system's.
+fn markdown_parser() -> MarkdownParser {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fonts");
+    let fonts = FontCollection::from_directory(path).unwrap();
+    let font = |family, size| fonts.select(family, FONT_WEIGHT_NORMAL, FontStyle::Normal, size)
+                                   .unwrap();
+    let mut parser = MarkdownParser::new();
+    parser.set_font(InlineSelector::Body, font("DejaVu Serif", 16.0));
+    parser.set_font(InlineSelector::Code, font("DejaVu Sans Mono", 12.0));
+    parser.set_font(InlineSelector::Heading1, font("DejaVu Serif", 48.0));
+    parser.set_font(InlineSelector::Heading2, font("DejaVu Serif", 36.0));
+    parser
+}
+
+fn layout(width: f32) -> (Document, pilcrow::Section) {
+    let mut document = Document::new();
+    markdown_parser().add_to_document(&mut document, MARKDOWN);
+    let framesetter = Framesetter::new(&document);
+    let section = framesetter.layout_in_rect(&Rect::new(Point2D::zero(),
+                                                        Size2D::new(width, 1000.0)),
+                                             None);
+    (document, section)
+}
+
+#[test]
+fn frames_stack_vertically() {
+    let (document, section) = layout(300.0);
+    assert_eq!(section.frames().len(), document.paragraphs().len());
+    let mut last_bottom = 0.0;
+    for frame in section.frames() {
+        let bounds = frame.bounds();
+        assert!(bounds.origin.y >= last_bottom);
+        last_bottom = bounds.max_y();
+    }
+}
+
+#[test]
+fn lines_cover_paragraph_text() {
+    let (document, section) = layout(200.0);
+    for (paragraph, frame) in document.paragraphs().iter().zip(section.frames()) {
+        assert_eq!(frame.char_len(), paragraph.char_len());
+        let mut next_char_index = 0;
+        for line in frame.lines() {
+            assert_eq!(line.char_range().start, next_char_index);
+            let last_char_index = line.char_range().end - 1;
+            assert!(line.inline_position_for_char_index(last_char_index) <= 200.0);
+            for run in line.runs() {
+                assert_eq!(run.glyphs().len(), run.positions().len());
+                assert!(!run.formatting().is_empty());
+            }
+            next_char_index = line.char_range().end;
+        }
+        assert_eq!(next_char_index, paragraph.char_len());
+    }
+
+    let body_frame = &section.frames()[1];
+    assert!(body_frame.lines().len() > 1);
+}
+
+#[test]
+fn hit_testing_round_trips() {
+    let (_, section) = layout(300.0);
+    let frame = &section.frames()[1];
+    let line = &frame.lines()[0];
+    let point = Point2D::new(line.origin.x + 1.0, line.origin.y - 1.0);
+    assert_eq!(section.frame_index_at_point(&point), Some(1));
+    assert_eq!(frame.line_index_at_point(&point), Some(0));
+
+    let x = line.inline_position_for_char_index(4);
+    assert!(x > 0.0);
+    assert_eq!(line.char_index_for_position(&Point2D::new(x, 0.0)), Some(4));
+}
+
+#[test]
+fn characters_outside_the_bmp_count_once() {
+    // Each of these letters takes two UTF-16 code units.
+    let text = "\u{1d49c}\u{1d49e} The quick brown fox jumps over the lazy \u{1d4a2} dog.";
+    let char_len = text.chars().count();
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string(text, ParagraphStyle::default()));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(150.0, 1000.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+
+    let frame = &section.frames()[0];
+    assert_eq!(frame.char_range(), 0..char_len);
+    let lines = frame.lines();
+    assert!(lines.len() > 1);
+    assert_eq!(lines[lines.len() - 1].char_range().end, char_len);
+    let runs_end = lines[0].runs().iter().map(|run| run.char_range().end).max();
+    assert_eq!(runs_end, Some(lines[0].char_range().end));
+
+    let line = &lines[0];
+    let x = line.inline_position_for_char_index(3);
+    assert!(x > line.inline_position_for_char_index(2));
+    assert_eq!(line.char_index_for_position(&Point2D::new(x, 0.0)), Some(3));
+}
+
+#[test]
+fn copy_string_spans_paragraphs() {
+    let (document, _) = layout(300.0);
+    let string = document.copy_string_in_range(TextLocation::new(0, 2)..TextLocation::new(1, 3));
+    assert_eq!(string, "ading\nThe");
+}
+
+fn long_document() -> Document {
+    let mut markdown = String::new();
+    for index in 0..12 {
+        markdown.push_str(&format!("## Section {}\n\n", index));
+        for _ in 0..(index % 4 + 1) {
+            markdown.push_str("The quick brown fox jumps over the lazy dog. ");
+        }
+        markdown.push_str("\n\n");
+    }
+    let mut document = Document::new();
+    markdown_parser().add_to_document(&mut document, &markdown);
+    document
+}
+
+#[test]
+fn pages_cover_every_line_once() {
+    let mut document = long_document();
+    document.style_mut().margin = SideOffsets2D::new_all_same(20.0);
+    let page_size = Size2D::new(300.0, 240.0);
+    let pages = Framesetter::new(&document).layout_in_pages(&page_size, None);
+    assert!(pages.len() > 2);
+
+    let mut next_location = TextLocation::new(0, 0);
+    for page in &pages {
+        assert!(!page.frames().is_empty());
+        for frame in page.frames() {
+            let bounds = frame.bounds();
+            assert!(bounds.origin.y >= 20.0);
+            assert!(bounds.max_y() <= page_size.height - 20.0 + 0.001);
+
+            if frame.paragraph_index() != next_location.paragraph_index {
+                let paragraph = &document.paragraphs()[next_location.paragraph_index];
+                assert_eq!(next_location.character_index, paragraph.char_len());
+                next_location = TextLocation::new(next_location.paragraph_index + 1, 0);
+            }
+            assert_eq!(frame.paragraph_index(), next_location.paragraph_index);
+            assert_eq!(frame.char_range().start, next_location.character_index);
+            next_location.character_index = frame.char_range().end;
+        }
+    }
+    assert_eq!(next_location.paragraph_index, document.paragraphs().len() - 1);
+    assert_eq!(next_location.character_index, document.paragraphs().last().unwrap().char_len());
+}
+
+#[test]
+fn paragraphs_split_between_lines_across_pages() {
+    let mut document = Document::new();
+    let markdown = "Lorem ipsum dolor sit amet. ".repeat(40);
+    markdown_parser().add_to_document(&mut document, &markdown);
+    let page_size = Size2D::new(200.0, 100.0);
+    let framesetter = Framesetter::new(&document);
+    let pages = framesetter.layout_in_pages(&page_size, None);
+    assert!(pages.len() > 1);
+
+    let unpaginated = framesetter.layout_in_rect(&Rect::new(Point2D::zero(), page_size), None);
+    let line_count = unpaginated.frames()[0].lines().len();
+    let paginated_line_count: usize = pages.iter().map(|page| {
+        assert_eq!(page.frames().len(), 1);
+        let frame = &page.frames()[0];
+        assert_eq!(frame.paragraph_index(), 0);
+        assert_eq!(frame.bounds().origin.y, 0.0);
+        frame.lines().len()
+    }).sum();
+    assert_eq!(paginated_line_count, line_count);
+}
+
+#[test]
+fn headings_stay_with_the_next_paragraph() {
+    let document = long_document();
+    let framesetter = Framesetter::new(&document);
+    for page_height in (120..400).step_by(7) {
+        let page_size = Size2D::new(300.0, page_height as f32);
+        let pages = framesetter.layout_in_pages(&page_size, None);
+        for page in &pages[..(pages.len() - 1)] {
+            let last_frame = page.frames().last().unwrap();
+            // `long_document()` alternates headings and body paragraphs, starting with a heading.
+            assert!(last_frame.paragraph_index() % 2 == 1,
+                    "Heading stranded at the bottom of a {}-point page",
+                    page_height);
+        }
+    }
+}
+
+#[test]
+fn splits_honor_widows_orphans_and_keep_together() {
+    let mut document = Document::new();
+    let markdown = "The quick brown fox jumps over the lazy dog. ".repeat(12);
+    markdown_parser().add_to_document(&mut document, &markdown);
+    markdown_parser().add_to_document(&mut document, &markdown);
+    {
+        let paragraphs = document.paragraphs_mut();
+        paragraphs[0].style_mut().min_orphan_lines = 3;
+        paragraphs[0].style_mut().min_widow_lines = 3;
+        paragraphs[1].style_mut().keep_together = true;
+    }
+
+    let framesetter = Framesetter::new(&document);
+    let unpaginated = framesetter.layout_in_rect(&Rect::new(Point2D::zero(),
+                                                            Size2D::new(300.0, 1000.0)),
+                                                 None);
+    assert!(unpaginated.frames()[0].lines().len() >= 6);
+    let second_paragraph_height = unpaginated.frames()[1].height();
+
+    for page_height in (90..400).step_by(5) {
+        let page_size = Size2D::new(300.0, page_height as f32);
+        let pages = framesetter.layout_in_pages(&page_size, None);
+        let frames: Vec<_> = pages.iter().flat_map(|page| page.frames()).collect();
+
+        let first_paragraph_frames: Vec<_> = frames.iter().filter(|frame| {
+            frame.paragraph_index() == 0
+        }).collect();
+        if first_paragraph_frames.len() > 1 {
+            for frame in &first_paragraph_frames {
+                assert!(frame.lines().len() >= 3,
+                        "Widow or orphan on a {}-point page",
+                        page_height);
+            }
+        }
+
+        let second_paragraph_frames = frames.iter().filter(|frame| {
+            frame.paragraph_index() == 1
+        }).count();
+        if second_paragraph_height <= page_height as f32 {
+            assert_eq!(second_paragraph_frames, 1, "Split on a {}-point page", page_height);
+        }
+    }
+}
+
+#[test]
+fn paragraphs_flow_through_columns_in_order() {
+    let mut document = long_document();
+    document.style_mut().column_count = 3;
+    document.style_mut().column_gap = 12.0;
+    document.style_mut().column_rule = true;
+    let page_size = Size2D::new(600.0, 300.0);
+    let pages = Framesetter::new(&document).layout_in_pages(&page_size, None);
+    assert!(pages.len() > 1);
+
+    let column_width = (600.0 - 12.0 * 2.0) / 3.0;
+    let mut next_location = TextLocation::new(0, 0);
+    for page in &pages {
+        let mut column_index = 0;
+        for frame in page.frames() {
+            let bounds = frame.bounds();
+            let frame_column_index = (bounds.origin.x / (column_width + 12.0)) as usize;
+            assert!(frame_column_index >= column_index);
+            column_index = frame_column_index;
+            let column_x = (column_width + 12.0) * column_index as f32;
+            assert!(bounds.origin.x >= column_x && bounds.max_x() <= column_x + column_width);
+            assert!(bounds.max_y() <= page_size.height + 0.001);
+
+            if frame.paragraph_index() != next_location.paragraph_index {
+                next_location = TextLocation::new(next_location.paragraph_index + 1, 0);
+            }
+            assert_eq!(frame.paragraph_index(), next_location.paragraph_index);
+            assert_eq!(frame.char_range().start, next_location.character_index);
+            next_location.character_index = frame.char_range().end;
+        }
+        assert_eq!(page.column_rules().len(), column_index);
+        for (rule_index, rule) in page.column_rules().iter().enumerate() {
+            let gap_center = (column_width + 12.0) * (rule_index + 1) as f32 - 6.0;
+            assert!((rule.center().x - gap_center).abs() < 0.001);
+        }
+    }
+    assert_eq!(pages[0].column_rules().len(), 2);
+    assert_eq!(next_location.paragraph_index, document.paragraphs().len() - 1);
+}
+
+#[test]
+fn last_column_of_a_rect_grows_to_fit() {
+    let mut document = long_document();
+    document.style_mut().column_count = 2;
+    document.style_mut().column_gap = 20.0;
+    let rect = Rect::new(Point2D::zero(), Size2D::new(400.0, 200.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+    assert!(section.column_rules().is_empty());
+
+    let frames = section.frames();
+    let first_right_frame_index = frames.iter()
+                                        .position(|frame| frame.bounds().origin.x >= 210.0)
+                                        .unwrap();
+    assert!(first_right_frame_index > 0);
+    assert!(frames[..first_right_frame_index].iter().all(|frame| {
+        frame.bounds().max_y() <= 200.0 + 0.001
+    }));
+    let last_frame = frames.last().unwrap();
+    assert_eq!(last_frame.paragraph_index(), document.paragraphs().len() - 1);
+    assert!(last_frame.bounds().max_y() > 200.0);
+
+    // Hit testing looks in the column under the point.
+    let first_right_bounds = frames[first_right_frame_index].bounds();
+    let point = Point2D::new(300.0, first_right_bounds.center().y);
+    assert_eq!(section.frame_index_at_point(&point), Some(first_right_frame_index));
+    let point = Point2D::new(50.0, frames[0].bounds().center().y);
+    assert_eq!(section.frame_index_at_point(&point), Some(0));
+}
+
+/// Returns the left and right edges of the text in `line`, not counting trailing whitespace.
+fn visible_extent(line: &pilcrow::Line, chars: &[char]) -> (f32, f32) {
+    let mut end = line.char_range().end;
+    while end > line.char_range().start && chars[end - 1].is_whitespace() {
+        end -= 1
+    }
+    (line.origin.x, line.origin.x + line.inline_position_for_char_index(end))
+}
+
+fn layout_aligned(alignment: TextAlignment, last_line_alignment: TextAlignment)
+                  -> (Vec<char>, pilcrow::Section) {
+    let markdown = "The quick brown fox jumps over the lazy dog. ".repeat(4);
+    layout_text_aligned(&markdown, alignment, last_line_alignment)
+}
+
+fn layout_text_aligned(markdown: &str,
+                       alignment: TextAlignment,
+                       last_line_alignment: TextAlignment)
+                       -> (Vec<char>, pilcrow::Section) {
+    let mut parser = markdown_parser();
+    {
+        let style = parser.paragraph_style_mut(BlockSelector::Body);
+        style.alignment = alignment;
+        style.last_line_alignment = last_line_alignment;
+        style.margin = SideOffsets2D::new(0.0, 10.0, 0.0, 30.0);
+    }
+    let mut document = Document::new();
+    parser.add_to_document(&mut document, markdown);
+    let chars = document.copy_string().chars().collect();
+    let rect = Rect::new(Point2D::zero(), Size2D::new(260.0, 1000.0));
+    (chars, Framesetter::new(&document).layout_in_rect(&rect, None))
+}
+
+#[test]
+fn lines_are_aligned_between_the_margins() {
+    for &alignment in &[TextAlignment::Start, TextAlignment::End, TextAlignment::Center] {
+        let (chars, section) = layout_aligned(alignment, TextAlignment::Start);
+        let lines = section.frames()[0].lines();
+        assert!(lines.len() > 2);
+        for line in &lines {
+            let (left, right) = visible_extent(line, &chars);
+            match alignment {
+                TextAlignment::Start => assert_eq!(left, 30.0),
+                TextAlignment::End => assert!((right - 250.0).abs() < 0.01),
+                _ => assert!(((left - 30.0) - (250.0 - right)).abs() < 0.01),
+            }
+        }
+    }
+}
+
+#[test]
+fn justified_lines_fill_the_width_except_the_last() {
+    for &last_line_alignment in &[TextAlignment::Start, TextAlignment::Center] {
+        let (chars, section) = layout_aligned(TextAlignment::Justify, last_line_alignment);
+        let lines = section.frames()[0].lines();
+        let (last_line, other_lines) = lines.split_last().unwrap();
+        for line in other_lines {
+            let (left, right) = visible_extent(line, &chars);
+            assert_eq!(left, 30.0);
+            assert!((right - 250.0).abs() < 0.01);
+        }
+
+        let (left, right) = visible_extent(last_line, &chars);
+        assert!(right < 240.0);
+        if last_line_alignment == TextAlignment::Start {
+            assert_eq!(left, 30.0);
+        } else {
+            assert!(((left - 30.0) - (250.0 - right)).abs() < 0.01);
+        }
+    }
+}
+
+#[test]
+fn right_to_left_justified_lines_stay_between_the_margins() {
+    let mut parser = markdown_parser();
+    {
+        let style = parser.paragraph_style_mut(BlockSelector::Body);
+        style.alignment = TextAlignment::Justify;
+        style.base_direction = BaseDirection::RightToLeft;
+        style.margin = SideOffsets2D::new(0.0, 10.0, 0.0, 30.0);
+    }
+    let mut document = Document::new();
+    let markdown = "The quick brown fox jumps over the lazy dog. ".repeat(4);
+    parser.add_to_document(&mut document, &markdown);
+    let rect = Rect::new(Point2D::zero(), Size2D::new(260.0, 1000.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+
+    let lines = section.frames()[0].lines();
+    assert!(lines.len() > 2);
+    let (last_line, other_lines) = lines.split_last().unwrap();
+    for line in other_lines {
+        // Trailing spaces go at the left, past the margin.
+        let left = line.origin.x + line.inline_position_for_char_index(line.char_range().start);
+        assert!((left - 30.0).abs() < 0.01);
+        assert!((line.typographic_bounding_rect().max_x() - 250.0).abs() < 0.01);
+    }
+    assert!((last_line.typographic_bounding_rect().max_x() - 250.0).abs() < 0.01);
+}
+
+#[test]
+fn last_lines_are_found_after_characters_outside_the_bmp() {
+    let markdown = "The quick brown \u{1d4bb}ox jumps over the lazy dog. ".repeat(4);
+    let (chars, section) = layout_text_aligned(&markdown,
+                                               TextAlignment::Justify,
+                                               TextAlignment::Center);
+    let lines = section.frames()[0].lines();
+    let (left, right) = visible_extent(lines.last().unwrap(), &chars);
+    assert!(right < 240.0);
+    assert!(((left - 30.0) - (250.0 - right)).abs() < 0.01);
+}
+
+fn layout_body_with_style<F>(set_style: F) -> pilcrow::Section
+                             where F: FnOnce(&mut ParagraphStyle) {
+    let mut parser = markdown_parser();
+    set_style(parser.paragraph_style_mut(BlockSelector::Body));
+    let mut document = Document::new();
+    let markdown = "The quick brown fox jumps over the lazy dog. ".repeat(4);
+    parser.add_to_document(&mut document, &markdown);
+    let rect = Rect::new(Point2D::zero(), Size2D::new(260.0, 1000.0));
+    Framesetter::new(&document).layout_in_rect(&rect, None)
+}
+
+fn baseline_gaps(section: &pilcrow::Section) -> Vec<f32> {
+    let lines = section.frames()[0].lines();
+    assert!(lines.len() > 2);
+    lines.windows(2).map(|lines| lines[1].origin.y - lines[0].origin.y).collect()
+}
+
+#[test]
+fn line_height_sets_the_distance_between_baselines() {
+    let natural_gap = baseline_gaps(&layout_body_with_style(|_| {}))[0];
+
+    let section = layout_body_with_style(|style| style.line_height = LineHeight::Absolute(30.0));
+    assert!(baseline_gaps(&section).iter().all(|&gap| (gap - 30.0).abs() < 0.001));
+    let first_line = &section.frames()[0].lines()[0];
+    let ascent = first_line.typographic_bounds().ascent;
+    let descent = first_line.typographic_bounds().descent;
+    let leading = first_line.typographic_bounds().leading;
+    let half_spacing = (30.0 - (ascent + descent + leading)) * 0.5;
+    assert!((first_line.origin.y - (half_spacing + ascent)).abs() < 0.001);
+
+    let section = layout_body_with_style(|style| style.line_height = LineHeight::Multiple(2.0));
+    assert!(baseline_gaps(&section).iter().all(|&gap| (gap - natural_gap * 2.0).abs() < 0.001));
+
+    let section = layout_body_with_style(|style| style.min_line_height = 40.0);
+    assert!(baseline_gaps(&section).iter().all(|&gap| (gap - 40.0).abs() < 0.001));
+
+    let section = layout_body_with_style(|style| {
+        style.line_height = LineHeight::Multiple(3.0);
+        style.max_line_height = 25.0;
+    });
+    assert!(baseline_gaps(&section).iter().all(|&gap| (gap - 25.0).abs() < 0.001));
+}
+
+#[test]
+fn indents_move_lines_without_adding_text() {
+    let section = layout_body_with_style(|style| {
+        style.margin = SideOffsets2D::new(0.0, 0.0, 0.0, 10.0);
+        style.first_line_indent = 0.0;
+        style.hanging_indent = 24.0;
+    });
+    let lines = section.frames()[0].lines();
+    assert_eq!(lines[0].origin.x, 10.0);
+    for line in &lines[1..] {
+        assert_eq!(line.origin.x, 34.0);
+        let last_char_index = line.char_range().end - 1;
+        assert!(line.origin.x + line.inline_position_for_char_index(last_char_index) <= 260.0);
+    }
+
+    let section = layout_body_with_style(|style| style.first_line_indent = 36.0);
+    let frame = &section.frames()[0];
+    let lines = frame.lines();
+    assert_eq!(lines[0].origin.x, 36.0);
+    assert_eq!(lines[0].char_range().start, 0);
+    assert!(lines[1..].iter().all(|line| line.origin.x == 0.0));
+
+    // Clicking at the start of the indented first line lands on its first character.
+    let point = Point2D::new(37.0, lines[0].origin.y);
+    assert_eq!(frame.line_index_at_point(&point), Some(0));
+    let position = Point2D::new(point.x - lines[0].origin.x, 0.0);
+    assert_eq!(lines[0].char_index_for_position(&position), Some(0));
+}
+
+#[test]
+fn tabs_advance_to_aligned_stops() {
+    let style = ParagraphStyle {
+        margin: SideOffsets2D::new(0.0, 0.0, 0.0, 20.0),
+        tab_stops: vec![
+            TabStop::new(240.0, TabAlignment::Decimal),
+            TabStop::new(60.0, TabAlignment::Left),
+            TabStop::new(160.0, TabAlignment::Right),
+            TabStop::new(320.0, TabAlignment::Center),
+        ],
+        default_tab_interval: 50.0,
+        ..ParagraphStyle::default()
+    };
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string("A\tB\tRight\t3.25\tCenter\tx", style));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(600.0, 100.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+    let lines = section.frames()[0].lines();
+    assert_eq!(lines.len(), 1);
+
+    // Positions are measured from the left margin.
+    let line = &lines[0];
+    let x = |index| line.origin.x - 20.0 + line.inline_position_for_char_index(index);
+    assert!((x(2) - 60.0).abs() < 0.01);
+    assert!((x(9) - 160.0).abs() < 0.01);
+    assert!((x(11) - 240.0).abs() < 0.01);
+    assert!(((x(15) + x(21)) * 0.5 - 320.0).abs() < 0.01);
+    let last_x = x(22);
+    assert!(last_x > x(21) && last_x % 50.0 < 0.01);
+    assert_eq!(document.copy_string(), "A\tB\tRight\t3.25\tCenter\tx");
+}
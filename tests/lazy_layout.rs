@@ -0,0 +1,135 @@
+// pilcrow/tests/lazy_layout.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, SideOffsets2D, Size2D};
+use pilcrow::{Document, Framesetter, LazyLayout, Paragraph, ParagraphStyle, WritingMode};
+
+const SHORT_TEXT: &str = "The quick brown fox.";
+const LONG_TEXT: &str = "The quick brown fox jumps over the lazy dog. Pack my box with five \
+                         dozen liquor jugs. How vexingly quick daft zebras jump!";
+
+const WIDTH: f32 = 300.0;
+const MARGIN_TOP: f32 = 3.0;
+const MARGIN_BOTTOM: f32 = 5.0;
+
+fn rect() -> Rect<f32> {
+    Rect::new(Point2D::new(0.0, 0.0), Size2D::new(WIDTH, 600.0))
+}
+
+fn viewport(y: f32) -> Rect<f32> {
+    Rect::new(Point2D::new(0.0, y), Size2D::new(WIDTH, 200.0))
+}
+
+fn document(paragraph_count: usize) -> Document {
+    let mut document = Document::new();
+    let style = ParagraphStyle {
+        margin: SideOffsets2D::new(MARGIN_TOP, 0.0, MARGIN_BOTTOM, 0.0),
+        ..ParagraphStyle::default()
+    };
+    for index in 0..paragraph_count {
+        let text = if index % 3 == 0 { LONG_TEXT } else { SHORT_TEXT };
+        document.append_paragraph(Paragraph::from_string(text, style.clone()));
+    }
+    document
+}
+
+/// Returns the index of the first and last paragraphs laid out.
+fn paragraph_range(layout: &LazyLayout) -> (usize, usize) {
+    let frames = layout.section().frames();
+    (frames[0].paragraph_index(), frames[frames.len() - 1].paragraph_index())
+}
+
+#[test]
+fn only_paragraphs_in_view_are_laid_out() {
+    let document = document(1000);
+    let mut layout = LazyLayout::new(&document, &rect(), None);
+    let viewport = layout.layout_viewport(&viewport(0.0));
+    assert_eq!(viewport, self::viewport(0.0));
+
+    // From the top, everything is measured, so the frames are where a full layout puts them.
+    let full_section = Framesetter::new(&document).layout_in_rect(&rect(), None);
+    let frames = layout.section().frames();
+    assert!(frames.len() < 20);
+    for (frame, full_frame) in frames.iter().zip(full_section.frames()) {
+        assert_eq!(frame.paragraph_index(), full_frame.paragraph_index());
+        assert_eq!(frame.bounds(), full_frame.bounds());
+    }
+    assert!(frames.last().unwrap().bounds().max_y() >= viewport.max_y());
+}
+
+#[test]
+fn frames_cover_viewports_far_down_the_document() {
+    let document = document(1000);
+    let mut layout = LazyLayout::new(&document, &rect(), None);
+    let y = layout.estimated_block_size() * 0.5;
+    let viewport = layout.layout_viewport(&viewport(y));
+
+    let (first, last) = paragraph_range(&layout);
+    assert!(first > 100 && last - first < 20, "{}..{}", first, last);
+    let frames = layout.section().frames();
+    assert!(frames[0].bounds().origin.y - MARGIN_TOP <= viewport.origin.y);
+    assert!(frames[frames.len() - 1].bounds().max_y() >= viewport.max_y());
+    assert!(frames.windows(2).all(|pair| pair[0].bounds().max_y() <= pair[1].bounds().origin.y));
+}
+
+#[test]
+fn viewports_stay_anchored_as_estimates_are_refined() {
+    let document = document(1000);
+    let mut layout = LazyLayout::new(&document, &rect(), None);
+    let estimated_block_size = layout.estimated_block_size();
+
+    // Measuring the paragraphs in view refines the estimates of everything before them, which
+    // moves the viewport along with the paragraph at its start.
+    let y = estimated_block_size * 0.5 + 10.0;
+    let first_viewport = layout.layout_viewport(&viewport(y));
+    assert!(layout.estimated_block_size() != estimated_block_size);
+    assert!(first_viewport.origin.y != y);
+    let anchor = layout.section().frames()[0].bounds();
+    assert!(anchor.origin.y - MARGIN_TOP <= first_viewport.origin.y);
+    assert!(first_viewport.origin.y < anchor.max_y() + MARGIN_BOTTOM);
+
+    // Nothing new is measured the second time, so nothing moves.
+    let paragraph_index = layout.section().frames()[0].paragraph_index();
+    assert_eq!(layout.layout_viewport(&first_viewport), first_viewport);
+    assert_eq!(layout.section().frames()[0].paragraph_index(), paragraph_index);
+    assert_eq!(layout.section().frames()[0].bounds(), anchor);
+}
+
+#[test]
+fn measured_documents_have_exact_sizes() {
+    let document = document(10);
+    let mut layout = LazyLayout::new(&document, &rect(), None);
+    let viewport = Rect::new(Point2D::zero(), Size2D::new(WIDTH, 10000.0));
+    layout.layout_viewport(&viewport);
+    assert_eq!(layout.section().frames().len(), 10);
+
+    let full_section = Framesetter::new(&document).layout_in_rect(&rect(), None);
+    let last_bottom = full_section.frames()[9].bounds().max_y() + MARGIN_BOTTOM;
+    assert!((layout.estimated_block_size() - last_bottom).abs() < 0.01);
+}
+
+#[test]
+fn vertical_viewports_run_across_the_page() {
+    let mut document = document(200);
+    document.style_mut().writing_mode = WritingMode::VerticalRl;
+    let mut layout = LazyLayout::new(&document, &rect(), None);
+
+    // The start of the document is at the right.
+    let viewport = Rect::new(Point2D::new(WIDTH - 100.0, 0.0), Size2D::new(100.0, 600.0));
+    layout.layout_viewport(&viewport);
+    let frames = layout.section().frames();
+    assert_eq!(frames[0].paragraph_index(), 0);
+    assert!((frames[0].bounds().max_x() - (WIDTH - MARGIN_TOP)).abs() < 0.01);
+    assert!(frames[frames.len() - 1].bounds().origin.x <= viewport.origin.x);
+    assert!(frames.len() < 200);
+}
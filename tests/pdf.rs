@@ -0,0 +1,122 @@
+// pilcrow/tests/pdf.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::markdown::{InlineSelector, MarkdownParser};
+use pilcrow::{Document, Font, Framesetter, LayoutCallbacks, PdfWriter};
+use std::path::PathBuf;
+use std::str;
+
+const MARKDOWN: &str = "Some [linked (text)](https://example.com/) here.\n\n---\n\n\
+                        ![Alt](image.png)\n";
+
+struct Images;
+
+impl LayoutCallbacks for Images {
+    fn get_image_size(&self, _: u32) -> Option<Size2D<u32>> {
+        Some(Size2D::new(2, 2))
+    }
+
+    fn get_image_rgba(&self, _: u32) -> Option<Vec<u8>> {
+        Some(vec![255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0])
+    }
+}
+
+fn write_pdf(page_count: usize) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fonts/DejaVuSerif.ttf");
+    let mut parser = MarkdownParser::new();
+    parser.set_font(InlineSelector::Body, Font::from_path(path, 0, 16.0).unwrap());
+    let mut document = Document::new();
+    parser.add_to_document(&mut document, MARKDOWN);
+
+    let page_size = Size2D::new(300.0, 200.0);
+    let framesetter = Framesetter::new(&document);
+    let mut writer = PdfWriter::new(page_size);
+    for _ in 0..page_count {
+        let section = framesetter.layout_in_rect(&Rect::new(Point2D::zero(), page_size),
+                                                 Some(Box::new(Images)));
+        writer.add_page(&section);
+    }
+    writer.finish()
+}
+
+fn count(haystack: &[u8], needle: &str) -> usize {
+    haystack.windows(needle.len()).filter(|window| *window == needle.as_bytes()).count()
+}
+
+#[test]
+fn pdf_has_pages_fonts_links_and_images() {
+    let pdf = write_pdf(2);
+    assert!(pdf.starts_with(b"%PDF-1.4\n"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+
+    assert_eq!(count(&pdf, "/Type /Page "), 2);
+    assert_eq!(count(&pdf, "/Count 2"), 1);
+    assert_eq!(count(&pdf, "/Subtype /Type3"), 1);
+    assert_eq!(count(&pdf, "/Subtype /Link"), 2);
+    assert_eq!(count(&pdf, "/URI (https://example.com/)"), 2);
+    // The image is shared between pages, and has a soft mask for its transparent pixel.
+    assert_eq!(count(&pdf, "/ColorSpace /DeviceRGB"), 1);
+    assert_eq!(count(&pdf, "/SMask"), 1);
+    assert_eq!(count(&pdf, "/Im0 Do"), 2);
+    // Rules are stroked lines.
+    assert_eq!(count(&pdf, " l S\n"), 2);
+
+    // Only the glyphs that are used are embedded: one procedure per distinct glyph.
+    let distinct_glyphs = {
+        let mut chars: Vec<char> = "Some linked (text) here.".chars().collect();
+        chars.sort();
+        chars.dedup();
+        chars.len()
+    };
+    assert_eq!(count(&pdf, " d1\n"), distinct_glyphs);
+}
+
+#[test]
+fn pdf_cross_reference_table_is_valid() {
+    let pdf = write_pdf(1);
+    assert_eq!(count(&pdf, "startxref"), 1);
+
+    // Everything after the binary comment in the header is ASCII.
+    let text = String::from_utf8_lossy(&pdf);
+    let xref_offset: usize = text.rsplit("startxref\n").next().unwrap()
+                                 .lines().next().unwrap()
+                                 .parse().unwrap();
+    assert!(pdf[xref_offset..].starts_with(b"xref\n"));
+
+    let xref = str::from_utf8(&pdf[xref_offset..]).unwrap();
+    let entries: Vec<&str> = xref.lines().skip(3).take_while(|line| {
+        line.ends_with(" n ")
+    }).collect();
+    assert!(!entries.is_empty());
+    for (index, entry) in entries.iter().enumerate() {
+        let offset: usize = entry[0..10].parse().unwrap();
+        let header = format!("{} 0 obj\n", index + 1);
+        assert!(pdf[offset..].starts_with(header.as_bytes()), "Bad offset for {}", header);
+    }
+}
+
+
+#[test]
+fn pdf_fonts_map_codes_back_to_text() {
+    let pdf = write_pdf(1);
+    assert_eq!(count(&pdf, "/ToUnicode "), 1);
+    assert_eq!(count(&pdf, "begincmap"), 1);
+
+    // The first glyph shown gets the first code, and every glyph maps back to its character.
+    assert_eq!(count(&pdf, "<00> <0053>\n"), 1);
+    for ch in "Some linked (text) here.".chars() {
+        let mapping = format!("> <{:04x}>\n", ch as u32);
+        assert_eq!(count(&pdf, &mapping), 1, "No mapping for {:?}", ch);
+    }
+}
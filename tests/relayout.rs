@@ -0,0 +1,110 @@
+// pilcrow/tests/relayout.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, SideOffsets2D, Size2D};
+use pilcrow::{Document, Framesetter, Layout, Paragraph, ParagraphStyle, Section};
+use std::ops::Range;
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+fn rect() -> Rect<f32> {
+    Rect::new(Point2D::new(10.0, 20.0), Size2D::new(200.0, 1000.0))
+}
+
+fn document() -> Document {
+    let mut document = Document::new();
+    let style = ParagraphStyle {
+        margin: SideOffsets2D::new(4.0, 0.0, 6.0, 0.0),
+        ..ParagraphStyle::default()
+    };
+    for _ in 0..6 {
+        document.append_paragraph(Paragraph::from_string(TEXT, style.clone()));
+    }
+    document
+}
+
+/// The bounds of a frame, with the origin and characters of each of its lines.
+type FrameGeometry = (Rect<f32>, Vec<(Point2D<f32>, Range<usize>)>);
+
+fn geometry(section: &Section) -> Vec<FrameGeometry> {
+    section.frames().iter().map(|frame| {
+        let lines = frame.lines().iter().map(|line| (line.origin, line.char_range())).collect();
+        (frame.bounds(), lines)
+    }).collect()
+}
+
+/// Checks that `layout` is what laying out `document` from scratch gives.
+fn assert_up_to_date(layout: &Layout, document: &Document) {
+    let section = Framesetter::new(document).layout_in_rect(&rect(), None);
+    assert_eq!(geometry(layout.section()), geometry(&section));
+}
+
+#[test]
+fn editing_a_paragraph_lays_out_only_that_paragraph() {
+    let mut document = document();
+    let mut layout = Layout::new(&document, &rect(), None);
+    let old_bounds = layout.section().frames()[4].bounds();
+
+    {
+        let mut cursor = document.paragraphs_mut()[2].edit_at(0);
+        cursor.push_string(TEXT);
+        cursor.commit();
+    }
+    assert_eq!(layout.update(&document), 2..3);
+    assert_up_to_date(&layout, &document);
+
+    // The frames after the longer paragraph moved down.
+    assert!(layout.section().frames()[4].bounds().origin.y > old_bounds.origin.y);
+
+    // Nothing changed, so nothing is laid out.
+    assert!(layout.update(&document).is_empty());
+}
+
+#[test]
+fn style_changes_and_new_paragraphs_are_laid_out() {
+    let mut document = document();
+    let mut layout = Layout::new(&document, &rect(), None);
+
+    document.paragraphs_mut()[1].style_mut().margin.top = 30.0;
+    assert_eq!(layout.update(&document), 1..2);
+    assert_up_to_date(&layout, &document);
+
+    document.append_paragraph(Paragraph::from_string(TEXT, ParagraphStyle::default()));
+    assert_eq!(layout.update(&document), 6..7);
+    assert_up_to_date(&layout, &document);
+
+    document.clear();
+    assert_eq!(layout.update(&document), 0..0);
+    assert!(layout.section().frames().is_empty());
+}
+
+#[test]
+fn document_style_changes_lay_out_everything() {
+    let mut document = document();
+    let mut layout = Layout::new(&document, &rect(), None);
+
+    document.style_mut().margin = SideOffsets2D::new(5.0, 5.0, 5.0, 5.0);
+    assert_eq!(layout.update(&document), 0..6);
+    assert_up_to_date(&layout, &document);
+
+    // Frames move between columns, so columns are always laid out again.
+    document.style_mut().column_count = 2;
+    layout.update(&document);
+    {
+        let mut cursor = document.paragraphs_mut()[0].edit_at(0);
+        cursor.push_string(TEXT);
+        cursor.commit();
+    }
+    assert_eq!(layout.update(&document), 0..6);
+    assert_up_to_date(&layout, &document);
+}
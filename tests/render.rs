@@ -0,0 +1,122 @@
+// pilcrow/tests/render.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{Color, Document, Font, Format, Framesetter, LayoutCallbacks, Paragraph};
+use pilcrow::{ParagraphContent, ParagraphStyle, Section};
+use std::path::PathBuf;
+
+const WIDTH: u32 = 200;
+const HEIGHT: u32 = 100;
+
+struct Images;
+
+impl LayoutCallbacks for Images {
+    fn get_image_size(&self, _: u32) -> Option<Size2D<u32>> {
+        Some(Size2D::new(10, 10))
+    }
+
+    fn get_image_rgba(&self, _: u32) -> Option<Vec<u8>> {
+        Some([0, 0, 255, 255].iter().cloned().cycle().take(10 * 10 * 4).collect())
+    }
+}
+
+fn font() -> Font {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fonts/DejaVuSerif.ttf");
+    Font::from_path(path, 0, 20.0).unwrap()
+}
+
+fn text_paragraph(string: &str, formats: Vec<Format>) -> Paragraph {
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        for format in formats {
+            cursor.push_format(format)
+        }
+        cursor.push_string(string);
+        cursor.commit();
+    }
+    paragraph
+}
+
+fn layout(document: &Document, callbacks: Option<Box<dyn LayoutCallbacks>>) -> Section {
+    let framesetter = Framesetter::new(document);
+    let rect = Rect::new(Point2D::zero(), Size2D::new(WIDTH as f32, HEIGHT as f32));
+    framesetter.layout_in_rect(&rect, callbacks)
+}
+
+fn pixel(rgba: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+    let offset = ((y * width + x) * 4) as usize;
+    [rgba[offset], rgba[offset + 1], rgba[offset + 2], rgba[offset + 3]]
+}
+
+fn inked_pixels(rgba: &[u8]) -> Vec<[u8; 4]> {
+    rgba.chunks(4).filter(|pixel| pixel[3] > 0).map(|pixel| {
+        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    }).collect()
+}
+
+#[test]
+fn text_renders_in_its_color() {
+    let red = Color::new(255, 0, 0, 255);
+    let mut document = Document::new();
+    document.append_paragraph(text_paragraph("Hello", vec![
+        Format::from_font(font()),
+        Format::from_color(red),
+    ]));
+    let section = layout(&document, None);
+
+    let rgba = section.render_to_rgba(WIDTH, HEIGHT, 1.0);
+    assert_eq!(rgba.len(), (WIDTH * HEIGHT * 4) as usize);
+    let inked = inked_pixels(&rgba);
+    assert!(inked.len() > 50);
+    assert!(inked.iter().all(|pixel| pixel[0] == 255 && pixel[1] == 0 && pixel[2] == 0));
+    assert!(inked.iter().any(|pixel| pixel[3] < 255), "Text should be antialiased");
+
+    // Nothing should be drawn below the only line.
+    let line_bottom = section.frames()[0].bounds().max_y().ceil() as u32;
+    assert!((line_bottom..HEIGHT).all(|y| (0..WIDTH).all(|x| pixel(&rgba, WIDTH, x, y)[3] == 0)));
+
+    // Rendering at a larger scale covers proportionally more pixels.
+    let scaled_rgba = section.render_to_rgba(WIDTH * 2, HEIGHT * 2, 2.0);
+    let scaled_inked = inked_pixels(&scaled_rgba).len();
+    assert!(scaled_inked > inked.len() * 3 && scaled_inked < inked.len() * 5);
+}
+
+#[test]
+fn rules_and_images_render() {
+    let mut document = Document::new();
+    document.append_paragraph(text_paragraph("\u{fffc}", vec![
+        Format::from_font(font()),
+        Format::from_image(0),
+    ]));
+    document.append_paragraph(Paragraph::new(ParagraphStyle::new(ParagraphContent::Rule)));
+    let section = layout(&document, Some(Box::new(Images)));
+    let rgba = section.render_to_rgba(WIDTH, HEIGHT, 1.0);
+
+    let line = &section.frames()[0].lines()[0];
+    let (x, y) = (line.origin.x as u32, line.origin.y as u32);
+    assert_eq!(pixel(&rgba, WIDTH, x + 5, y - 5), [0, 0, 255, 255]);
+    assert_eq!(pixel(&rgba, WIDTH, x + 15, y - 5)[3], 0);
+
+    let rule_y = section.frames()[1].bounds().origin.y as u32;
+    let rule_pixel = pixel(&rgba, WIDTH, WIDTH / 2, rule_y);
+    assert_eq!(rule_pixel[..3], [0, 0, 0]);
+    assert!(rule_pixel[3] > 0);
+    assert_eq!(pixel(&rgba, WIDTH, WIDTH / 2, rule_y + 3)[3], 0);
+
+    // Without pixels from the callbacks, the image is left blank.
+    let section = layout(&document, None);
+    let rgba = section.render_to_rgba(WIDTH, HEIGHT, 1.0);
+    assert!(inked_pixels(&rgba).iter().all(|pixel| pixel[..3] == [0, 0, 0]));
+}
@@ -0,0 +1,127 @@
+// pilcrow/tests/segmentation.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::segmentation;
+use pilcrow::{Document, Framesetter, Paragraph, ParagraphStyle};
+use std::char;
+use std::fs;
+use std::path::PathBuf;
+
+/// A test case from one of the Unicode break test files: the characters, and the index of each
+/// character with a break before it, including the end of the text.
+struct BreakTest {
+    line: String,
+    chars: Vec<char>,
+    breaks: Vec<usize>,
+}
+
+/// Reads a test file in the format of `LineBreakTest.txt`, where `÷` marks a break and `×` marks
+/// the lack of one.
+fn read_break_tests(file_name: &str) -> Vec<BreakTest> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+                                                        .join("unicode")
+                                                        .join(file_name);
+    let contents = fs::read_to_string(path).unwrap();
+    contents.lines().filter_map(|line| {
+        let fields = line.split('#').next().unwrap();
+        if fields.trim().is_empty() {
+            return None
+        }
+        let (mut chars, mut breaks) = (vec![], vec![]);
+        for field in fields.split_whitespace() {
+            match field {
+                "÷" => breaks.push(chars.len()),
+                "×" => {}
+                code_point => {
+                    let code_point = u32::from_str_radix(code_point, 16).unwrap();
+                    chars.push(char::from_u32(code_point).unwrap())
+                }
+            }
+        }
+        Some(BreakTest { line: line.to_owned(), chars, breaks })
+    }).collect()
+}
+
+#[test]
+fn line_breaks_conform_to_uax_14() {
+    let tests = read_break_tests("LineBreakTest.txt");
+    assert!(tests.len() > 7000);
+    let failures: Vec<_> = tests.iter().filter(|test| {
+        let breaks: Vec<_> = segmentation::line_break_opportunities(&test.chars)
+            .into_iter()
+            .map(|opportunity| opportunity.char_index)
+            .collect();
+        // The file marks that there's no break before the first character.
+        breaks != test.breaks
+    }).map(|test| &test.line).collect();
+    assert!(failures.is_empty(), "{} failures, including:\n{:#?}", failures.len(), failures);
+}
+
+#[test]
+fn grapheme_boundaries_conform_to_uax_29() {
+    let tests = read_break_tests("GraphemeBreakTest.txt");
+    assert!(tests.len() > 700);
+    let failures: Vec<_> = tests.iter().filter(|test| {
+        segmentation::grapheme_boundaries(&test.chars) != test.breaks
+    }).map(|test| &test.line).collect();
+    assert!(failures.is_empty(), "{} failures, including:\n{:#?}", failures.len(), failures);
+}
+
+#[test]
+fn mandatory_breaks_follow_hard_line_breaks() {
+    let chars: Vec<char> = "one two\r\nthree\u{2028}".chars().collect();
+    let opportunities = segmentation::line_break_opportunities(&chars);
+    let breaks: Vec<_> = opportunities.iter().map(|opportunity| {
+        (opportunity.char_index, opportunity.mandatory)
+    }).collect();
+    assert_eq!(breaks, vec![(4, false), (9, true), (15, true)]);
+}
+
+/// Lays out text whose accents are combining marks, so each accented letter is one grapheme
+/// cluster of two characters.
+fn layout_accented_text(width: f32) -> (Vec<char>, Vec<pilcrow::Line>) {
+    let text = "Cafe\u{301} cre\u{300}me bru\u{302}le\u{301}e";
+    let mut document = Document::new();
+    document.append_paragraph(Paragraph::from_string(text, ParagraphStyle::default()));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(width, 1000.0));
+    let section = Framesetter::new(&document).layout_in_rect(&rect, None);
+    (text.chars().collect(), section.frames()[0].lines())
+}
+
+#[test]
+fn hit_testing_stays_on_grapheme_boundaries() {
+    let (chars, lines) = layout_accented_text(1000.0);
+    let boundaries = segmentation::grapheme_boundaries(&chars);
+    let line = &lines[0];
+    for x in 0..(line.typographic_bounds().width.ceil() as usize + 2) {
+        let index = line.char_index_for_position(&Point2D::new(x as f32, 0.0)).unwrap();
+        assert!(boundaries.contains(&index), "{} isn't a boundary", index);
+    }
+
+    // A combining accent sits at the position of the letter it belongs to.
+    let accent_index = chars.iter().position(|&ch| ch == '\u{301}').unwrap();
+    assert_eq!(line.inline_position_for_char_index(accent_index),
+               line.inline_position_for_char_index(accent_index - 1));
+}
+
+#[test]
+fn narrow_lines_break_between_grapheme_clusters() {
+    let (chars, lines) = layout_accented_text(1.0);
+    let boundaries = segmentation::grapheme_boundaries(&chars);
+    assert!(lines.len() > 3);
+    for line in lines {
+        assert!(boundaries.contains(&line.char_range().start));
+        assert!(boundaries.contains(&line.char_range().end));
+    }
+}
@@ -0,0 +1,169 @@
+// pilcrow/tests/selection.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{Affinity, BaseDirection, Document, DocumentStyle, Framesetter, Paragraph};
+use pilcrow::{ParagraphStyle, Section, Selection, TextLocation, WritingMode};
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+/// Latin words around Hebrew words with a number between them.
+const BIDI_TEXT: &str = "one \u{5e9}\u{5dc}\u{5d5}\u{5dd} 12 \u{5e2}\u{5d5} two";
+
+const WIDTH: f32 = 150.0;
+
+fn layout(texts: &[&str], document_style: DocumentStyle) -> Section {
+    let mut document = Document::new();
+    *document.style_mut() = document_style;
+    for text in texts {
+        document.append_paragraph(Paragraph::from_string(text, ParagraphStyle::default()));
+    }
+    let rect = Rect::new(Point2D::zero(), Size2D::new(WIDTH, 1000.0));
+    Framesetter::new(&document).layout_in_rect(&rect, None)
+}
+
+/// Lays out `TEXT`, then an empty paragraph of `style`.
+fn layout_with_empty_paragraph(style: ParagraphStyle, document_style: DocumentStyle)
+                               -> Section {
+    let mut document = Document::new();
+    *document.style_mut() = document_style;
+    document.append_paragraph(Paragraph::from_string(TEXT, ParagraphStyle::default()));
+    document.append_paragraph(Paragraph::new(style));
+    let rect = Rect::new(Point2D::zero(), Size2D::new(WIDTH, 1000.0));
+    Framesetter::new(&document).layout_in_rect(&rect, None)
+}
+
+fn location(paragraph_index: usize, character_index: usize) -> TextLocation {
+    TextLocation::new(paragraph_index, character_index)
+}
+
+#[test]
+fn caret_spans_its_line() {
+    let section = layout(&[TEXT], DocumentStyle::default());
+    let lines = section.frames()[0].lines();
+    let line_rect = lines[0].typographic_bounding_rect();
+
+    let caret = section.caret_rect(location(0, 0), Affinity::Downstream).unwrap();
+    assert_eq!(caret, Rect::new(line_rect.origin, Size2D::new(0.0, line_rect.size.height)));
+    let caret = section.caret_rect(location(0, 4), Affinity::Downstream).unwrap();
+    assert_eq!(caret.origin.x, lines[0].inline_position_for_char_index(4));
+
+    // Locations outside the section have no caret.
+    assert_eq!(section.caret_rect(location(1, 0), Affinity::Downstream), None);
+}
+
+#[test]
+fn affinity_picks_the_line_where_a_line_wraps() {
+    let section = layout(&[TEXT], DocumentStyle::default());
+    let lines = section.frames()[0].lines();
+    assert!(lines.len() > 1);
+    let wrap = location(0, lines[1].char_range().start);
+
+    let upstream_caret = section.caret_rect(wrap, Affinity::Upstream).unwrap();
+    let downstream_caret = section.caret_rect(wrap, Affinity::Downstream).unwrap();
+    assert_eq!(upstream_caret.origin.y, lines[0].typographic_bounding_rect().origin.y);
+    assert_eq!(downstream_caret.origin.y, lines[1].typographic_bounding_rect().origin.y);
+    assert_eq!(downstream_caret.origin.x, 0.0);
+    assert!(upstream_caret.origin.x > 0.0);
+}
+
+#[test]
+fn selection_rects_cover_each_line_of_the_range() {
+    let section = layout(&[TEXT, TEXT], DocumentStyle::default());
+    let first_lines = section.frames()[0].lines();
+    let selection = Selection::new(location(1, 4), location(0, 4), Affinity::Downstream);
+    assert!(!selection.is_caret());
+    assert_eq!(selection.range(), location(0, 4)..location(1, 4));
+
+    let rects = section.selection_rects(selection.range());
+    let line_count = first_lines.len() + 1;
+    assert_eq!(rects.len(), line_count);
+
+    // The first rect starts at the caret, the last ends at it, and whole lines are covered
+    // between them.
+    let start_caret = section.caret_rect(location(0, 4), Affinity::Downstream).unwrap();
+    let end_caret = section.caret_rect(location(1, 4), Affinity::Downstream).unwrap();
+    assert_eq!(rects[0].origin, start_caret.origin);
+    assert_eq!(rects[line_count - 1].max_x(), end_caret.origin.x);
+    assert_eq!(rects[line_count - 1].origin.y, end_caret.origin.y);
+    let second_line_rect = first_lines[1].typographic_bounding_rect();
+    assert_eq!(rects[1].origin, second_line_rect.origin);
+    assert_eq!(rects[1].size.height, second_line_rect.size.height);
+
+    assert!(section.selection_rects(location(0, 4)..location(0, 4)).is_empty());
+}
+
+#[test]
+fn selections_that_change_direction_take_a_rect_per_direction() {
+    let section = layout(&[BIDI_TEXT], DocumentStyle::default());
+    let line = &section.frames()[0].lines()[0];
+
+    // The end of "one " is at the left of the Hebrew run, and its first letters at the right.
+    let rects = section.selection_rects(location(0, 2)..location(0, 6));
+    assert_eq!(rects.len(), 2);
+    assert_eq!(rects[0].origin.x, line.inline_position_for_char_index(2));
+    assert!(rects[1].origin.x > rects[0].max_x());
+    assert_eq!(rects[1].max_x(), line.inline_position_for_char_index(4));
+}
+
+#[test]
+fn vertical_carets_run_across_the_line() {
+    let document_style = DocumentStyle {
+        writing_mode: WritingMode::VerticalRl,
+        ..DocumentStyle::default()
+    };
+    let section = layout(&[TEXT], document_style);
+    let line = &section.frames()[0].lines()[0];
+    let line_rect = line.typographic_bounding_rect();
+
+    let caret = section.caret_rect(location(0, 2), Affinity::Downstream).unwrap();
+    assert_eq!(caret.size.height, 0.0);
+    assert_eq!(caret.size.width, line_rect.size.width);
+    assert_eq!(caret.origin.y, line.origin.y + line.inline_position_for_char_index(2));
+
+    let rects = section.selection_rects(location(0, 0)..location(0, 2));
+    assert_eq!(rects.len(), 1);
+    assert_eq!(rects[0].origin, line_rect.origin);
+    assert_eq!(rects[0].max_y(), caret.origin.y);
+}
+
+#[test]
+fn empty_right_to_left_paragraphs_put_the_caret_at_the_right() {
+    let style = ParagraphStyle {
+        base_direction: BaseDirection::RightToLeft,
+        ..ParagraphStyle::default()
+    };
+    let section = layout_with_empty_paragraph(style, DocumentStyle::default());
+    let bounds = section.frames()[1].bounds();
+    let caret = section.caret_rect(location(1, 0), Affinity::Downstream).unwrap();
+    assert_eq!(caret.origin, Point2D::new(bounds.max_x(), bounds.origin.y));
+    assert_eq!(caret.size.width, 0.0);
+}
+
+#[test]
+fn empty_vertical_paragraphs_put_the_caret_at_the_top_right() {
+    let style = ParagraphStyle { first_line_indent: 10.0, ..ParagraphStyle::default() };
+    let document_style = DocumentStyle {
+        writing_mode: WritingMode::VerticalRl,
+        ..DocumentStyle::default()
+    };
+    let section = layout_with_empty_paragraph(style, document_style);
+    let bounds = section.frames()[1].bounds();
+    let caret = section.caret_rect(location(1, 0), Affinity::Downstream).unwrap();
+
+    // Lines stack leftwards from the right edge, and start below the indent.
+    assert_eq!(caret.max_x(), bounds.max_x());
+    assert_eq!(caret.origin.y, bounds.origin.y + 10.0);
+    assert_eq!(caret.size.height, 0.0);
+    assert!(caret.max_x() <= section.frames()[0].bounds().origin.x);
+}
@@ -0,0 +1,92 @@
+// pilcrow/tests/svg.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::markdown::{InlineSelector, MarkdownParser};
+use pilcrow::{Color, Document, Font, Format, Framesetter, LayoutCallbacks, Paragraph};
+use pilcrow::{ParagraphStyle, Section};
+use std::path::PathBuf;
+
+const MARKDOWN: &str = "Some [linked & quoted](https://example.com/?a=1&b=2) text.\n\n---\n\n\
+                        ![Alt](image.png)\n";
+
+struct Images;
+
+impl LayoutCallbacks for Images {
+    fn get_image_size(&self, _: u32) -> Option<Size2D<u32>> {
+        Some(Size2D::new(32, 16))
+    }
+}
+
+fn font_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fonts").join(name)
+}
+
+fn layout(document: &Document) -> Section {
+    let framesetter = Framesetter::new(document);
+    let rect = Rect::new(Point2D::zero(), Size2D::new(300.0, 200.0));
+    framesetter.layout_in_rect(&rect, Some(Box::new(Images)))
+}
+
+fn markdown_svg() -> String {
+    let mut parser = MarkdownParser::new();
+    parser.set_font(InlineSelector::Body,
+                    Font::from_path(font_path("DejaVuSerif.ttf"), 0, 16.0).unwrap());
+    let mut document = Document::new();
+    parser.add_to_document(&mut document, MARKDOWN);
+    layout(&document).to_svg(300.0, 200.0)
+}
+
+#[test]
+fn markdown_exports_to_svg() {
+    let svg = markdown_svg();
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.contains("viewBox=\"0 0 300 200\""));
+    assert!(svg.ends_with("</svg>\n"));
+
+    assert!(svg.contains("<a xlink:href=\"https://example.com/?a=1&amp;b=2\">"));
+    assert_eq!(svg.matches("<a ").count(), svg.matches("</a>").count());
+    assert!(svg.contains("<line "));
+    assert!(svg.contains("width=\"32\" height=\"16\" data-image-id=\"0\"/>"));
+
+    // Glyphs are defined once and used once per occurrence; spaces have no outline.
+    let defined_glyphs = svg.matches("<path id=\"glyph-").count();
+    let used_glyphs = svg.matches("<use ").count();
+    assert!(defined_glyphs > 5);
+    assert_eq!(used_glyphs, "Somelinked&quotedtext.".chars().count());
+    assert!(used_glyphs > defined_glyphs);
+}
+
+#[test]
+fn svg_export_is_deterministic() {
+    assert_eq!(markdown_svg(), markdown_svg());
+}
+
+#[test]
+fn colors_export_as_fills() {
+    let font = Font::from_path(font_path("DejaVuSerif.ttf"), 0, 16.0).unwrap();
+    let mut paragraph = Paragraph::new(ParagraphStyle::default());
+    {
+        let mut cursor = paragraph.edit_at(0);
+        cursor.push_format(Format::from_font(font));
+        cursor.push_string("A");
+        cursor.push_format(Format::from_color(Color::new(255, 128, 0, 128)));
+        cursor.push_string("B");
+        cursor.commit();
+    }
+    let mut document = Document::new();
+    document.append_paragraph(paragraph);
+    let svg = layout(&document).to_svg(300.0, 200.0);
+    assert!(svg.contains("<g fill=\"#000000\">"));
+    assert!(svg.contains("<g fill=\"#ff8000\" fill-opacity=\"0.5\">"));
+}
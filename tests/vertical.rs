@@ -0,0 +1,129 @@
+// pilcrow/tests/vertical.rs
+//
+// Copyright © 2018 The Pathfinder Project Developers.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate euclid;
+extern crate pilcrow;
+
+use euclid::{Point2D, Rect, Size2D};
+use pilcrow::{Document, Framesetter, GlyphOrientation, Paragraph, ParagraphStyle, Section};
+use pilcrow::{TextOrientation, WritingMode};
+use std::ops::Range;
+
+const TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+/// Symbols that stand upright in vertical text, then Latin letters that lie on their side.
+const MIXED_TEXT: &str = "\u{a7}\u{b1}\u{d7} abc";
+
+fn rect() -> Rect<f32> {
+    Rect::new(Point2D::new(10.0, 20.0), Size2D::new(300.0, 200.0))
+}
+
+fn layout(writing_mode: WritingMode, texts: &[&str], style: ParagraphStyle) -> Section {
+    let mut document = Document::new();
+    document.style_mut().writing_mode = writing_mode;
+    for text in texts {
+        document.append_paragraph(Paragraph::from_string(text, style.clone()));
+    }
+    Framesetter::new(&document).layout_in_rect(&rect(), None)
+}
+
+/// Returns the character range and glyph orientation of each run of the first line.
+fn run_orientations(writing_mode: WritingMode, text_orientation: TextOrientation)
+                    -> Vec<(Range<usize>, GlyphOrientation)> {
+    let style = ParagraphStyle { text_orientation, ..ParagraphStyle::default() };
+    let section = layout(writing_mode, &[MIXED_TEXT], style);
+    let line = &section.frames()[0].lines()[0];
+    line.runs().iter().map(|run| (run.char_range(), run.glyph_orientation())).collect()
+}
+
+#[test]
+fn vertical_rl_stacks_lines_and_paragraphs_from_the_right() {
+    let section = layout(WritingMode::VerticalRl, &[TEXT, TEXT], ParagraphStyle::default());
+    let frames = section.frames();
+    assert_eq!(frames.len(), 2);
+    let rect = rect();
+
+    let first = frames[0].bounds();
+    assert!((first.max_x() - rect.max_x()).abs() < 0.01, "{:?}", first);
+    assert_eq!(first.origin.y, rect.origin.y);
+    assert!(first.size.height <= rect.size.height);
+    assert!(frames[1].bounds().max_x() <= first.origin.x + 0.01);
+
+    // Lines run down the page, each to the left of the one before.
+    let lines = frames[0].lines();
+    assert!(lines.len() > 1);
+    assert!(lines.windows(2).all(|pair| pair[1].origin.x < pair[0].origin.x));
+    for line in &lines {
+        let bounds = line.typographic_bounding_rect();
+        assert!(bounds.size.height > bounds.size.width);
+        assert!(first.contains_rect(&bounds.inflate(-0.01, -0.01)), "{:?}", bounds);
+    }
+}
+
+#[test]
+fn vertical_lr_stacks_lines_and_paragraphs_from_the_left() {
+    let section = layout(WritingMode::VerticalLr, &[TEXT, TEXT], ParagraphStyle::default());
+    let frames = section.frames();
+    let first = frames[0].bounds();
+    assert_eq!(first.origin, rect().origin);
+    assert!(frames[1].bounds().origin.x >= first.max_x() - 0.01);
+
+    let lines = frames[0].lines();
+    assert!(lines.windows(2).all(|pair| pair[1].origin.x > pair[0].origin.x));
+}
+
+#[test]
+fn mixed_orientation_stands_symbols_upright_and_turns_latin_text() {
+    let runs = run_orientations(WritingMode::VerticalRl, TextOrientation::Mixed);
+    assert_eq!(runs.first().unwrap(), &(0..3, GlyphOrientation::Upright));
+    assert_eq!(runs.last().unwrap(), &(3..7, GlyphOrientation::Rotated));
+
+    let runs = run_orientations(WritingMode::VerticalRl, TextOrientation::Upright);
+    assert_eq!(runs, vec![(0..7, GlyphOrientation::Upright)]);
+    let runs = run_orientations(WritingMode::VerticalLr, TextOrientation::Sideways);
+    assert_eq!(runs, vec![(0..7, GlyphOrientation::Rotated)]);
+
+    // Horizontal text is upright whatever the style says.
+    let runs = run_orientations(WritingMode::HorizontalTb, TextOrientation::Sideways);
+    assert!(runs.iter().all(|&(_, orientation)| orientation == GlyphOrientation::Upright));
+}
+
+#[test]
+fn glyphs_advance_down_vertical_lines() {
+    let section = layout(WritingMode::VerticalRl, &[MIXED_TEXT], ParagraphStyle::default());
+    let line = &section.frames()[0].lines()[0];
+    for run in line.runs() {
+        let positions = run.positions();
+        // Positions have Y up, so later glyphs are further below the origin.
+        assert!(positions.windows(2).all(|pair| pair[1].y < pair[0].y), "{:?}", positions);
+    }
+}
+
+#[test]
+fn hit_testing_works_down_the_page() {
+    let section = layout(WritingMode::VerticalRl, &[TEXT, TEXT], ParagraphStyle::default());
+    let frames = section.frames();
+    for (frame_index, frame) in frames.iter().enumerate() {
+        let lines = frame.lines();
+        for (line_index, line) in lines.iter().enumerate() {
+            let bounds = line.typographic_bounding_rect();
+            let point = Point2D::new(bounds.origin.x + bounds.size.width * 0.5,
+                                     bounds.origin.y + 1.0);
+            assert_eq!(section.frame_index_at_point(&point), Some(frame_index));
+            assert_eq!(frame.line_index_at_point(&point), Some(line_index));
+        }
+
+        let line = &lines[0];
+        for index in line.char_range() {
+            let y = line.inline_position_for_char_index(index) + 0.5;
+            assert_eq!(line.char_index_for_position(&Point2D::new(0.0, y)), Some(index));
+        }
+    }
+}